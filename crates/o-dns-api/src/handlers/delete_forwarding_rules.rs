@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse as _, Response};
+use axum::Json;
+use futures::StreamExt as _;
+use o_dns_common::DnsServerCommand;
+use o_dns_db::ForwardingRuleEntry;
+
+use crate::util::build_delete_forwarding_rules_query;
+use crate::ApiState;
+
+pub async fn handler(State(state): State<Arc<ApiState>>, Json(ids): Json<Vec<u32>>) -> Response {
+    if let Err(e) = delete_forwarding_rules_handler(state, &ids).await {
+        tracing::debug!(ids = ?ids, "Error while deleting forwarding rules: {:#}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    StatusCode::OK.into_response()
+}
+
+async fn delete_forwarding_rules_handler(state: Arc<ApiState>, ids: &[u32]) -> anyhow::Result<()> {
+    let mut query = build_delete_forwarding_rules_query(ids);
+
+    let mut connection = state.db.get_connection().await?;
+
+    let mut deleted_rules = query.build_query_as::<ForwardingRuleEntry>().fetch(&mut *connection);
+
+    while let Some(rule) = deleted_rules.next().await {
+        let rule = rule.context("failed to delete a forwarding rule")?;
+
+        let _ = state
+            .command_tx
+            .send(DnsServerCommand::RemoveForwardingRule(rule.domain))
+            .await;
+    }
+
+    Ok(())
+}
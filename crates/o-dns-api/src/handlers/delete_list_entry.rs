@@ -34,7 +34,10 @@ async fn delete_list_entry_handler(state: Arc<ApiState>, ids: &[u32]) -> anyhow:
     while let Some(entry) = deleted_entries.next().await {
         let entry = entry.context("failed to delete a list entry")?;
 
-        let domain = entry.domain.as_ref().map(|domain| hash_to_u128(domain.as_ref(), None));
+        let domain = entry
+            .domain
+            .as_ref()
+            .map(|domain| hash_to_u128(domain.to_ascii_lowercase(), None));
         let cmd = DnsServerCommand::RemoveListEntry(match entry.kind {
             EntryKind::Deny => {
                 AccessListEntryKind::DenyDomain(domain.context("bug: missing 'domain' for a Deny entry?")?)
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse as _, Response};
+use axum::Json;
+use o_dns_db::{ForwardingRuleEntry, SqliteDb};
+
+use crate::ApiState;
+
+pub async fn handler(State(state): State<Arc<ApiState>>) -> Response {
+    let rules = match get_forwarding_rules_handler(&state.db).await {
+        Ok(rules) => rules,
+        Err(e) => {
+            tracing::debug!("Error while getting forwarding rules: {:#}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    Json(rules).into_response()
+}
+
+async fn get_forwarding_rules_handler(db: &SqliteDb) -> anyhow::Result<Vec<ForwardingRuleEntry>> {
+    let mut connection = db.get_connection().await?;
+
+    ForwardingRuleEntry::select_all(&mut connection).await
+}
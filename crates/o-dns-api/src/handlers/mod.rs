@@ -1,8 +1,13 @@
+mod delete_forwarding_rules;
 mod delete_list_entry;
+mod get_forwarding_rules;
 mod get_list_entries;
 mod get_query_logs;
 mod get_stats;
 mod modify_list_entry;
+mod resolve_dns_query;
+mod resolve_json_query;
+mod set_forwarding_rule;
 
 use std::sync::Arc;
 
@@ -11,12 +16,17 @@ use axum::extract::{FromRequest, Request, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::{async_trait, Json};
+pub use delete_forwarding_rules::handler as delete_forwarding_rules;
 pub use delete_list_entry::handler as delete_list_entry;
+pub use get_forwarding_rules::handler as get_forwarding_rules;
 pub use get_list_entries::{handler as get_list_entries, ListEntriesFilter};
 pub use get_query_logs::{handler as get_query_logs, LatestLogsFilter};
 pub use get_stats::handler as get_stats;
 pub use modify_list_entry::handler as modify_list_entry;
+pub use resolve_dns_query::{get_handler as get_dns_query, post_handler as post_dns_query};
+pub use resolve_json_query::handler as resolve_json_query;
 use serde::Deserialize;
+pub use set_forwarding_rule::handler as set_forwarding_rule;
 
 use crate::ApiState;
 
@@ -62,7 +72,7 @@ where
     async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
         let Json(raw) = Json::<T::Raw>::from_request(req, state)
             .await
-            .map_err(|e| (ValidationRejection::JsonError(e.to_string())))?;
+            .map_err(|e| ValidationRejection::JsonError(e.to_string()))?;
         Ok(ValidatedJson(
             T::validate(raw).map_err(|e| ValidationRejection::ValidationError(e.to_string()))?,
         ))
@@ -5,9 +5,8 @@ use anyhow::Context as _;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use o_dns_common::{hash_to_u128, AccessListEntryKind, DnsServerCommand};
+use o_dns_common::{build_denylist_regex, hash_to_u128, AccessListEntryKind, DnsServerCommand};
 use o_dns_db::{EntryKind, ListEntry, ListEntryUpdateRequest, Model as _, Updatable as _};
-use regex::Regex;
 use serde::Deserialize;
 use tokio::sync::mpsc::Sender;
 
@@ -48,11 +47,14 @@ impl ValidatableRequest for ModifyListEntryRequest {
         };
 
         // Validate all other fields and turn them into a DNS server command
-        let domain = raw.domain.as_ref().map(|domain| hash_to_u128(domain, None));
+        let domain = raw
+            .domain
+            .as_ref()
+            .map(|domain| hash_to_u128(domain.to_ascii_lowercase(), None));
         let cmd = match kind {
             EntryKind::Deny => AccessListEntryKind::DenyDomain(domain.context("Missing 'domain' for a deny entry")?),
             EntryKind::DenyRegex => {
-                let regex = match Regex::new(
+                let regex = match build_denylist_regex(
                     raw.data
                         .as_ref()
                         .context("Missing 'data' for a deny entry with regex")?,
@@ -176,7 +178,7 @@ async fn delete_existing_entry(
     command_tx: &Sender<DnsServerCommand>,
 ) -> anyhow::Result<()> {
     // Delete the existing entry in the DNS server
-    let domain = domain.map(|domain| hash_to_u128(domain, None));
+    let domain = domain.map(|domain| hash_to_u128(domain.to_ascii_lowercase(), None));
     let cmd = DnsServerCommand::RemoveListEntry(match kind {
         EntryKind::Deny => AccessListEntryKind::DenyDomain(domain.context("bug: missing 'domain' for a Deny entry?")?),
         EntryKind::DenyRegex => AccessListEntryKind::DenyRegex((id, None)),
@@ -0,0 +1,193 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse as _, Response};
+use data_encoding::BASE64URL_NOPAD;
+use o_dns_common::{ApiQueryResponse, DnsServerCommand};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+use crate::ApiState;
+
+/// Media type of the wire format DNS messages (RFC8484)
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+#[derive(Debug, Deserialize)]
+pub struct DnsQueryParams {
+    /// Base64url-encoded query without padding
+    pub dns: String,
+}
+
+pub async fn get_handler(
+    State(state): State<Arc<ApiState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(params): Query<DnsQueryParams>,
+) -> Response {
+    // Be lenient towards clients that add the padding anyway
+    let query = match BASE64URL_NOPAD.decode(params.dns.trim_end_matches('=').as_bytes()) {
+        Ok(query) => query,
+        Err(e) => {
+            tracing::debug!(dns = params.dns, "Invalid DoH query: {}", e);
+            return (StatusCode::BAD_REQUEST, "Invalid 'dns' parameter").into_response();
+        }
+    };
+
+    resolve_dns_query_handler(&state, query, client.ip()).await
+}
+
+pub async fn post_handler(
+    State(state): State<Arc<ApiState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if headers
+        .get(CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != DNS_MESSAGE_CONTENT_TYPE)
+    {
+        return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+    }
+    if body.len() > u16::MAX as usize {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    }
+
+    resolve_dns_query_handler(&state, body.to_vec(), client.ip()).await
+}
+
+async fn resolve_dns_query_handler(state: &ApiState, query: Vec<u8>, client: IpAddr) -> Response {
+    let response = match resolve_query(state, query, client).await {
+        Ok(response) => response,
+        Err(status) => return status.into_response(),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE.parse().unwrap());
+    // HTTP caches may keep the response for as long as its RRs are valid (RFC8484, section 5.1)
+    if let Some(max_age) = response.ttl {
+        headers.insert(CACHE_CONTROL, format!("max-age={}", max_age).parse().unwrap());
+    }
+
+    (headers, response.response).into_response()
+}
+
+/// Resolves the encoded query with the DNS server, returning the status code to respond with if it fails
+pub(super) async fn resolve_query(
+    state: &ApiState,
+    query: Vec<u8>,
+    client: IpAddr,
+) -> Result<ApiQueryResponse, StatusCode> {
+    let (response_tx, response_rx) = oneshot::channel();
+    let cmd = DnsServerCommand::ResolveQuery {
+        query,
+        client: Some(client),
+        response_tx,
+    };
+    if state.command_tx.send(cmd).await.is_err() {
+        tracing::debug!("Error while resolving a query: DNS server is not running");
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    response_rx.await.map_err(|_| {
+        tracing::debug!("Error while resolving a query: DNS server has dropped the query");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use data_encoding::BASE64URL;
+    use o_dns_lib::{ByteBuf, DnsPacket, EncodeToBuf as _, QueryType, Question};
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+    use crate::test_utils::get_api_state;
+
+    const CLIENT: ([u8; 4], u16) = ([127, 0, 0, 1], 12345);
+
+    /// Returns the state, where the DNS server answers the queries by echoing them back along with `ttl`
+    async fn get_state(ttl: Option<u32>) -> Arc<ApiState> {
+        let (command_tx, mut command_rx) = channel(1);
+        tokio::spawn(async move {
+            while let Some(command) = command_rx.recv().await {
+                if let DnsServerCommand::ResolveQuery { query, response_tx, .. } = command {
+                    let _ = response_tx.send(ApiQueryResponse {
+                        response: query,
+                        source: None,
+                        ttl,
+                    });
+                }
+            }
+        });
+
+        get_api_state(command_tx).await
+    }
+
+    fn get_query(qname: &str) -> Vec<u8> {
+        let mut query = DnsPacket::new();
+        query.header.recursion_desired = true;
+        query.add_question(Question::new(qname.parse().unwrap(), QueryType::A, None));
+        let mut buf = ByteBuf::new_empty(None);
+        query.encode_to_buf(&mut buf, None).unwrap();
+        buf.into_inner().into_owned()
+    }
+
+    async fn get(state: &Arc<ApiState>, dns: String) -> Response {
+        get_handler(
+            State(state.clone()),
+            ConnectInfo(CLIENT.into()),
+            Query(DnsQueryParams { dns }),
+        )
+        .await
+    }
+
+    async fn post(state: &Arc<ApiState>, content_type: Option<&str>, body: Vec<u8>) -> Response {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        }
+        post_handler(State(state.clone()), ConnectInfo(CLIENT.into()), headers, body.into()).await
+    }
+
+    #[tokio::test]
+    async fn get_accepts_base64url_with_and_without_padding() {
+        let state = get_state(Some(300)).await;
+        let query = get_query("example.com");
+        let padded = BASE64URL.encode(&query);
+        assert!(padded.ends_with('='), "query should need padding");
+
+        for dns in [BASE64URL_NOPAD.encode(&query), padded] {
+            let response = get(&state, dns).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[CONTENT_TYPE], DNS_MESSAGE_CONTENT_TYPE);
+            assert_eq!(response.headers()[CACHE_CONTROL], "max-age=300");
+            assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), query);
+        }
+
+        let response = get(&state, "not base64url!".to_string()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn post_checks_content_type_and_size() {
+        let state = get_state(None).await;
+        let query = get_query("example.com");
+
+        let response = post(&state, Some(DNS_MESSAGE_CONTENT_TYPE), query.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // Responses without any RRs aren't cacheable
+        assert!(response.headers().get(CACHE_CONTROL).is_none());
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), query);
+
+        let response = post(&state, Some("application/json"), query.clone()).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = post(&state, None, query).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = post(&state, Some(DNS_MESSAGE_CONTENT_TYPE), vec![0; u16::MAX as usize + 1]).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,313 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse as _, Response};
+use axum::Json;
+use o_dns_common::ResponseSource;
+use o_dns_lib::{
+    ByteBuf, DnsPacket, DomainName, EncodeToBuf as _, FromBuf as _, QueryType, Question, ResourceData, ResourceRecord,
+};
+use serde::{Deserialize, Serialize};
+
+use super::resolve_dns_query::resolve_query;
+use crate::ApiState;
+
+/// Advertised UDP payload size of the query, as in the queries sent to the upstream resolvers
+const EDNS_BUF_SIZE: u16 = 1232;
+const EDNS_DO_BIT: u32 = 1 << 15;
+
+#[derive(Debug, Deserialize)]
+pub struct JsonQueryParams {
+    pub name: String,
+    /// Type mnemonic or number, `A` by default
+    #[serde(rename = "type")]
+    pub query_type: Option<String>,
+    /// DNSSEC OK bit
+    #[serde(rename = "do")]
+    pub dnssec_ok: Option<String>,
+    /// Checking Disabled bit
+    pub cd: Option<String>,
+}
+
+/// Response in the format of the public JSON DNS APIs, e.g. `https://dns.google/resolve`
+#[derive(Debug, Serialize)]
+struct JsonResponse {
+    #[serde(rename = "Status")]
+    status: u8,
+    #[serde(rename = "TC")]
+    truncated: bool,
+    #[serde(rename = "RD")]
+    recursion_desired: bool,
+    #[serde(rename = "RA")]
+    recursion_available: bool,
+    #[serde(rename = "AD")]
+    authentic_data: bool,
+    #[serde(rename = "CD")]
+    checking_disabled: bool,
+    #[serde(rename = "Question")]
+    question: Vec<JsonQuestion>,
+    #[serde(rename = "Answer", skip_serializing_if = "Vec::is_empty")]
+    answer: Vec<JsonRecord>,
+    #[serde(rename = "Authority", skip_serializing_if = "Vec::is_empty")]
+    authority: Vec<JsonRecord>,
+    /// Which part of the resolver produced the response
+    #[serde(rename = "Source")]
+    source: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonQuestion {
+    name: String,
+    #[serde(rename = "type")]
+    query_type: u16,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonRecord {
+    name: String,
+    #[serde(rename = "type")]
+    query_type: u16,
+    #[serde(rename = "TTL")]
+    ttl: u32,
+    data: String,
+}
+
+pub async fn handler(
+    State(state): State<Arc<ApiState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(params): Query<JsonQueryParams>,
+) -> Response {
+    let query = match get_query(&params) {
+        Ok(query) => query,
+        Err(e) => {
+            tracing::debug!(params = ?params, "Invalid JSON DNS query: {}", e);
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+
+    let response = match resolve_query(&state, query, client.ip()).await {
+        Ok(response) => response,
+        Err(status) => return status.into_response(),
+    };
+    let packet = match DnsPacket::from_buf(&mut ByteBuf::new(&response.response)) {
+        Ok(packet) => packet,
+        Err(e) => {
+            tracing::debug!("Error while decoding the response to a JSON DNS query: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    Json(get_json_response(&packet, response.source)).into_response()
+}
+
+/// Builds the encoded recursive query from the request parameters
+fn get_query(params: &JsonQueryParams) -> anyhow::Result<Vec<u8>> {
+    let qname: DomainName = params
+        .name
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid 'name' parameter: {}", e))?;
+    let query_type = match params.query_type.as_deref() {
+        None => QueryType::A,
+        Some(query_type) => match query_type.parse::<u16>() {
+            Ok(query_type) => QueryType::from(query_type),
+            Err(_) => query_type
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid 'type' parameter"))?,
+        },
+    };
+    let dnssec_ok = parse_flag(params.dnssec_ok.as_deref()).ok_or_else(|| anyhow::anyhow!("Invalid 'do' parameter"))?;
+    let checking_disabled =
+        parse_flag(params.cd.as_deref()).ok_or_else(|| anyhow::anyhow!("Invalid 'cd' parameter"))?;
+
+    let mut packet = DnsPacket::new();
+    packet.header.id = rand::random();
+    packet.header.recursion_desired = true;
+    // CD bit
+    packet.header.z[2] = checking_disabled;
+    packet.add_question(Question::new(qname, query_type, None));
+    packet.set_edns(ResourceRecord::new(
+        DomainName::root(),
+        ResourceData::OPT { options: Vec::new() },
+        dnssec_ok.then_some(EDNS_DO_BIT),
+        Some(EDNS_BUF_SIZE),
+    ));
+
+    let mut buf = ByteBuf::new_empty(None);
+    packet.encode_to_buf(&mut buf, None)?;
+
+    Ok(buf.into_inner().into_owned())
+}
+
+fn parse_flag(value: Option<&str>) -> Option<bool> {
+    match value {
+        None | Some("" | "0" | "false") => Some(false),
+        Some("1" | "true") => Some(true),
+        Some(_) => None,
+    }
+}
+
+fn get_json_response(packet: &DnsPacket<'_>, source: Option<ResponseSource>) -> JsonResponse {
+    JsonResponse {
+        status: packet.header.response_code.into(),
+        truncated: packet.header.truncation,
+        recursion_desired: packet.header.recursion_desired,
+        recursion_available: packet.header.recursion_available,
+        // AD bit
+        authentic_data: packet.header.z[1],
+        // CD bit
+        checking_disabled: packet.header.z[2],
+        question: packet
+            .questions
+            .iter()
+            .map(|question| JsonQuestion {
+                name: get_absolute_name(&question.qname),
+                query_type: question.query_type.into(),
+            })
+            .collect(),
+        answer: get_json_records(&packet.answers),
+        authority: get_json_records(&packet.authorities),
+        source: source.map(|source| match source {
+            ResponseSource::Denylist => "Denylist",
+            ResponseSource::Allowlist => "Allowlist",
+            ResponseSource::Cache => "Cache",
+            ResponseSource::NoRecurse => "NoRecurse",
+            ResponseSource::Upstream => "Upstream",
+            ResponseSource::UpstreamTimeout => "UpstreamTimeout",
+        }),
+    }
+}
+
+fn get_json_records(records: &[ResourceRecord<'_>]) -> Vec<JsonRecord> {
+    records
+        .iter()
+        .map(|rr| JsonRecord {
+            name: get_absolute_name(&rr.name),
+            query_type: rr.resource_data.get_query_type().into(),
+            ttl: rr.ttl,
+            data: rr.resource_data.to_string(),
+        })
+        .collect()
+}
+
+fn get_absolute_name(name: &DomainName<'_>) -> String {
+    if name.is_root() {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use o_dns_lib::ResponseCode;
+
+    use super::*;
+
+    fn get_params(name: &str, query_type: Option<&str>, dnssec_ok: Option<&str>, cd: Option<&str>) -> JsonQueryParams {
+        JsonQueryParams {
+            name: name.to_string(),
+            query_type: query_type.map(str::to_string),
+            dnssec_ok: dnssec_ok.map(str::to_string),
+            cd: cd.map(str::to_string),
+        }
+    }
+
+    fn decode_query(params: &JsonQueryParams) -> DnsPacket<'static> {
+        let query = get_query(params).expect("shouldn't have failed");
+        DnsPacket::from_buf(&mut ByteBuf::new_from_vec(query)).unwrap()
+    }
+
+    #[test]
+    fn parse_flags() {
+        for value in [None, Some(""), Some("0"), Some("false")] {
+            assert_eq!(parse_flag(value), Some(false));
+        }
+        for value in [Some("1"), Some("true")] {
+            assert_eq!(parse_flag(value), Some(true));
+        }
+        assert_eq!(parse_flag(Some("yes")), None);
+    }
+
+    #[test]
+    fn build_query_from_params() {
+        let query = decode_query(&get_params("example.com", None, None, None));
+        assert!(query.header.recursion_desired);
+        assert!(!query.header.z[2]);
+        assert_eq!(query.questions[0].qname, "example.com".parse().unwrap());
+        assert_eq!(query.questions[0].query_type, QueryType::A);
+        let edns_data = query.additionals[query.edns.unwrap()].get_edns_data().unwrap();
+        assert_eq!(edns_data.udp_payload_size, EDNS_BUF_SIZE as usize);
+        assert!(!edns_data.dnssec_ok_bit);
+
+        let query = decode_query(&get_params("example.com", Some("AAAA"), Some("1"), Some("true")));
+        assert_eq!(query.questions[0].query_type, QueryType::AAAA);
+        assert!(query.header.z[2]);
+        let edns_data = query.additionals[query.edns.unwrap()].get_edns_data().unwrap();
+        assert!(edns_data.dnssec_ok_bit);
+
+        // Types may be given by their numbers as well
+        let query = decode_query(&get_params("example.com", Some("65"), None, None));
+        assert_eq!(query.questions[0].query_type, QueryType::HTTPS);
+        let query = decode_query(&get_params("example.com", Some("65280"), None, None));
+        assert_eq!(query.questions[0].query_type, QueryType::UNKNOWN(65280));
+
+        for (params, error) in [
+            (
+                get_params("example.com", Some("NOTATYPE"), None, None),
+                "Invalid 'type' parameter",
+            ),
+            (
+                get_params("example.com", None, Some("2"), None),
+                "Invalid 'do' parameter",
+            ),
+            (
+                get_params("example.com", None, None, Some("on")),
+                "Invalid 'cd' parameter",
+            ),
+        ] {
+            assert_eq!(get_query(&params).unwrap_err().to_string(), error);
+        }
+        let error = get_query(&get_params("a..example", None, None, None)).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid 'name' parameter"));
+    }
+
+    #[test]
+    fn build_json_response() {
+        let mut packet = DnsPacket::new();
+        packet.header.is_response = true;
+        packet.header.recursion_desired = true;
+        packet.header.recursion_available = true;
+        // AD bit
+        packet.header.z[1] = true;
+        packet.header.response_code = ResponseCode::Unknown(10);
+        packet.add_question(Question::new("example.com".parse().unwrap(), QueryType::A, None));
+        packet.add_answer(ResourceRecord::new(
+            "example.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::new(192, 0, 2, 1),
+            },
+            Some(300),
+            None,
+        ));
+
+        let response = serde_json::to_value(get_json_response(&packet, Some(ResponseSource::Cache))).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "Status": 10,
+                "TC": false,
+                "RD": true,
+                "RA": true,
+                "AD": true,
+                "CD": false,
+                "Question": [{ "name": "example.com.", "type": 1 }],
+                "Answer": [{ "name": "example.com.", "type": 1, "TTL": 300, "data": "192.0.2.1" }],
+                "Source": "Cache",
+            })
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse as _, Response};
+use o_dns_common::{DnsServerCommand, ForwardingRule};
+use o_dns_db::{ForwardingRuleEntry, Model as _};
+use o_dns_lib::DomainName;
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+use super::{ValidatableRequest, ValidatedJson};
+use crate::ApiState;
+
+#[derive(Debug, Deserialize)]
+pub struct RawForwardingRuleRequest {
+    pub domain: String,
+    pub upstreams: Vec<String>,
+    pub label: Option<String>,
+}
+
+pub struct SetForwardingRuleRequest {
+    pub entry: ForwardingRuleEntry,
+}
+
+impl ValidatableRequest for SetForwardingRuleRequest {
+    type Raw = RawForwardingRuleRequest;
+
+    fn validate(raw: Self::Raw) -> anyhow::Result<Self> {
+        // Rules are stored in the same form as the query log domains, so that each domain has a single rule
+        let domain = match raw.domain.parse::<DomainName>() {
+            Ok(domain) => domain.to_ascii_lowercase().to_string(),
+            Err(e) => anyhow::bail!("Invalid 'domain': {:#}", e),
+        };
+        if raw.upstreams.is_empty() {
+            anyhow::bail!("At least one upstream resolver is required");
+        }
+
+        Ok(SetForwardingRuleRequest {
+            entry: ForwardingRuleEntry::new(domain, raw.upstreams, raw.label)?,
+        })
+    }
+}
+
+pub async fn handler(
+    State(state): State<Arc<ApiState>>,
+    ValidatedJson(request): ValidatedJson<SetForwardingRuleRequest>,
+) -> Response {
+    match set_forwarding_rule_handler(&state, request).await {
+        Ok(Ok(())) => StatusCode::OK.into_response(),
+        Ok(Err(reason)) => (StatusCode::BAD_REQUEST, reason).into_response(),
+        Err(e) => {
+            tracing::debug!("Error while setting a forwarding rule: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Stores the rule and sets it on the DNS server, restoring the previous one if the server has rejected it.
+/// Returns the reason why the rule was rejected
+async fn set_forwarding_rule_handler(
+    state: &ApiState,
+    request: SetForwardingRuleRequest,
+) -> anyhow::Result<Result<(), String>> {
+    // Commit right away, as the transaction blocks the query log inserts
+    let mut txn = state.db.begin_transaction().await?;
+    let previous_entry = ForwardingRuleEntry::select_by_domain(&mut txn, &request.entry.domain).await?;
+    request.entry.replace_into(&mut txn).await?;
+    txn.commit().await.context("failed to commit the forwarding rule")?;
+
+    let domain = request.entry.domain.clone();
+    let result = send_forwarding_rule(state, ForwardingRule::from(request.entry)).await;
+    if !matches!(result, Ok(Ok(()))) {
+        // Keep the stored rules the same as the ones used by the DNS server
+        let mut connection = state.db.get_connection().await?;
+        match previous_entry {
+            Some(entry) => entry.replace_into(&mut connection).await.map(|_| ()),
+            None => ForwardingRuleEntry::delete_by_domain(&mut connection, &domain).await,
+        }
+        .context("failed to restore the previous forwarding rule")?;
+    }
+
+    result
+}
+
+async fn send_forwarding_rule(state: &ApiState, rule: ForwardingRule) -> anyhow::Result<Result<(), String>> {
+    let (response_tx, response_rx) = oneshot::channel();
+    let cmd = DnsServerCommand::SetForwardingRule { rule, response_tx };
+    state.command_tx.send(cmd).await.context("DNS server is not running")?;
+
+    response_rx.await.context("DNS server has dropped the command")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+    use crate::test_utils::get_api_state;
+
+    /// Returns the state, where the DNS server rejects the rules with the `invalid` upstream
+    async fn get_state() -> Arc<ApiState> {
+        let (command_tx, mut command_rx) = channel(1);
+        tokio::spawn(async move {
+            while let Some(command) = command_rx.recv().await {
+                if let DnsServerCommand::SetForwardingRule { rule, response_tx } = command {
+                    let result = if rule.upstreams.iter().any(|upstream| upstream == "invalid") {
+                        Err("invalid upstream resolver".to_string())
+                    } else {
+                        Ok(())
+                    };
+                    let _ = response_tx.send(result);
+                }
+            }
+        });
+
+        get_api_state(command_tx).await
+    }
+
+    async fn set_rule(state: &ApiState, domain: &str, upstream: &str) -> Result<(), String> {
+        let request = SetForwardingRuleRequest::validate(RawForwardingRuleRequest {
+            domain: domain.to_string(),
+            upstreams: vec![upstream.to_string()],
+            label: None,
+        })
+        .unwrap();
+        set_forwarding_rule_handler(state, request).await.unwrap()
+    }
+
+    async fn get_rules(state: &ApiState) -> Vec<(String, Vec<String>)> {
+        let mut connection = state.db.get_connection().await.unwrap();
+        ForwardingRuleEntry::select_all(&mut connection)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.domain, entry.upstreams))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn restores_previous_rule_on_rejection() {
+        let state = get_state().await;
+        let rules = vec![("corp.example".to_string(), vec!["10.0.0.1".to_string()])];
+
+        assert_eq!(set_rule(&state, "Corp.Example.", "10.0.0.1").await, Ok(()));
+        assert_eq!(get_rules(&state).await, rules);
+
+        // Rejected replacement leaves the previous rule in place
+        assert!(set_rule(&state, "corp.example", "invalid").await.is_err());
+        assert_eq!(get_rules(&state).await, rules);
+
+        // Rejected new rule isn't stored at all
+        assert!(set_rule(&state, "lan", "invalid").await.is_err());
+        assert_eq!(get_rules(&state).await, rules);
+    }
+}
@@ -1,30 +1,48 @@
 mod handlers;
 mod routes;
+#[cfg(test)]
+mod test_utils;
 mod util;
 
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
-use axum::Router;
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
 use o_dns_common::DnsServerCommand;
 use o_dns_db::SqliteDb;
-use routes::get_router;
+use routes::{get_doh_router, get_router};
+use rustls::ServerConfig;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc::Sender;
+use tokio_rustls::TlsAcceptor;
 
+/// Time limit for clients of the DoH listener to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
 pub struct ApiServer {
     router: Router,
+    /// Router with the DNS-over-HTTPS endpoint only
+    doh_router: Router,
 }
 
 impl ApiServer {
     pub fn new(db: SqliteDb, dns_server_command_tx: Sender<DnsServerCommand>) -> Self {
-        let state = ApiState {
+        let state = Arc::new(ApiState {
             db,
             command_tx: dns_server_command_tx,
-        };
-        let router = get_router(state);
+        });
 
-        ApiServer { router }
+        ApiServer {
+            router: get_router(state.clone()),
+            doh_router: get_doh_router(state),
+        }
     }
 
     pub async fn serve(self, listen_on: SocketAddr) -> anyhow::Result<()> {
@@ -32,9 +50,55 @@ impl ApiServer {
             .await
             .context("failed to bind a listener")?;
 
-        axum::serve(listener, self.router)
+        axum::serve(
+            listener,
+            self.router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .context("error while serving requests")
+    }
+
+    /// Serves the DNS-over-HTTPS endpoint over TLS, without exposing the rest of the API
+    pub async fn serve_doh(self, listen_on: SocketAddr, mut tls_config: ServerConfig) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(listen_on)
             .await
-            .context("error while serving requests")
+            .context("failed to bind a DoH listener")?;
+        tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        loop {
+            let (stream, client) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    tracing::debug!("Error while accepting a DoH connection: {}", e);
+                    continue;
+                }
+            };
+
+            let acceptor = acceptor.clone();
+            // Handlers extract the client's address the same way as with `axum::serve`
+            let service = TowerToHyperService::new(self.doh_router.clone().layer(Extension(ConnectInfo(client))));
+            tokio::spawn(async move {
+                let stream = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        tracing::debug!(%client, "DoH TLS handshake has failed: {}", e);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!(%client, "DoH TLS handshake has timed out");
+                        return;
+                    }
+                };
+
+                if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!(%client, "Error while serving a DoH connection: {}", e);
+                }
+            });
+        }
     }
 }
 
@@ -8,18 +8,23 @@ use tower_http::cors::{Any, CorsLayer};
 
 use super::ApiState;
 use crate::handlers::{
-    delete_list_entry, get_list_entries, get_query_logs, get_stats, health_check, modify_list_entry,
+    delete_forwarding_rules, delete_list_entry, get_dns_query, get_forwarding_rules, get_list_entries, get_query_logs,
+    get_stats, health_check, modify_list_entry, post_dns_query, resolve_json_query, set_forwarding_rule,
 };
 
-pub fn get_router(state: ApiState) -> Router {
-    let state = Arc::new(state);
+pub fn get_router(state: Arc<ApiState>) -> Router {
     Router::new()
         .route("/", get(health_check))
         .route("/logs", get(get_query_logs))
         .route("/entry", post(modify_list_entry))
         .route("/entry", delete(delete_list_entry))
         .route("/entry", get(get_list_entries))
+        .route("/forward", post(set_forwarding_rule))
+        .route("/forward", delete(delete_forwarding_rules))
+        .route("/forward", get(get_forwarding_rules))
         .route("/stats", get(get_stats))
+        .route("/dns-query", get(get_dns_query).post(post_dns_query))
+        .route("/resolve", get(resolve_json_query))
         .layer(
             CorsLayer::new()
                 .allow_methods([Method::GET, Method::POST, Method::DELETE])
@@ -28,3 +33,10 @@ pub fn get_router(state: ApiState) -> Router {
         )
         .with_state(state)
 }
+
+/// DNS-over-HTTPS (RFC8484) endpoint, which is served on its own by the DoH listener
+pub fn get_doh_router(state: Arc<ApiState>) -> Router {
+    Router::new()
+        .route("/dns-query", get(get_dns_query).post(post_dns_query))
+        .with_state(state)
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use o_dns_common::DnsServerCommand;
+use o_dns_db::SqliteDb;
+use tokio::sync::mpsc::Sender;
+
+use crate::ApiState;
+
+/// Creates the state with a new DB in a temporary directory
+pub async fn get_api_state(command_tx: Sender<DnsServerCommand>) -> Arc<ApiState> {
+    let path = std::env::temp_dir().join(format!("o-dns-api-test-{:x}", rand::random::<u64>()));
+    let db = SqliteDb::new(&path).await.unwrap();
+    db.init_tables().await.unwrap();
+
+    Arc::new(ApiState { db, command_tx })
+}
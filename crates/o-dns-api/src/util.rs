@@ -34,6 +34,15 @@ pub fn build_delete_list_entries_query(ids: &[u32]) -> QueryBuilder<'static, Sql
     query
 }
 
+pub fn build_delete_forwarding_rules_query(ids: &[u32]) -> QueryBuilder<'static, Sqlite> {
+    let mut query = sqlx::QueryBuilder::new("DELETE FROM forwarding_rule WHERE id IN ");
+    query.push_tuples(ids, |mut tup, id| {
+        tup.push_bind(*id);
+    });
+    query.push(" RETURNING *");
+    query
+}
+
 pub fn build_select_list_entries_with_filters(filter: &ListEntriesFilter) -> QueryBuilder<'static, Sqlite> {
     let mut query = sqlx::QueryBuilder::new("SELECT * FROM allow_deny_list");
 
@@ -3,7 +3,8 @@ mod util;
 use std::net::IpAddr;
 
 use regex::Regex;
-pub use util::hash_to_u128;
+use tokio::sync::oneshot;
+pub use util::{build_denylist_regex, hash_to_u128};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ResponseSource {
@@ -12,6 +13,7 @@ pub enum ResponseSource {
     Cache,
     NoRecurse,
     Upstream,
+    UpstreamTimeout,
 }
 
 #[derive(Debug)]
@@ -21,8 +23,38 @@ pub enum AccessListEntryKind {
     Hosts((u128, IpAddr)),
 }
 
+/// Conditional forwarding rule, where queries for `domain` and its subdomains are sent to dedicated upstreams
+#[derive(Debug, Clone)]
+pub struct ForwardingRule {
+    pub domain: String,
+    /// Upstream resolvers in the same format as the `--upstream-resolver` argument
+    pub upstreams: Vec<String>,
+}
+
+/// Encoded response to a query received by the API server
+#[derive(Debug)]
+pub struct ApiQueryResponse {
+    pub response: Vec<u8>,
+    pub source: Option<ResponseSource>,
+    /// Lowest TTL of the response's RRs, for how long HTTP caches may keep it
+    pub ttl: Option<u32>,
+}
+
 #[derive(Debug)]
 pub enum DnsServerCommand {
     AddNewListEntry(AccessListEntryKind),
     RemoveListEntry(AccessListEntryKind),
+    /// Query received by the API server, e.g. over DoH, which is resolved with the same pipeline as the others
+    ResolveQuery {
+        query: Vec<u8>,
+        client: Option<IpAddr>,
+        response_tx: oneshot::Sender<ApiQueryResponse>,
+    },
+    /// Adds a forwarding rule or replaces the one for the same domain, replying with an error if it's invalid
+    SetForwardingRule {
+        rule: ForwardingRule,
+        response_tx: oneshot::Sender<Result<(), String>>,
+    },
+    /// Removes the forwarding rule for the domain
+    RemoveForwardingRule(String),
 }
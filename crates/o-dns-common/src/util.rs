@@ -1,3 +1,4 @@
+use regex::{Regex, RegexBuilder};
 use sha1::Digest as _;
 
 pub fn hash_to_u128(data: impl AsRef<[u8]>, prefix: Option<&[u8]>) -> u128 {
@@ -9,3 +10,9 @@ pub fn hash_to_u128(data: impl AsRef<[u8]>, prefix: Option<&[u8]>) -> u128 {
     let hash = hasher.finalize();
     u128::from_be_bytes(hash[..16].try_into().unwrap())
 }
+
+/// Compiles a denylist regex, which is matched against names in the lowercase presentation format,
+/// so that patterns with uppercase letters still match
+pub fn build_denylist_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
@@ -3,7 +3,9 @@ use std::path::Path;
 use std::time::Duration;
 
 use anyhow::Context as _;
-pub use models::{EntryKind, ListEntry, ListEntryUpdateRequest, Model, QueryLog, StatsEntry, Updatable};
+pub use models::{
+    EntryKind, ForwardingRuleEntry, ListEntry, ListEntryUpdateRequest, Model, QueryLog, StatsEntry, Updatable,
+};
 use sqlx::pool::PoolConnection;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::{Sqlite, SqlitePool, Transaction};
@@ -45,13 +47,27 @@ impl SqliteDb {
                 client TEXT,
                 response_code INTEGER NOT NULL,
                 response_delay_ms INTEGER NOT NULL,
-                source INTEGER
+                source INTEGER,
+                upstream TEXT
             )",
         )
         .execute(&self.connection_pool)
         .await
         .context("error while initializing the 'query_log' table")?;
 
+        // Tables created before the 'upstream' column was introduced
+        let has_upstream_column: bool =
+            sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info('query_log') WHERE name = 'upstream'")
+                .fetch_one(&self.connection_pool)
+                .await
+                .context("error while reading the 'query_log' table info")?;
+        if !has_upstream_column {
+            sqlx::query("ALTER TABLE query_log ADD COLUMN upstream TEXT")
+                .execute(&self.connection_pool)
+                .await
+                .context("error while adding the 'upstream' column to the 'query_log' table")?;
+        }
+
         sqlx::query(
             "CREATE TABLE IF NOT EXISTS allow_deny_list (
                 id INTEGER PRIMARY KEY,
@@ -66,6 +82,19 @@ impl SqliteDb {
         .await
         .context("error while initializing the 'allow_deny_list' table")?;
 
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS forwarding_rule (
+                id INTEGER PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                domain TEXT NOT NULL UNIQUE,
+                upstreams TEXT NOT NULL,
+                label TEXT
+            )",
+        )
+        .execute(&self.connection_pool)
+        .await
+        .context("error while initializing the 'forwarding_rule' table")?;
+
         Ok(())
     }
 
@@ -77,7 +106,7 @@ impl SqliteDb {
     }
 
     /// It is the responsibility of the caller to commit the transaction.
-    pub async fn begin_transaction(&self) -> anyhow::Result<Transaction<Sqlite>> {
+    pub async fn begin_transaction(&self) -> anyhow::Result<Transaction<'_, Sqlite>> {
         self.connection_pool
             .begin()
             .await
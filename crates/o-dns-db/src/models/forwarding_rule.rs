@@ -0,0 +1,126 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use o_dns_common::ForwardingRule;
+use serde::Serialize;
+use sqlx::sqlite::{SqliteQueryResult, SqliteRow};
+use sqlx::{FromRow, Row, SqliteConnection};
+
+use super::Model;
+
+/// Separator of the upstreams in the 'upstreams' column
+const UPSTREAMS_SEPARATOR: char = ',';
+
+#[derive(Debug, Serialize)]
+pub struct ForwardingRuleEntry {
+    pub id: u32,
+    pub timestamp: u32,
+    /// Lowercase domain without the trailing dot
+    pub domain: String,
+    pub upstreams: Vec<String>,
+    pub label: Option<String>,
+}
+
+impl ForwardingRuleEntry {
+    pub fn new(domain: String, upstreams: Vec<String>, label: Option<String>) -> anyhow::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("bug: misconfigured time on the system")?
+            .as_secs() as u32;
+
+        if upstreams.iter().any(|upstream| upstream.contains(UPSTREAMS_SEPARATOR)) {
+            anyhow::bail!("upstream resolvers can't contain '{}'", UPSTREAMS_SEPARATOR);
+        }
+
+        Ok(ForwardingRuleEntry {
+            id: 0,
+            timestamp,
+            domain,
+            upstreams,
+            label,
+        })
+    }
+
+    pub async fn select_all(connection: &mut SqliteConnection) -> anyhow::Result<Vec<ForwardingRuleEntry>> {
+        sqlx::query_as("SELECT * FROM forwarding_rule")
+            .fetch_all(connection)
+            .await
+            .context("failed to select all forwarding rules")
+    }
+
+    pub async fn select_by_domain(
+        connection: &mut SqliteConnection,
+        domain: &str,
+    ) -> anyhow::Result<Option<ForwardingRuleEntry>> {
+        sqlx::query_as("SELECT * FROM forwarding_rule WHERE domain = ?1")
+            .bind(domain)
+            .fetch_optional(connection)
+            .await
+            .context("failed to select a forwarding rule")
+    }
+
+    pub async fn delete_by_domain(connection: &mut SqliteConnection, domain: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM forwarding_rule WHERE domain = ?1")
+            .bind(domain)
+            .execute(connection)
+            .await
+            .context("failed to delete a forwarding rule")?;
+        Ok(())
+    }
+}
+
+impl From<ForwardingRuleEntry> for ForwardingRule {
+    fn from(entry: ForwardingRuleEntry) -> Self {
+        ForwardingRule {
+            domain: entry.domain,
+            upstreams: entry.upstreams,
+        }
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for ForwardingRuleEntry {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        let upstreams: String = row.try_get("upstreams")?;
+
+        Ok(ForwardingRuleEntry {
+            id: row.try_get("id")?,
+            timestamp: row.try_get("timestamp")?,
+            domain: row.try_get("domain")?,
+            upstreams: upstreams
+                .split(UPSTREAMS_SEPARATOR)
+                .filter(|upstream| !upstream.is_empty())
+                .map(Into::into)
+                .collect(),
+            label: row.try_get("label")?,
+        })
+    }
+}
+
+impl Model for ForwardingRuleEntry {
+    const NAME: &'static str = "ForwardingRule";
+
+    async fn bind_and_insert(&self, connection: &mut SqliteConnection) -> anyhow::Result<SqliteQueryResult> {
+        sqlx::query("INSERT INTO forwarding_rule (timestamp, domain, upstreams, label) VALUES (?1, ?2, ?3, ?4)")
+            .bind(self.timestamp)
+            .bind(&self.domain)
+            .bind(self.upstreams.join(&UPSTREAMS_SEPARATOR.to_string()))
+            .bind(&self.label)
+            .execute(connection)
+            .await
+            .context("error while inserting a forwarding rule")
+    }
+
+    async fn bind_and_replace(&self, connection: &mut SqliteConnection) -> anyhow::Result<SqliteQueryResult> {
+        sqlx::query(
+            "REPLACE INTO forwarding_rule (id, timestamp, domain, upstreams, label)
+            VALUES ((SELECT id FROM forwarding_rule WHERE domain = ?2), ?1, ?2, ?3, ?4)",
+        )
+        .bind(self.timestamp)
+        .bind(&self.domain)
+        .bind(self.upstreams.join(&UPSTREAMS_SEPARATOR.to_string()))
+        .bind(&self.label)
+        .execute(connection)
+        .await
+        .context("error while replacing a forwarding rule")
+    }
+}
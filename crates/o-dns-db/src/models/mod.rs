@@ -1,8 +1,10 @@
+mod forwarding_rule;
 mod list_entry;
 mod query_log;
 mod stats;
 
 use anyhow::Context as _;
+pub use forwarding_rule::ForwardingRuleEntry;
 pub use list_entry::{EntryKind, ListEntry, ListEntryUpdateRequest};
 pub use query_log::QueryLog;
 use serde::Serialize;
@@ -20,6 +20,8 @@ pub struct QueryLog {
     pub response_code: u8,
     pub response_delay_ms: u32,
     pub source: Option<u8>,
+    /// Upstream resolver that has answered the query
+    pub upstream: Option<String>,
 }
 
 impl QueryLog {
@@ -28,6 +30,7 @@ impl QueryLog {
         client: Option<IpAddr>,
         response_delay_ms: u32,
         source: Option<ResponseSource>,
+        upstream: Option<String>,
     ) -> anyhow::Result<Self> {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -42,12 +45,13 @@ impl QueryLog {
         Ok(QueryLog {
             id: 0,
             timestamp,
-            domain: question.qname.clone().into_owned(),
+            domain: question.qname.to_string(),
             qtype: question.query_type.into(),
             client: client.map(|addr| addr.to_string()),
-            response_code: response.header.response_code as u8,
+            response_code: response.header.response_code.into(),
             response_delay_ms,
             source: source.map(|src| src as u8),
+            upstream,
         })
     }
 }
@@ -57,8 +61,8 @@ impl Model for QueryLog {
 
     async fn bind_and_insert(&self, connection: &mut SqliteConnection) -> anyhow::Result<SqliteQueryResult> {
         sqlx::query(
-            "INSERT INTO query_log (timestamp, domain, qtype, client, response_code, response_delay_ms, source)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            "INSERT INTO query_log (timestamp, domain, qtype, client, response_code, response_delay_ms, source, upstream)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
         )
         .bind(self.timestamp)
         .bind(&self.domain)
@@ -67,6 +71,7 @@ impl Model for QueryLog {
         .bind(self.response_code)
         .bind(self.response_delay_ms)
         .bind(self.source.as_ref().map(|src| *src))
+        .bind(&self.upstream)
         .execute(connection)
         .await
         .context("error while inserting a log entry")
@@ -1,4 +1,3 @@
-use core::str;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::Write;
@@ -6,15 +5,21 @@ use std::ops::{Deref, DerefMut};
 
 use anyhow::Context;
 
-pub trait FromBuf: Sized {
-    fn from_buf(buf: &mut ByteBuf) -> anyhow::Result<Self>;
+use crate::{DnsError, DomainName};
+
+/// Names can't have more than 127 labels, so any more hops would mean that pointers point to other pointers
+const MAX_POINTER_HOPS: usize = 127;
+
+/// Decoding from a buffer, where the decoded item can borrow from the buffer's source data
+pub trait FromBuf<'a>: Sized {
+    fn from_buf(buf: &mut ByteBuf<'a>) -> anyhow::Result<Self>;
 }
 
 pub trait EncodeToBuf {
     fn encode_to_buf_with_cache<'cache, 'r: 'cache>(
         &'r self,
         buf: &mut ByteBuf,
-        label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
         max_size: Option<usize>,
     ) -> anyhow::Result<usize>;
 
@@ -24,7 +29,7 @@ pub trait EncodeToBuf {
 }
 
 pub trait EncodedSize {
-    fn get_encoded_size(&self, label_cache: Option<&HashMap<&str, usize>>) -> usize;
+    fn get_encoded_size(&self, label_cache: Option<&HashMap<&[u8], usize>>) -> usize;
 }
 
 pub struct ByteBuf<'a> {
@@ -69,6 +74,10 @@ impl<'a> ByteBuf<'a> {
         }
     }
 
+    pub fn new_from_cow(src: Cow<'a, [u8]>) -> ByteBuf<'a> {
+        ByteBuf { buf: src, pos: 0 }
+    }
+
     pub fn new_empty(capacity: Option<usize>) -> ByteBuf<'static> {
         ByteBuf {
             buf: Cow::Owned(Vec::with_capacity(capacity.unwrap_or(512))),
@@ -92,6 +101,10 @@ impl<'a> ByteBuf<'a> {
         self.pos = 0;
     }
 
+    pub fn get_pos(&self) -> usize {
+        self.pos
+    }
+
     pub fn resize(&mut self, new_len: usize) {
         self.buf.to_mut().resize(new_len, 0);
     }
@@ -131,6 +144,57 @@ impl<'a> ByteBuf<'a> {
         self.write_bytes(&data.to_be_bytes(), Some(pos))
     }
 
+    pub fn read_u32(&mut self) -> anyhow::Result<u32> {
+        self.read_bytes(4)
+            .and_then(|bytes| {
+                TryInto::<[u8; 4]>::try_into(bytes).context("bug: should be exactly four bytes in length")
+            })
+            .map(u32::from_be_bytes)
+    }
+
+    pub fn write_u32(&mut self, data: u32) -> anyhow::Result<()> {
+        self.write_bytes(&data.to_be_bytes(), None)
+    }
+
+    /// Reads a length-prefixed `<character-string>` as defined in RFC1035
+    pub fn read_character_string(&mut self) -> anyhow::Result<&[u8]> {
+        let length = self
+            .read_u8()
+            .context("malformed packet: expected character-string length")?;
+        let pos = self.pos;
+        self.read_bytes(length as usize).with_context(|| {
+            format!(
+                "malformed packet: expected character-string of length {} at byte {}",
+                length, pos
+            )
+        })
+    }
+
+    /// Same as [`ByteBuf::read_character_string`], but borrows from the source data if possible
+    pub fn read_cow_character_string(&mut self) -> anyhow::Result<Cow<'a, [u8]>> {
+        let length = self
+            .read_u8()
+            .context("malformed packet: expected character-string length")?;
+        let pos = self.pos;
+        self.read_cow_bytes(length as usize).with_context(|| {
+            format!(
+                "malformed packet: expected character-string of length {} at byte {}",
+                length, pos
+            )
+        })
+    }
+
+    /// Writes a length-prefixed `<character-string>` as defined in RFC1035
+    pub fn write_character_string(&mut self, data: &[u8]) -> anyhow::Result<usize> {
+        if data.len() > u8::MAX as usize {
+            return Err(DnsError::CharacterStringTooLong(data.len()).into());
+        }
+        self.write_u8(data.len() as u8);
+        self.write_bytes(data, None)
+            .context("error while writing character-string to the underlying buffer")?;
+        Ok(1 + data.len())
+    }
+
     pub fn read_bytes(&mut self, n: usize) -> anyhow::Result<&[u8]> {
         self.ensure_length(n, None)?;
         let pos = self.pos;
@@ -139,6 +203,17 @@ impl<'a> ByteBuf<'a> {
             .ok_or_else(|| anyhow::anyhow!("bug: should be present"))
     }
 
+    /// Same as [`ByteBuf::read_bytes`], but borrows from the source data if the buffer doesn't own it
+    pub fn read_cow_bytes(&mut self, n: usize) -> anyhow::Result<Cow<'a, [u8]>> {
+        self.ensure_length(n, None)?;
+        let pos = self.pos;
+        self.pos += n;
+        Ok(match &self.buf {
+            Cow::Borrowed(buf) => Cow::Borrowed(&buf[pos..pos + n]),
+            Cow::Owned(buf) => Cow::Owned(buf[pos..pos + n].to_vec()),
+        })
+    }
+
     pub fn peek_bytes(&self, pos: usize, n: usize) -> anyhow::Result<&[u8]> {
         self.ensure_length(n, None)?;
         self.get_range(pos, n).context("bug: should be present")
@@ -188,9 +263,20 @@ impl<'a> ByteBuf<'a> {
         Ok(pos - self.pos)
     }
 
-    pub fn read_qname(&mut self) -> anyhow::Result<Cow<'static, str>> {
+    /// Reads a possibly compressed domain name.
+    ///
+    /// Compression pointers must point strictly backwards, i.e. before the start of the labels that contain them,
+    /// which makes pointer loops impossible.
+    ///
+    /// The name borrows from the source data if it isn't compressed and the buffer doesn't own the data.
+    pub fn read_qname(&mut self) -> anyhow::Result<DomainName<'a>> {
+        let start = self.pos;
         let mut jumped = false;
+        let mut pointer_hops = 0;
         let mut pos = self.pos;
+        // Start of the labels sequence that is currently being read
+        let mut sequence_start = self.pos;
+        let mut name_length = 1 /* null byte */;
         let mut labels = Vec::new();
         loop {
             self.ensure_length(1, Some(pos))
@@ -202,8 +288,17 @@ impl<'a> ByteBuf<'a> {
                     .context("malformed packet: expected second jump ptr byte in QNAME")?;
                 let ptr_second_byte = self.buf[pos + 1] as u16;
                 // Construct a jump offset by clearing two MSB bits and joining two bytes
-                let offset = ((label_length as u16 ^ 0xC0) << 8) | ptr_second_byte;
-                pos = offset as usize;
+                let offset = (((label_length as u16 ^ 0xC0) << 8) | ptr_second_byte) as usize;
+
+                pointer_hops += 1;
+                if pointer_hops > MAX_POINTER_HOPS {
+                    return Err(DnsError::TooManyPointers.into());
+                }
+                if offset >= sequence_start {
+                    return Err(DnsError::BadPointer { pos, offset }.into());
+                }
+                sequence_start = offset;
+                pos = offset;
 
                 if !jumped {
                     // Skip two jump ptr bytes if jumped for the first time to continue parsing after processing QNAME
@@ -215,14 +310,27 @@ impl<'a> ByteBuf<'a> {
                 pos += 1;
 
                 if label_length != 0 {
-                    let label = self.buf.get(pos..pos + label_length as usize).with_context(|| {
-                        format!(
-                            "malformed packet: expected label of length {} at byte {}",
-                            label_length, pos
-                        )
-                    })?;
-                    let label = str::from_utf8(label)
-                        .with_context(|| format!("malformed packet: QNAME label at byte {} is not UTF-8", pos))?;
+                    name_length += 1 + label_length as usize;
+                    if name_length > DomainName::MAX_LENGTH {
+                        return Err(DnsError::NameTooLong(name_length).into());
+                    }
+
+                    let label = self
+                        .buf
+                        .get(pos..pos + label_length as usize)
+                        .ok_or(DnsError::Truncated {
+                            pos,
+                            length: label_length as usize,
+                        })
+                        .with_context(|| {
+                            format!(
+                                "malformed packet: expected label of length {} at byte {}",
+                                label_length, pos
+                            )
+                        })?;
+                    if label.len() > DomainName::MAX_LABEL_LENGTH {
+                        return Err(DnsError::LabelTooLong(label.len()).into());
+                    }
                     labels.push(label);
 
                     pos += label_length as usize;
@@ -240,70 +348,50 @@ impl<'a> ByteBuf<'a> {
             }
         }
 
-        let qname = if labels.is_empty() {
-            "".into()
-        } else {
-            labels.join(".").into()
-        };
-
-        Ok(qname)
+        match &self.buf {
+            Cow::Borrowed(buf) if !jumped => Ok(DomainName::from_wire_unchecked(Cow::Borrowed(&buf[start..self.pos]))),
+            _ => DomainName::from_labels(labels).context("malformed packet: invalid QNAME"),
+        }
     }
 
     pub fn write_qname<'cache, 'key: 'cache>(
         &mut self,
-        qname: &'key str,
-        label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        qname: &'key DomainName<'key>,
+        label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
     ) -> anyhow::Result<usize> {
-        let mut total_qname_length = 0;
-
+        let wire = qname.as_wire();
         let label_start_position = self.buf.len();
-        let mut used_cache = false;
-        for (idx, label) in qname.split('.').enumerate() {
-            if label.len() > 0x3f {
-                anyhow::bail!("label is too long ({}): {}", label.len(), label);
-            }
 
-            if !label.is_empty() {
-                let remaining_qname = qname.splitn(idx + 1, '.').last().unwrap();
-
-                let cached_position = label_cache.as_ref().and_then(|cache| cache.get(remaining_qname));
-
-                if let Some(offset) = cached_position {
-                    let jump_ptr = 0xc000 | (*offset as u16);
-                    self.write_u16(jump_ptr).context("writing jump PTR")?;
-                    used_cache = true;
-                } else {
-                    self.write_u8(label.len() as u8);
-                    self.write_bytes(label.as_bytes(), None)
-                        .with_context(|| format!("error while writing label '{}' to the underlying buffer", label))?;
+        let mut pos = 0;
+        while wire[pos] != 0 {
+            // Look up the remaining part of the name
+            let cached_position = label_cache.as_ref().and_then(|cache| cache.get(&wire[pos..]));
+            if let Some(offset) = cached_position {
+                let jump_ptr = 0xc000 | (*offset as u16);
+                self.write_bytes(&wire[..pos], None)
+                    .context("error while writing labels to the underlying buffer")?;
+                self.write_u16(jump_ptr).context("writing jump PTR")?;
+                if pos > 0 {
+                    cache_name_position(label_cache, wire, label_start_position);
                 }
-
-                if used_cache {
-                    // PTR bytes
-                    total_qname_length += 2;
-                    break;
-                } else {
-                    total_qname_length += 1 + label.as_bytes().len();
-                };
+                return Ok(pos + 2 /* PTR bytes */);
             }
+            pos += 1 + wire[pos] as usize;
         }
 
-        if total_qname_length > 0 {
-            label_cache.and_then(|cache| cache.insert(qname, label_start_position));
+        self.write_bytes(wire, None)
+            .context("error while writing labels to the underlying buffer")?;
+        if !qname.is_root() {
+            cache_name_position(label_cache, wire, label_start_position);
         }
 
-        if !used_cache {
-            self.write_u8(0);
-            // Account for the null byte
-            total_qname_length += 1;
-        }
-
-        Ok(total_qname_length)
+        Ok(wire.len())
     }
 
     fn ensure_length(&self, n: usize, pos: Option<usize>) -> anyhow::Result<()> {
-        if self.buf.len() < pos.unwrap_or(self.pos) + n {
-            anyhow::bail!("underlying buffer is too small")
+        let pos = pos.unwrap_or(self.pos);
+        if self.buf.len() < pos + n {
+            return Err(DnsError::Truncated { pos, length: n }.into());
         }
         Ok(())
     }
@@ -313,6 +401,17 @@ impl<'a> ByteBuf<'a> {
     }
 }
 
+fn cache_name_position<'cache>(
+    label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
+    wire: &'cache [u8],
+    position: usize,
+) {
+    // Jump pointers have only 14 bits for the offset
+    if position <= 0x3FFF {
+        label_cache.map(|cache| cache.insert(wire, position));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -322,7 +421,7 @@ mod tests {
         let qname = &[0x0];
         let mut buf = ByteBuf::new(qname);
         let result = buf.read_qname().expect("shouldn't have failed");
-        assert_eq!(result, "");
+        assert!(result.is_root());
     }
 
     #[test]
@@ -330,7 +429,7 @@ mod tests {
         let qname = &[0x6, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x3, 0x63, 0x6f, 0x6d, 0x0];
         let mut buf = ByteBuf::new(qname);
         let result = buf.read_qname().expect("shouldn't have failed");
-        assert_eq!(result, "google.com");
+        assert_eq!(result.to_string(), "google.com");
     }
 
     #[test]
@@ -352,15 +451,16 @@ mod tests {
     #[test]
     fn write_empty_qname() {
         let mut buf = ByteBuf::new_empty(None);
-        buf.write_qname("", None).expect("shouldn't have failed");
+        buf.write_qname(&DomainName::root(), None)
+            .expect("shouldn't have failed");
         assert_eq!(&*buf, &[0x0])
     }
 
     #[test]
     fn write_qname() {
-        let qname = "google.com";
+        let qname = "google.com".parse().unwrap();
         let mut buf = ByteBuf::new_empty(None);
-        buf.write_qname(qname, None).expect("shouldn't have failed");
+        buf.write_qname(&qname, None).expect("shouldn't have failed");
         assert_eq!(
             &*buf,
             &[0x6, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x3, 0x63, 0x6f, 0x6d, 0x0,]
@@ -369,22 +469,23 @@ mod tests {
 
     #[test]
     fn write_qname_with_cache() {
-        let qname = "api.google.com";
-        let domain = qname.split_once('.').unwrap().1;
+        let qname: DomainName = "api.google.com".parse().unwrap();
+        let domain = qname.parent().unwrap();
         let mut buf = ByteBuf::new_empty(None);
         let mut cache = HashMap::new();
 
         // Should write 'google.com' and add it to cache
-        buf.write_qname(domain, Some(&mut cache))
+        buf.write_qname(&domain, Some(&mut cache))
             .expect("shouldn't have failed");
         assert_eq!(cache.len(), 1);
-        assert!(cache.get(domain).is_some_and(|pos| *pos == 0));
+        assert!(cache.get(domain.as_wire()).is_some_and(|pos| *pos == 0));
 
         // Should write 'api' and point to the rest of the qname using a jump ptr
-        buf.write_qname(qname, Some(&mut cache)).expect("shouldn't have failed");
+        buf.write_qname(&qname, Some(&mut cache))
+            .expect("shouldn't have failed");
         // Should have cached a new label
         assert_eq!(cache.len(), 2);
-        assert!(cache.get(qname).is_some_and(|pos| *pos == 12));
+        assert!(cache.get(qname.as_wire()).is_some_and(|pos| *pos == 12));
 
         assert_eq!(
             &*buf,
@@ -393,20 +494,64 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(
-        expected = "label is too long (64): very_very_very_very_very_very_long_label_that_exceeds_max_length"
-    )]
-    fn write_qname_with_long_label() {
-        let qname = "very_very_very_very_very_very_long_label_that_exceeds_max_length.com";
-        let mut buf = ByteBuf::new_empty(None);
-        buf.write_qname(qname, None).unwrap();
+    #[should_panic(expected = "label is too long (64)")]
+    fn read_qname_with_long_label() {
+        let mut qname = vec![0x40];
+        qname.extend_from_slice(&[0x61; 64]);
+        qname.push(0x0);
+        let mut buf = ByteBuf::new(&qname);
+        buf.read_qname().unwrap();
+    }
+
+    #[test]
+    fn read_qname_with_pointer_loop() {
+        let qname = &[0x1, 0x61, 0xc0, 0x0];
+        let mut buf = ByteBuf::new(qname);
+        let error = buf.read_qname().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DnsError>(),
+            Some(&DnsError::BadPointer { pos: 2, offset: 0 })
+        );
+    }
+
+    #[test]
+    fn read_qname_with_forward_pointer() {
+        let qname = &[0xc0, 0x2, 0x1, 0x61, 0x0];
+        let mut buf = ByteBuf::new(qname);
+        let error = buf.read_qname().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DnsError>(),
+            Some(&DnsError::BadPointer { pos: 0, offset: 2 })
+        );
+    }
+
+    #[test]
+    fn read_too_long_compressed_qname() {
+        // Each name has a 63-byte label and points to the previous one
+        let mut data = Vec::new();
+        let mut previous_name_pos = None;
+        for _ in 0..4 {
+            let name_pos = data.len();
+            data.push(0x3f);
+            data.extend_from_slice(&[0x61; 63]);
+            match previous_name_pos {
+                Some(pos) => data.extend_from_slice(&(0xc000 | pos as u16).to_be_bytes()),
+                None => data.push(0x0),
+            }
+            previous_name_pos = Some(name_pos);
+        }
+
+        let mut buf = ByteBuf::new(&data);
+        buf.read_bytes(previous_name_pos.unwrap()).unwrap();
+        let error = buf.read_qname().unwrap_err();
+        assert_eq!(error.downcast_ref::<DnsError>(), Some(&DnsError::NameTooLong(257)));
     }
 
     #[test]
     fn qname_roundtrip() {
-        let qname = "google.com";
+        let qname = DomainName::from_labels([b"g\x00o.gle".as_slice(), b"com"]).unwrap();
         let mut buf = ByteBuf::new_empty(None);
-        buf.write_qname(qname, None).expect("shouldn't have failed");
+        buf.write_qname(&qname, None).expect("shouldn't have failed");
         let roundtripped = buf.read_qname().expect("shouldn't have failed");
         assert_eq!(qname, roundtripped);
     }
@@ -0,0 +1,302 @@
+//! Async DNS client over UDP and TCP, along with the TCP message framing (RFC1035, section 4.2.2)
+
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::{ByteBuf, DnsPacket, EncodeToBuf, FromBuf};
+
+/// Messages that don't fit into this size must be sent over TCP, unless both sides support EDNS (RFC1035)
+const MAX_STANDARD_DNS_MSG_SIZE: usize = 512;
+/// UDP payload can't be larger than this anyway
+const MAX_UDP_MSG_SIZE: usize = u16::MAX as usize;
+
+/// Writes the message prefixed with its 2-byte length
+pub async fn write_tcp_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]) -> anyhow::Result<()> {
+    let length = u16::try_from(message.len()).context("TCP: message is too long")?;
+    // Write both parts at once, so that they aren't split into separate segments
+    let mut framed = Vec::with_capacity(2 + message.len());
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+    writer
+        .write_all(&framed)
+        .await
+        .context("TCP: error while sending a DNS message")?;
+    writer.flush().await.context("TCP: error while flushing the stream")
+}
+
+/// Reads a single length-prefixed message into `dst`, resizing it if needed. Returns the message's length
+pub async fn read_tcp_message<R: AsyncRead + Unpin>(reader: &mut R, dst: &mut ByteBuf<'_>) -> anyhow::Result<usize> {
+    let length = reader
+        .read_u16()
+        .await
+        .context("TCP: error while reading message's length")? as usize;
+    if dst.len() < length {
+        dst.resize(length);
+    }
+    reader
+        .read_exact(&mut dst[..length])
+        .await
+        .context("TCP: error while reading a DNS message")?;
+
+    Ok(length)
+}
+
+/// Sends queries to a single DNS server.
+///
+/// Queries don't time out on their own, as a UDP response may never arrive,
+/// so callers must limit them, e.g. with `tokio::time::timeout`.
+#[derive(Debug, Clone)]
+pub struct DnsClient {
+    server: SocketAddr,
+}
+
+impl DnsClient {
+    pub fn new(server: SocketAddr) -> Self {
+        DnsClient { server }
+    }
+
+    pub fn get_server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Sends the query over UDP and retries over TCP if the response is truncated.
+    ///
+    /// Queries that are too large for UDP are sent over TCP right away.
+    pub async fn query(&self, query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        let encoded_query = encode_query(query)?;
+        if encoded_query.len() > MAX_STANDARD_DNS_MSG_SIZE {
+            return self.send_tcp(&encoded_query, query).await;
+        }
+
+        let response = self.send_udp(&encoded_query, query).await?;
+        if response.header.truncation {
+            return self.send_tcp(&encoded_query, query).await;
+        }
+
+        Ok(response)
+    }
+
+    /// Sends the query over UDP, ignoring responses that don't match it until the matching one arrives
+    pub async fn query_udp(&self, query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        self.send_udp(&encode_query(query)?, query).await
+    }
+
+    /// Sends the query over TCP, where the response must match it and must not be truncated
+    pub async fn query_tcp(&self, query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        self.send_tcp(&encode_query(query)?, query).await
+    }
+
+    async fn send_udp(&self, encoded_query: &[u8], query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        let bind_addr: SocketAddr = match self.server {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0; 16], 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .context("UDP: unable to bind a socket")?;
+        // Connected socket only receives datagrams from the server
+        socket
+            .connect(self.server)
+            .await
+            .context("UDP: error while connecting to the server")?;
+        socket
+            .send(encoded_query)
+            .await
+            .context("UDP: error while sending the query")?;
+
+        let mut recv = vec![0; MAX_UDP_MSG_SIZE];
+        loop {
+            let length = socket
+                .recv(&mut recv)
+                .await
+                .context("UDP: error while reading the response")?;
+
+            // Keep waiting for the actual response if the datagram is malformed or doesn't match the query
+            let Ok(response) = DnsPacket::from_buf(&mut ByteBuf::new_from_vec(recv[..length].to_vec())) else {
+                continue;
+            };
+            if is_response_to(&response, query) {
+                break Ok(response);
+            }
+        }
+    }
+
+    async fn send_tcp(&self, encoded_query: &[u8], query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        let mut stream = TcpStream::connect(self.server)
+            .await
+            .context("TCP: error while connecting to the server")?;
+        write_tcp_message(&mut stream, encoded_query).await?;
+
+        let mut recv = ByteBuf::new_empty(None);
+        let length = read_tcp_message(&mut stream, &mut recv).await?;
+        recv.get_inner_mut().truncate(length);
+
+        let response = DnsPacket::from_buf(&mut recv).context("error while decoding the response")?;
+        if !is_response_to(&response, query) {
+            anyhow::bail!("TCP: response doesn't match the query");
+        }
+        if response.header.truncation {
+            anyhow::bail!("TCP: response is truncated");
+        }
+
+        Ok(response)
+    }
+}
+
+fn encode_query(query: &DnsPacket<'_>) -> anyhow::Result<Vec<u8>> {
+    let mut buf = ByteBuf::new_empty(None);
+    query
+        .encode_to_buf(&mut buf, None)
+        .context("error while encoding the query")?;
+    Ok(buf.into_inner().into_owned())
+}
+
+/// Checks the ID and the question, so that spoofed or late responses aren't accepted (RFC5452)
+fn is_response_to(response: &DnsPacket<'_>, query: &DnsPacket<'_>) -> bool {
+    response.header.is_response
+        && response.header.id == query.header.id
+        // Some responses (e.g. FORMERR) don't include the question
+        && (response.questions.is_empty() || response.questions == query.questions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{QueryType, Question, ResourceData, ResourceRecord};
+
+    fn get_query() -> DnsPacket<'static> {
+        let mut query = DnsPacket::new();
+        query.header.id = 0x1234;
+        query.header.recursion_desired = true;
+        query.add_question(Question::new("example.com".parse().unwrap(), QueryType::A, None));
+        query
+    }
+
+    fn get_response(query: &DnsPacket<'static>, truncation: bool) -> DnsPacket<'static> {
+        let mut response = query.clone();
+        response.header.is_response = true;
+        response.header.truncation = truncation;
+        if !truncation {
+            response.add_answer(ResourceRecord::new(
+                "example.com".parse().unwrap(),
+                ResourceData::A {
+                    address: Ipv4Addr::new(192, 0, 2, 1),
+                },
+                Some(60),
+                None,
+            ));
+        }
+        response
+    }
+
+    /// Binds both sockets to the same port, which may be already taken for TCP after binding UDP
+    async fn bind_udp_and_tcp() -> (UdpSocket, TcpListener) {
+        loop {
+            let udp_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            if let Ok(tcp_listener) = TcpListener::bind(udp_socket.local_addr().unwrap()).await {
+                break (udp_socket, tcp_listener);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_message_roundtrip() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        write_tcp_message(&mut client, b"first").await.unwrap();
+        write_tcp_message(&mut client, b"second message").await.unwrap();
+
+        let mut buf = ByteBuf::new_empty(None);
+        let length = read_tcp_message(&mut server, &mut buf).await.unwrap();
+        assert_eq!(&buf[..length], b"first");
+        let length = read_tcp_message(&mut server, &mut buf).await.unwrap();
+        assert_eq!(&buf[..length], b"second message");
+    }
+
+    #[tokio::test]
+    async fn udp_ignores_mismatched_responses() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = DnsClient::new(server.local_addr().unwrap());
+        let query = get_query();
+
+        let server_task = tokio::spawn(async move {
+            let mut recv = vec![0; MAX_UDP_MSG_SIZE];
+            let (length, peer) = server.recv_from(&mut recv).await.unwrap();
+            let query = DnsPacket::from_buf(&mut ByteBuf::new_from_vec(recv[..length].to_vec())).unwrap();
+
+            let mut spoofed = get_response(&query, false);
+            spoofed.header.id = query.header.id.wrapping_add(1);
+            server.send_to(&encode_query(&spoofed).unwrap(), peer).await.unwrap();
+            server.send_to(b"garbage", peer).await.unwrap();
+            server
+                .send_to(&encode_query(&get_response(&query, false)).unwrap(), peer)
+                .await
+                .unwrap();
+        });
+
+        let response = client.query_udp(&query).await.expect("shouldn't have failed");
+        server_task.await.unwrap();
+        assert_eq!(response, get_response(&query, false));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_tcp_on_truncation() {
+        let (udp_server, tcp_server) = bind_udp_and_tcp().await;
+        let client = DnsClient::new(udp_server.local_addr().unwrap());
+        let query = get_query();
+
+        let server_task = tokio::spawn(async move {
+            let mut recv = vec![0; MAX_UDP_MSG_SIZE];
+            let (length, peer) = udp_server.recv_from(&mut recv).await.unwrap();
+            let query = DnsPacket::from_buf(&mut ByteBuf::new_from_vec(recv[..length].to_vec())).unwrap();
+            udp_server
+                .send_to(&encode_query(&get_response(&query, true)).unwrap(), peer)
+                .await
+                .unwrap();
+
+            let (mut stream, _) = tcp_server.accept().await.unwrap();
+            let mut buf = ByteBuf::new_empty(None);
+            let length = read_tcp_message(&mut stream, &mut buf).await.unwrap();
+            assert_eq!(&buf[..length], &encode_query(&query).unwrap());
+            write_tcp_message(&mut stream, &encode_query(&get_response(&query, false)).unwrap())
+                .await
+                .unwrap();
+        });
+
+        let response = client.query(&query).await.expect("shouldn't have failed");
+        server_task.await.unwrap();
+        assert_eq!(response, get_response(&query, false));
+    }
+
+    #[tokio::test]
+    async fn tcp_rejects_mismatched_and_truncated_responses() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = DnsClient::new(server.local_addr().unwrap());
+        let query = get_query();
+
+        let server_task = tokio::spawn(async move {
+            let mut spoofed = get_response(&get_query(), false);
+            spoofed.header.id = spoofed.header.id.wrapping_add(1);
+            for response in [spoofed, get_response(&get_query(), true)] {
+                let (mut stream, _) = server.accept().await.unwrap();
+                let mut buf = ByteBuf::new_empty(None);
+                read_tcp_message(&mut stream, &mut buf).await.unwrap();
+                write_tcp_message(&mut stream, &encode_query(&response).unwrap())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let error = client.query_tcp(&query).await.expect_err("should have failed");
+        assert_eq!(error.to_string(), "TCP: response doesn't match the query");
+        let error = client.query_tcp(&query).await.expect_err("should have failed");
+        assert_eq!(error.to_string(), "TCP: response is truncated");
+        server_task.await.unwrap();
+    }
+}
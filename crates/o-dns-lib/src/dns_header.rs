@@ -45,8 +45,10 @@ pub enum ResponseCode {
     NotImplemented,
     /// Server refuses to complete the specified operation
     Refused,
-    // 6-15 codes
-    Unknown,
+    /// Server isn't authoritative for the zone or the request isn't authorized (RFC8945)
+    NotAuth,
+    /// 6-8 and 10-15 codes, kept as received
+    Unknown(#[cfg_attr(test, proptest(strategy = "proptest::prop_oneof![6u8..=8, 10u8..=15]"))] u8),
 }
 
 impl From<u8> for ResponseCode {
@@ -58,7 +60,23 @@ impl From<u8> for ResponseCode {
             3 => ResponseCode::NameError,
             4 => ResponseCode::NotImplemented,
             5 => ResponseCode::Refused,
-            _ => ResponseCode::Unknown,
+            9 => ResponseCode::NotAuth,
+            _ => ResponseCode::Unknown(value),
+        }
+    }
+}
+
+impl From<ResponseCode> for u8 {
+    fn from(val: ResponseCode) -> Self {
+        match val {
+            ResponseCode::Success => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NameError => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::NotAuth => 9,
+            ResponseCode::Unknown(rcode) => rcode,
         }
     }
 }
@@ -110,12 +128,12 @@ impl DnsHeader {
             | (self.z[0] as u8) << 6
             | (self.z[1] as u8) << 5
             | (self.z[2] as u8) << 4
-            | self.response_code as u8;
+            | u8::from(self.response_code) & 0xf;
         (first_byte as u16) << 8 | (second_byte as u16)
     }
 }
 
-impl FromBuf for DnsHeader {
+impl FromBuf<'_> for DnsHeader {
     fn from_buf(buf: &mut ByteBuf) -> anyhow::Result<Self> {
         let id = buf.read_u16().context("id is missing")?;
         let flags = buf.read_u16().context("flags are missing")?;
@@ -180,7 +198,7 @@ impl EncodeToBuf for DnsHeader {
     fn encode_to_buf_with_cache<'cache, 'r: 'cache>(
         &'r self,
         buf: &mut ByteBuf,
-        _label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        _label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
         max_size: Option<usize>,
     ) -> anyhow::Result<usize> {
         let encoded_size = self.get_encoded_size(None);
@@ -201,7 +219,7 @@ impl EncodeToBuf for DnsHeader {
 }
 
 impl EncodedSize for DnsHeader {
-    fn get_encoded_size(&self, _: Option<&HashMap<&str, usize>>) -> usize {
+    fn get_encoded_size(&self, _: Option<&HashMap<&[u8], usize>>) -> usize {
         2 /* ID */ + 2 /* flags */ + 2 /* question count */
             + 2 /* answer count */ + 2 /* authority count */ + 2 /* additional count */
     }
@@ -235,6 +253,19 @@ mod tests {
         assert_eq!(header.additional_rr_count, 9);
     }
 
+    #[test]
+    fn dns_header_keeps_unknown_response_code() {
+        // NOTZONE isn't mapped to a variant, so it must be kept as received
+        let stub_header = &mut [0x0, 0x1, 0x81, 0x8a, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0];
+        let header = DnsHeader::from_buf(&mut ByteBuf::new(stub_header)).expect("shouldn't have failed");
+        assert_eq!(header.response_code, ResponseCode::Unknown(10));
+        assert_eq!(header.get_flags(), 0x818a);
+        assert_eq!(
+            header.to_string().lines().next(),
+            Some(";; ->>HEADER<<- opcode: QUERY, status: RESERVED10, id: 1")
+        );
+    }
+
     proptest! {
         #[test]
         fn dns_header_roundtrip(dns_header: DnsHeader) {
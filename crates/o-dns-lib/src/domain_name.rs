@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::presentation::{parse_labels, write_name};
+use crate::DnsError;
+
+/// Domain name stored in the uncompressed wire format, so that labels can contain arbitrary bytes.
+///
+/// Comparison and hashing are case-insensitive as defined in RFC4343, while the original case is preserved.
+#[derive(Clone)]
+pub struct DomainName<'a> {
+    /// Length-prefixed labels followed by the null byte
+    wire: Cow<'a, [u8]>,
+}
+
+impl<'a> DomainName<'a> {
+    pub const MAX_LABEL_LENGTH: usize = 63;
+    /// Includes length bytes and the null byte
+    pub const MAX_LENGTH: usize = 255;
+
+    pub fn root() -> DomainName<'static> {
+        DomainName {
+            wire: Cow::Borrowed(&[0]),
+        }
+    }
+
+    /// Creates a domain name from the labels, where the root label is implied
+    pub fn from_labels<'l>(labels: impl IntoIterator<Item = &'l [u8]>) -> anyhow::Result<DomainName<'static>> {
+        let mut wire = Vec::new();
+        for label in labels {
+            if label.is_empty() {
+                anyhow::bail!("empty label");
+            }
+            if label.len() > Self::MAX_LABEL_LENGTH {
+                return Err(DnsError::LabelTooLong(label.len()).into());
+            }
+            wire.push(label.len() as u8);
+            wire.extend_from_slice(label);
+        }
+        wire.push(0);
+
+        if wire.len() > Self::MAX_LENGTH {
+            return Err(DnsError::NameTooLong(wire.len()).into());
+        }
+
+        Ok(DomainName { wire: Cow::Owned(wire) })
+    }
+
+    /// Wraps a name in the uncompressed wire format, which must already be validated
+    pub(crate) fn from_wire_unchecked(wire: Cow<'a, [u8]>) -> DomainName<'a> {
+        DomainName { wire }
+    }
+
+    /// Returns the name in the uncompressed wire format
+    pub fn as_wire(&self) -> &[u8] {
+        &self.wire
+    }
+
+    /// Iterates over the labels, excluding the root label
+    pub fn labels(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let mut remaining = self.wire.as_ref();
+        std::iter::from_fn(move || {
+            let (&length, rest) = remaining.split_first()?;
+            if length == 0 {
+                return None;
+            }
+            let (label, rest) = rest.split_at(length as usize);
+            remaining = rest;
+            Some(label)
+        })
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.wire.len() == 1
+    }
+
+    /// Returns the name without its leftmost label, or `None` for the root name
+    pub fn parent(&self) -> Option<DomainName<'_>> {
+        let length = *self.wire.first()? as usize;
+        if length == 0 {
+            return None;
+        }
+        Some(DomainName {
+            wire: Cow::Borrowed(&self.wire[1 + length..]),
+        })
+    }
+
+    /// Returns `true` if the name is equal to `other` or is below it
+    pub fn is_subdomain_of(&self, other: &DomainName<'_>) -> bool {
+        let mut pos = 0;
+        loop {
+            if self.wire[pos..].eq_ignore_ascii_case(&other.wire) {
+                return true;
+            }
+            if self.wire[pos] == 0 {
+                return false;
+            }
+            pos += 1 + self.wire[pos] as usize;
+        }
+    }
+
+    pub fn to_ascii_lowercase(&self) -> DomainName<'static> {
+        DomainName {
+            wire: Cow::Owned(self.wire.to_ascii_lowercase()),
+        }
+    }
+
+    pub fn into_owned(self) -> DomainName<'static> {
+        DomainName {
+            wire: Cow::Owned(self.wire.into_owned()),
+        }
+    }
+}
+
+impl Default for DomainName<'_> {
+    fn default() -> Self {
+        DomainName::root()
+    }
+}
+
+impl PartialEq for DomainName<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        // Length bytes are always lower than 64, so they aren't affected by the ASCII case folding
+        self.wire.eq_ignore_ascii_case(&other.wire)
+    }
+}
+
+impl Eq for DomainName<'_> {}
+
+impl Hash for DomainName<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.wire
+            .iter()
+            .for_each(|byte| state.write_u8(byte.to_ascii_lowercase()));
+    }
+}
+
+/// Formats the name in the presentation format without the trailing dot, except for the root name
+impl fmt::Display for DomainName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, self)
+    }
+}
+
+impl fmt::Debug for DomainName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// Parses the name in the presentation format, where the trailing dot is optional
+impl FromStr for DomainName<'static> {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (labels, _) = parse_labels(text)?;
+        DomainName::from_labels(labels.iter().map(Vec::as_slice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn case_insensitive_comparison() {
+        let name: DomainName = "WWW.Example.COM".parse().expect("shouldn't have failed");
+        let lowercase_name: DomainName = "www.example.com.".parse().expect("shouldn't have failed");
+        assert_eq!(name, lowercase_name);
+        assert_eq!(HashSet::from([name.clone()]).get(&lowercase_name), Some(&name));
+        // Original case should be preserved
+        assert_eq!(name.to_string(), "WWW.Example.COM");
+        assert!(name.is_subdomain_of(&"example.com".parse().unwrap()));
+        assert!(!name.is_subdomain_of(&"ample.com".parse().unwrap()));
+    }
+
+    #[test]
+    fn escaped_labels() {
+        let name: DomainName = "a\\.b.\\000\\255.example".parse().expect("shouldn't have failed");
+        assert_eq!(
+            name.labels().collect::<Vec<_>>(),
+            [b"a.b".as_slice(), &[0, 255], b"example"]
+        );
+        assert_eq!(name.to_string(), "a\\.b.\\000\\255.example");
+        assert_eq!(name.label_count(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "name is too long (257)")]
+    fn name_length_limit() {
+        let label = [b'a'; 63];
+        DomainName::from_labels([label.as_slice(); 4]).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "label is too long (64)")]
+    fn label_length_limit() {
+        DomainName::from_labels([[b'a'; 64].as_slice()]).unwrap();
+    }
+}
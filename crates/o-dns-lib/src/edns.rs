@@ -0,0 +1,382 @@
+use std::borrow::Cow;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::Context;
+
+use crate::utils::{cow_bytes_into_str_lossy, get_u16_length};
+use crate::ByteBuf;
+
+/// A single EDNS(0) option of the OPT RR as defined in RFC6891 (section 6.1.2)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EdnsOption<'a> {
+    /// Name Server Identifier (RFC5001), empty in queries
+    Nsid { nsid: Cow<'a, [u8]> },
+    /// Client Subnet (RFC7871)
+    ClientSubnet {
+        source_prefix_length: u8,
+        /// Always 0 in queries
+        scope_prefix_length: u8,
+        /// All bits after `source_prefix_length` must be zeroed
+        address: IpAddr,
+    },
+    /// DNS Cookie (RFC7873)
+    Cookie {
+        client_cookie: [u8; 8],
+        /// 8 to 32 bytes long, missing if the client doesn't know the server cookie yet
+        server_cookie: Option<Cow<'a, [u8]>>,
+    },
+    /// edns-tcp-keepalive (RFC7828)
+    TcpKeepalive {
+        /// Idle timeout in units of 100 milliseconds, missing in queries
+        timeout: Option<u16>,
+    },
+    /// Padding (RFC7830), which is always encoded as zeroes
+    Padding { length: u16 },
+    /// Extended DNS Error (RFC8914)
+    ExtendedDnsError { info_code: u16, extra_text: Cow<'a, str> },
+    /// Any other option that we don't handle
+    Unknown { code: u16, data: Cow<'a, [u8]> },
+}
+
+impl EdnsOption<'_> {
+    pub const NSID: u16 = 3;
+    pub const CLIENT_SUBNET: u16 = 8;
+    pub const COOKIE: u16 = 10;
+    pub const TCP_KEEPALIVE: u16 = 11;
+    pub const PADDING: u16 = 12;
+    pub const EXTENDED_DNS_ERROR: u16 = 15;
+
+    pub fn get_code(&self) -> u16 {
+        match self {
+            EdnsOption::Nsid { .. } => Self::NSID,
+            EdnsOption::ClientSubnet { .. } => Self::CLIENT_SUBNET,
+            EdnsOption::Cookie { .. } => Self::COOKIE,
+            EdnsOption::TcpKeepalive { .. } => Self::TCP_KEEPALIVE,
+            EdnsOption::Padding { .. } => Self::PADDING,
+            EdnsOption::ExtendedDnsError { .. } => Self::EXTENDED_DNS_ERROR,
+            EdnsOption::Unknown { code, .. } => *code,
+        }
+    }
+
+    pub fn into_owned(self) -> EdnsOption<'static> {
+        match self {
+            EdnsOption::Nsid { nsid } => EdnsOption::Nsid {
+                nsid: Cow::Owned(nsid.into_owned()),
+            },
+            EdnsOption::ClientSubnet {
+                source_prefix_length,
+                scope_prefix_length,
+                address,
+            } => EdnsOption::ClientSubnet {
+                source_prefix_length,
+                scope_prefix_length,
+                address,
+            },
+            EdnsOption::Cookie {
+                client_cookie,
+                server_cookie,
+            } => EdnsOption::Cookie {
+                client_cookie,
+                server_cookie: server_cookie.map(|cookie| Cow::Owned(cookie.into_owned())),
+            },
+            EdnsOption::TcpKeepalive { timeout } => EdnsOption::TcpKeepalive { timeout },
+            EdnsOption::Padding { length } => EdnsOption::Padding { length },
+            EdnsOption::ExtendedDnsError { info_code, extra_text } => EdnsOption::ExtendedDnsError {
+                info_code,
+                extra_text: Cow::Owned(extra_text.into_owned()),
+            },
+            EdnsOption::Unknown { code, data } => EdnsOption::Unknown {
+                code,
+                data: Cow::Owned(data.into_owned()),
+            },
+        }
+    }
+
+    fn get_data_length(&self) -> usize {
+        match self {
+            EdnsOption::Nsid { nsid } => nsid.len(),
+            EdnsOption::ClientSubnet {
+                source_prefix_length, ..
+            } => {
+                2 /* FAMILY */ + 1 /* SOURCE PREFIX-LENGTH */ + 1 /* SCOPE PREFIX-LENGTH */
+                + get_client_subnet_address_length(*source_prefix_length)
+            }
+            EdnsOption::Cookie { server_cookie, .. } => 8 + server_cookie.as_ref().map_or(0, |cookie| cookie.len()),
+            EdnsOption::TcpKeepalive { timeout } => timeout.map_or(0, |_| 2),
+            EdnsOption::Padding { length } => *length as usize,
+            EdnsOption::ExtendedDnsError { extra_text, .. } => 2 /* INFO-CODE */ + extra_text.len(),
+            EdnsOption::Unknown { data, .. } => data.len(),
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            EdnsOption::ClientSubnet {
+                source_prefix_length,
+                scope_prefix_length,
+                address,
+            } => {
+                let (max_prefix_length, octets) = match address {
+                    IpAddr::V4(address) => (32, address.octets().to_vec()),
+                    IpAddr::V6(address) => (128, address.octets().to_vec()),
+                };
+                if *source_prefix_length > max_prefix_length {
+                    anyhow::bail!("unexpected SOURCE PREFIX-LENGTH {}", source_prefix_length);
+                }
+                if *scope_prefix_length > max_prefix_length {
+                    anyhow::bail!("unexpected SCOPE PREFIX-LENGTH {}", scope_prefix_length);
+                }
+                // RFC7871: ADDRESS must be truncated to SOURCE PREFIX-LENGTH bits
+                let prefix = u128::from_be_bytes({
+                    let mut bytes = [0; 16];
+                    bytes[..octets.len()].copy_from_slice(&octets);
+                    bytes
+                });
+                if prefix.checked_shl(*source_prefix_length as u32).unwrap_or(0) != 0 {
+                    anyhow::bail!("ADDRESS has bits set after SOURCE PREFIX-LENGTH");
+                }
+            }
+            EdnsOption::Cookie {
+                server_cookie: Some(server_cookie),
+                ..
+            } if !(8..=32).contains(&server_cookie.len()) => {
+                anyhow::bail!("unexpected server cookie length {}", server_cookie.len());
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads EDNS options that take exactly `length` bytes
+pub(crate) fn read_edns_options<'a>(buf: &mut ByteBuf<'a>, length: usize) -> anyhow::Result<Vec<EdnsOption<'a>>> {
+    let mut options = Vec::new();
+    let mut remaining_length = length;
+    while remaining_length != 0 {
+        let code = buf.read_u16().with_context(|| {
+            format!(
+                "EDNS options: option code is missing at RDLENGTH offset {}",
+                length - remaining_length
+            )
+        })?;
+        let option_length = buf
+            .read_u16()
+            .with_context(|| format!("EDNS options: option length is missing for option {}", code))?;
+        remaining_length = remaining_length
+            .checked_sub(4 + option_length as usize)
+            .with_context(|| format!("EDNS options: option {} exceeds RDLENGTH", code))?;
+        let data = buf.read_cow_bytes(option_length as usize).with_context(|| {
+            format!(
+                "EDNS options: option data of length {} is missing for option {}",
+                option_length, code
+            )
+        })?;
+
+        // An option with invalid contents shouldn't make the whole message unreadable
+        let option = parse_edns_option(code, data.clone()).unwrap_or(EdnsOption::Unknown { code, data });
+        options.push(option);
+    }
+
+    Ok(options)
+}
+
+fn parse_edns_option(code: u16, data: Cow<'_, [u8]>) -> anyhow::Result<EdnsOption<'_>> {
+    let option = match code {
+        EdnsOption::NSID => EdnsOption::Nsid { nsid: data },
+        EdnsOption::CLIENT_SUBNET => {
+            let mut data_buf = ByteBuf::new(&data);
+            let family = data_buf.read_u16().context("FAMILY is missing")?;
+            let source_prefix_length = data_buf.read_u8().context("SOURCE PREFIX-LENGTH is missing")?;
+            let scope_prefix_length = data_buf.read_u8().context("SCOPE PREFIX-LENGTH is missing")?;
+            let address_length = data.len() - data_buf.get_pos();
+            if address_length != get_client_subnet_address_length(source_prefix_length) {
+                anyhow::bail!(
+                    "ADDRESS length {} doesn't match SOURCE PREFIX-LENGTH {}",
+                    address_length,
+                    source_prefix_length
+                );
+            }
+            let address_raw = data_buf.read_bytes(address_length).context("ADDRESS is missing")?;
+            let address = match family {
+                1 if address_length <= 4 => {
+                    let mut octets = [0; 4];
+                    octets[..address_length].copy_from_slice(address_raw);
+                    IpAddr::V4(Ipv4Addr::from(octets))
+                }
+                2 if address_length <= 16 => {
+                    let mut octets = [0; 16];
+                    octets[..address_length].copy_from_slice(address_raw);
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                }
+                1 | 2 => anyhow::bail!("unexpected SOURCE PREFIX-LENGTH {}", source_prefix_length),
+                family => anyhow::bail!("unsupported FAMILY {}", family),
+            };
+            EdnsOption::ClientSubnet {
+                source_prefix_length,
+                scope_prefix_length,
+                address,
+            }
+        }
+        EdnsOption::COOKIE => {
+            if data.len() != 8 && !(16..=40).contains(&data.len()) {
+                anyhow::bail!("unexpected cookie length {}", data.len());
+            }
+            let data_length = data.len();
+            let mut data_buf = ByteBuf::new_from_cow(data);
+            let client_cookie = data_buf.read_bytes(8)?.try_into().unwrap();
+            let server_cookie = data_buf.read_cow_bytes(data_length - 8)?;
+            EdnsOption::Cookie {
+                client_cookie,
+                server_cookie: (!server_cookie.is_empty()).then_some(server_cookie),
+            }
+        }
+        EdnsOption::TCP_KEEPALIVE => {
+            let timeout = match data.as_ref() {
+                [] => None,
+                [msb, lsb] => Some(u16::from_be_bytes([*msb, *lsb])),
+                _ => anyhow::bail!("unexpected edns-tcp-keepalive length {}", data.len()),
+            };
+            EdnsOption::TcpKeepalive { timeout }
+        }
+        // RFC7830: padding octets must be ignored by the receiver
+        EdnsOption::PADDING => EdnsOption::Padding {
+            length: data.len() as u16,
+        },
+        EdnsOption::EXTENDED_DNS_ERROR => {
+            let data_length = data.len();
+            let mut data_buf = ByteBuf::new_from_cow(data);
+            let info_code = data_buf.read_u16().context("INFO-CODE is missing")?;
+            let extra_text = data_buf.read_cow_bytes(data_length - 2)?;
+            EdnsOption::ExtendedDnsError {
+                info_code,
+                // RFC8914: EXTRA-TEXT should be UTF-8, but it's only meant for humans anyway
+                extra_text: cow_bytes_into_str_lossy(extra_text),
+            }
+        }
+        code => EdnsOption::Unknown { code, data },
+    };
+    option.validate()?;
+
+    Ok(option)
+}
+
+pub(crate) fn write_edns_options(buf: &mut ByteBuf, options: &[EdnsOption<'_>]) -> anyhow::Result<usize> {
+    let mut written = 0;
+    for option in options {
+        let code = option.get_code();
+        option
+            .validate()
+            .with_context(|| format!("EDNS options: invalid option {}", code))?;
+
+        let data_length = option.get_data_length();
+        buf.write_u16(code)
+            .with_context(|| format!("EDNS options: writing option code {}", code))?;
+        buf.write_u16(get_u16_length(data_length)?)
+            .with_context(|| format!("EDNS options: writing option length for option {}", code))?;
+        match option {
+            EdnsOption::Nsid { nsid } => buf.write_bytes(nsid, None)?,
+            EdnsOption::ClientSubnet {
+                source_prefix_length,
+                scope_prefix_length,
+                address,
+            } => {
+                let (family, octets) = match address {
+                    IpAddr::V4(address) => (1, address.octets().to_vec()),
+                    IpAddr::V6(address) => (2, address.octets().to_vec()),
+                };
+                buf.write_u16(family)?;
+                buf.write_u8(*source_prefix_length);
+                buf.write_u8(*scope_prefix_length);
+                buf.write_bytes(&octets[..get_client_subnet_address_length(*source_prefix_length)], None)?;
+            }
+            EdnsOption::Cookie {
+                client_cookie,
+                server_cookie,
+            } => {
+                buf.write_bytes(client_cookie, None)?;
+                if let Some(server_cookie) = server_cookie {
+                    buf.write_bytes(server_cookie, None)?;
+                }
+            }
+            EdnsOption::TcpKeepalive { timeout } => {
+                if let Some(timeout) = timeout {
+                    buf.write_u16(*timeout)?;
+                }
+            }
+            EdnsOption::Padding { length } => buf.write_bytes(&vec![0; *length as usize], None)?,
+            EdnsOption::ExtendedDnsError { info_code, extra_text } => {
+                buf.write_u16(*info_code)?;
+                buf.write_bytes(extra_text.as_bytes(), None)?;
+            }
+            EdnsOption::Unknown { data, .. } => buf.write_bytes(data, None)?,
+        }
+        written += 4 + data_length;
+    }
+
+    Ok(written)
+}
+
+pub(crate) fn get_edns_options_size(options: &[EdnsOption<'_>]) -> usize {
+    options
+        .iter()
+        .map(|option| 2 /* option code */ + 2 /* option length */ + option.get_data_length())
+        .sum()
+}
+
+fn get_client_subnet_address_length(source_prefix_length: u8) -> usize {
+    (source_prefix_length as usize).div_ceil(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_client_subnet() {
+        let data = &[
+            /* OPTION-CODE */ 0x0, 0x8, /* OPTION-LENGTH */ 0x0, 0x7, /* FAMILY */ 0x0, 0x1,
+            /* SOURCE PREFIX-LENGTH */ 0x14, /* SCOPE PREFIX-LENGTH */ 0x0, /* ADDRESS */ 0xc0, 0xa8,
+            0x10,
+        ];
+        let mut buf = ByteBuf::new(data);
+        let options = read_edns_options(&mut buf, data.len()).expect("shouldn't have failed");
+        assert_eq!(
+            options,
+            [EdnsOption::ClientSubnet {
+                source_prefix_length: 20,
+                scope_prefix_length: 0,
+                address: IpAddr::V4(Ipv4Addr::new(192, 168, 16, 0)),
+            }]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ADDRESS has bits set after SOURCE PREFIX-LENGTH")]
+    fn write_client_subnet_with_host_bits() {
+        let options = [EdnsOption::ClientSubnet {
+            source_prefix_length: 24,
+            scope_prefix_length: 0,
+            address: IpAddr::V4(Ipv4Addr::new(192, 168, 16, 1)),
+        }];
+        let mut buf = ByteBuf::new_empty(None);
+        write_edns_options(&mut buf, &options).unwrap();
+    }
+
+    #[test]
+    fn read_cookie_with_short_server_cookie() {
+        let data = &[
+            /* OPTION-CODE */ 0x0, 0xa, /* OPTION-LENGTH */ 0x0, 0xc, /* CLIENT COOKIE */ 0x1, 0x2, 0x3,
+            0x4, 0x5, 0x6, 0x7, 0x8, /* SERVER COOKIE */ 0x1, 0x2, 0x3, 0x4,
+        ];
+        let mut buf = ByteBuf::new(data);
+        let options = read_edns_options(&mut buf, data.len()).expect("shouldn't have failed");
+        assert_eq!(
+            options,
+            [EdnsOption::Unknown {
+                code: EdnsOption::COOKIE,
+                data: data[4..].into(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+
+/// Error that explains why a DNS message can't be decoded, encoded or handled.
+///
+/// Functions that return `anyhow::Result` wrap it, so it can be matched after `anyhow::Error::downcast_ref`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DnsError {
+    /// Message ends before `length` bytes at `pos` could be read
+    Truncated { pos: usize, length: usize },
+    /// Compression pointer at `pos` doesn't point strictly backwards (see RFC9267)
+    BadPointer { pos: usize, offset: usize },
+    /// Too many compression pointers were followed
+    TooManyPointers,
+    /// Label is longer than 63 bytes
+    LabelTooLong(usize),
+    /// Name is longer than 255 bytes in the uncompressed wire format
+    NameTooLong(usize),
+    /// EDNS version other than 0 (RFC6891)
+    BadEdnsVersion(u8),
+    /// EDNS Client Subnet option of a query has invalid contents (RFC7871, section 7.1.1)
+    BadClientSubnet,
+    /// Question class other than IN, CH, HS, NONE or ANY
+    UnknownClass(u16),
+    /// TSIG RR isn't the last RR of the message
+    MisplacedTsig,
+    /// TSIG key or algorithm isn't known
+    BadTsigKey,
+    /// TSIG MAC doesn't match the message
+    BadTsigSignature,
+    /// TSIG time signed is outside of the fudge window
+    BadTsigTime { time_signed: u64, now: u64 },
+    /// Section has more entries than its 16-bit count can hold
+    TooManyEntries(usize),
+    /// Data is longer than its 16-bit length field can hold, e.g. RDATA
+    LengthOverflow(usize),
+    /// Character-string is longer than 255 bytes
+    CharacterStringTooLong(usize),
+}
+
+impl DnsError {
+    /// Returns the error code for the TSIG RR of the response (RFC8945, section 3)
+    pub fn get_tsig_error(&self) -> Option<u16> {
+        match self {
+            DnsError::BadTsigSignature => Some(16),
+            DnsError::BadTsigKey => Some(17),
+            DnsError::BadTsigTime { .. } => Some(18),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::Truncated { pos, length } => {
+                write!(f, "message is truncated: expected {} bytes at byte {}", length, pos)
+            }
+            DnsError::BadPointer { pos, offset } => write!(
+                f,
+                "compression pointer at byte {} doesn't point backwards ({})",
+                pos, offset
+            ),
+            DnsError::TooManyPointers => write!(f, "too many compression pointers"),
+            DnsError::LabelTooLong(length) => write!(f, "label is too long ({})", length),
+            DnsError::NameTooLong(length) => write!(f, "name is too long ({})", length),
+            DnsError::BadEdnsVersion(version) => write!(f, "unsupported EDNS version {}", version),
+            DnsError::BadClientSubnet => write!(f, "malformed EDNS Client Subnet option"),
+            DnsError::UnknownClass(class) => write!(f, "unknown class {}", class),
+            DnsError::MisplacedTsig => write!(f, "TSIG RR must be the last RR"),
+            DnsError::BadTsigKey => write!(f, "unknown TSIG key"),
+            DnsError::BadTsigSignature => write!(f, "TSIG MAC doesn't match"),
+            DnsError::BadTsigTime { time_signed, now } => write!(
+                f,
+                "TSIG time signed {} is too far from the current time {}",
+                time_signed, now
+            ),
+            DnsError::TooManyEntries(count) => write!(f, "section has too many entries ({})", count),
+            DnsError::LengthOverflow(length) => write!(f, "data is too long for a 16-bit length ({})", length),
+            DnsError::CharacterStringTooLong(length) => write!(f, "character-string is too long ({})", length),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
@@ -0,0 +1,395 @@
+//! JSON representation of DNS messages as defined in RFC8427, where RDATA is kept in the presentation format
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use data_encoding::HEXUPPER;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::presentation::{Class, Name};
+use crate::zone::parse_rdata_text;
+use crate::{
+    ByteBuf, DnsHeader, DnsPacket, DomainName, EncodeToBuf, QueryType, Question, ResourceData, ResourceRecord,
+};
+
+#[derive(Serialize, Deserialize)]
+struct JsonMessage {
+    #[serde(rename = "ID")]
+    id: u16,
+    #[serde(rename = "QR")]
+    is_response: bool,
+    #[serde(rename = "Opcode")]
+    opcode: u8,
+    #[serde(rename = "AA")]
+    is_authoritative: bool,
+    #[serde(rename = "TC")]
+    truncation: bool,
+    #[serde(rename = "RD")]
+    recursion_desired: bool,
+    #[serde(rename = "RA")]
+    recursion_available: bool,
+    #[serde(rename = "AD")]
+    authentic_data: bool,
+    #[serde(rename = "CD")]
+    checking_disabled: bool,
+    #[serde(rename = "RCODE")]
+    response_code: u8,
+    // Counts are derived from the sections when the packet is encoded, so they are ignored when deserializing
+    #[serde(rename = "QDCOUNT", default, skip_deserializing)]
+    question_count: usize,
+    #[serde(rename = "ANCOUNT", default, skip_deserializing)]
+    answer_rr_count: usize,
+    #[serde(rename = "NSCOUNT", default, skip_deserializing)]
+    authority_rr_count: usize,
+    #[serde(rename = "ARCOUNT", default, skip_deserializing)]
+    additional_rr_count: usize,
+    #[serde(rename = "questionRRs", default)]
+    questions: Vec<JsonQuestion>,
+    #[serde(rename = "answerRRs", default)]
+    answers: Vec<JsonResourceRecord>,
+    #[serde(rename = "authorityRRs", default)]
+    authorities: Vec<JsonResourceRecord>,
+    #[serde(rename = "additionalRRs", default)]
+    additionals: Vec<JsonResourceRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonQuestion {
+    #[serde(rename = "NAME")]
+    name: String,
+    #[serde(rename = "TYPE")]
+    query_type: u16,
+    #[serde(rename = "TYPEname", default, skip_deserializing)]
+    type_name: String,
+    #[serde(rename = "CLASS")]
+    class: u16,
+    #[serde(rename = "CLASSname", default, skip_deserializing)]
+    class_name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonResourceRecord {
+    #[serde(rename = "NAME")]
+    name: String,
+    #[serde(rename = "TYPE")]
+    query_type: u16,
+    #[serde(rename = "TYPEname", default, skip_deserializing)]
+    type_name: String,
+    #[serde(rename = "CLASS")]
+    class: u16,
+    #[serde(rename = "CLASSname", default, skip_deserializing)]
+    class_name: String,
+    #[serde(rename = "TTL")]
+    ttl: u32,
+    /// Used for types without a presentation format
+    #[serde(rename = "RDATAHEX", default, skip_serializing_if = "Option::is_none")]
+    rdata_hex: Option<String>,
+    /// `rdata<TYPEname>` member with RDATA in the presentation format
+    #[serde(flatten)]
+    rdata: BTreeMap<String, String>,
+}
+
+/// Returns `false` for types whose RDATA is represented as `RDATAHEX`
+fn has_presentation_format(query_type: QueryType) -> bool {
+    match query_type {
+        QueryType::UNKNOWN(_) | QueryType::TSIG => false,
+        #[cfg(feature = "edns")]
+        QueryType::OPT => false,
+        _ => true,
+    }
+}
+
+fn parse_name(text: &str) -> anyhow::Result<DomainName<'static>> {
+    text.parse().with_context(|| format!("invalid name '{}'", text))
+}
+
+impl From<&Question<'_>> for JsonQuestion {
+    fn from(question: &Question<'_>) -> Self {
+        JsonQuestion {
+            name: Name(&question.qname).to_string(),
+            query_type: question.query_type.into(),
+            type_name: question.query_type.to_string(),
+            class: question.qclass,
+            class_name: Class(question.qclass).to_string(),
+        }
+    }
+}
+
+impl TryFrom<JsonQuestion> for Question<'static> {
+    type Error = anyhow::Error;
+
+    fn try_from(question: JsonQuestion) -> Result<Self, Self::Error> {
+        Ok(Question {
+            qname: parse_name(&question.name)?,
+            query_type: question.query_type.into(),
+            qclass: question.class,
+        })
+    }
+}
+
+impl TryFrom<&ResourceRecord<'_>> for JsonResourceRecord {
+    type Error = anyhow::Error;
+
+    fn try_from(rr: &ResourceRecord<'_>) -> Result<Self, Self::Error> {
+        let query_type = rr.resource_data.get_query_type();
+        let mut rdata = BTreeMap::new();
+        let mut rdata_hex = None;
+        if has_presentation_format(query_type) {
+            rdata.insert(format!("rdata{}", query_type), rr.resource_data.to_string());
+        } else {
+            let mut buf = ByteBuf::new_empty(None);
+            rr.resource_data
+                .encode_to_buf(&mut buf, None)
+                .context("error while encoding RDATA")?;
+            // Skip RDLENGTH
+            rdata_hex = Some(HEXUPPER.encode(&buf[2..]));
+        }
+
+        Ok(JsonResourceRecord {
+            name: Name(&rr.name).to_string(),
+            query_type: query_type.into(),
+            type_name: query_type.to_string(),
+            class: rr.class,
+            class_name: Class(rr.class).to_string(),
+            ttl: rr.ttl,
+            rdata_hex,
+            rdata,
+        })
+    }
+}
+
+impl TryFrom<JsonResourceRecord> for ResourceRecord<'static> {
+    type Error = anyhow::Error;
+
+    fn try_from(mut rr: JsonResourceRecord) -> Result<Self, Self::Error> {
+        let query_type = QueryType::from(rr.query_type);
+        let name = parse_name(&rr.name)?;
+
+        let resource_data = match (rr.rdata.remove(&format!("rdata{}", query_type)), rr.rdata_hex) {
+            (Some(text), _) if has_presentation_format(query_type) => parse_rdata_text(query_type, &text)
+                .with_context(|| format!("invalid {} RDATA '{}'", query_type, text))?,
+            (_, Some(hex)) => {
+                let rdata = HEXUPPER
+                    .decode(hex.to_ascii_uppercase().as_bytes())
+                    .context("RDATAHEX is not a valid hex string")?;
+                let rd_length = u16::try_from(rdata.len()).context("RDATAHEX is too long")?;
+                let mut buf = ByteBuf::new_empty(Some(2 + rdata.len()));
+                buf.write_u16(rd_length)?;
+                buf.write_bytes(&rdata, None)?;
+                ResourceData::from_buf_with_type(&mut buf, query_type)
+                    .with_context(|| format!("invalid {} RDATAHEX", query_type))?
+                    .into_owned()
+            }
+            _ => anyhow::bail!("{} record: RDATA is missing", query_type),
+        };
+
+        Ok(ResourceRecord {
+            name,
+            class: rr.class,
+            ttl: rr.ttl,
+            resource_data,
+        })
+    }
+}
+
+impl TryFrom<&DnsPacket<'_>> for JsonMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(packet: &DnsPacket<'_>) -> Result<Self, Self::Error> {
+        let convert_section = |section: &[ResourceRecord<'_>]| {
+            section
+                .iter()
+                .map(JsonResourceRecord::try_from)
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let header = &packet.header;
+
+        Ok(JsonMessage {
+            id: header.id,
+            is_response: header.is_response,
+            opcode: header.opcode as u8,
+            is_authoritative: header.is_authoritative,
+            truncation: header.truncation,
+            recursion_desired: header.recursion_desired,
+            recursion_available: header.recursion_available,
+            authentic_data: header.z[1],
+            checking_disabled: header.z[2],
+            response_code: header.response_code.into(),
+            question_count: packet.questions.len(),
+            answer_rr_count: packet.answers.len(),
+            authority_rr_count: packet.authorities.len(),
+            additional_rr_count: packet.additionals.len(),
+            questions: packet.questions.iter().map(JsonQuestion::from).collect(),
+            answers: convert_section(&packet.answers).context("answer section")?,
+            authorities: convert_section(&packet.authorities).context("authority section")?,
+            additionals: convert_section(&packet.additionals).context("additional section")?,
+        })
+    }
+}
+
+impl TryFrom<JsonMessage> for DnsPacket<'static> {
+    type Error = anyhow::Error;
+
+    fn try_from(message: JsonMessage) -> Result<Self, Self::Error> {
+        let mut packet = DnsPacket {
+            header: DnsHeader {
+                id: message.id,
+                is_response: message.is_response,
+                opcode: message.opcode.into(),
+                is_authoritative: message.is_authoritative,
+                truncation: message.truncation,
+                recursion_desired: message.recursion_desired,
+                recursion_available: message.recursion_available,
+                z: [false, message.authentic_data, message.checking_disabled],
+                response_code: message.response_code.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        for question in message.questions {
+            packet.add_question(question.try_into().context("question section")?);
+        }
+        for rr in message.answers {
+            packet.add_answer(rr.try_into().context("answer section")?);
+        }
+        for rr in message.authorities {
+            packet.add_authority(rr.try_into().context("authority section")?);
+        }
+        for rr in message.additionals {
+            packet.add_additional(rr.try_into().context("additional section")?);
+        }
+
+        Ok(packet)
+    }
+}
+
+impl Serialize for Question<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonQuestion::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Question<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Question::try_from(JsonQuestion::deserialize(deserializer)?).map_err(|e| D::Error::custom(format!("{:#}", e)))
+    }
+}
+
+impl Serialize for ResourceRecord<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonResourceRecord::try_from(self)
+            .map_err(|e| S::Error::custom(format!("{:#}", e)))?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ResourceRecord<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ResourceRecord::try_from(JsonResourceRecord::deserialize(deserializer)?)
+            .map_err(|e| D::Error::custom(format!("{:#}", e)))
+    }
+}
+
+impl Serialize for DnsPacket<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonMessage::try_from(self)
+            .map_err(|e| S::Error::custom(format!("{:#}", e)))?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DnsPacket<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        DnsPacket::try_from(JsonMessage::deserialize(deserializer)?).map_err(|e| D::Error::custom(format!("{:#}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::test_utils::{arb_question, arb_resource_record};
+
+    #[test]
+    fn dns_packet_json_layout() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 1234;
+        packet.header.is_response = true;
+        packet.header.recursion_desired = true;
+        packet.add_question(Question::new("example.com".parse().unwrap(), QueryType::A, None));
+        packet.add_answer(ResourceRecord::new(
+            "example.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::new(192, 0, 2, 1),
+            },
+            Some(300),
+            None,
+        ));
+        packet.add_answer(ResourceRecord::new(
+            "example.com".parse().unwrap(),
+            ResourceData::UNKNOWN {
+                qtype: 65280,
+                rdata: b"\x01\x02".as_slice().into(),
+            },
+            Some(300),
+            None,
+        ));
+
+        let json = serde_json::to_value(&packet).expect("shouldn't have failed");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "ID": 1234, "QR": true, "Opcode": 0, "AA": false, "TC": false, "RD": true, "RA": false,
+                "AD": false, "CD": false, "RCODE": 0,
+                "QDCOUNT": 1, "ANCOUNT": 2, "NSCOUNT": 0, "ARCOUNT": 0,
+                "questionRRs": [
+                    { "NAME": "example.com.", "TYPE": 1, "TYPEname": "A", "CLASS": 1, "CLASSname": "IN" }
+                ],
+                "answerRRs": [
+                    {
+                        "NAME": "example.com.", "TYPE": 1, "TYPEname": "A", "CLASS": 1, "CLASSname": "IN",
+                        "TTL": 300, "rdataA": "192.0.2.1"
+                    },
+                    {
+                        "NAME": "example.com.", "TYPE": 65280, "TYPEname": "TYPE65280", "CLASS": 1,
+                        "CLASSname": "IN", "TTL": 300, "RDATAHEX": "0102"
+                    }
+                ],
+                "authorityRRs": [],
+                "additionalRRs": []
+            })
+        );
+
+        let roundtripped_packet: DnsPacket = serde_json::from_value(json).expect("shouldn't have failed");
+        assert_eq!(packet, roundtripped_packet);
+    }
+
+    #[test]
+    #[should_panic(expected = "A record: RDATA is missing")]
+    fn resource_record_without_rdata() {
+        serde_json::from_str::<ResourceRecord>(r#"{ "NAME": "example.com.", "TYPE": 1, "CLASS": 1, "TTL": 300 }"#)
+            .unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn question_json_roundtrip(question in arb_question()) {
+            let json = serde_json::to_string(&question).expect("shouldn't have failed");
+            let roundtripped_question: Question = serde_json::from_str(&json).expect("shouldn't have failed");
+            prop_assert_eq!(question, roundtripped_question, "Question JSON roundtrip test failed");
+        }
+
+        #[test]
+        fn resource_record_json_roundtrip(resource_record in arb_resource_record()) {
+            let json = serde_json::to_string(&resource_record).expect("shouldn't have failed");
+            let roundtripped_record: ResourceRecord = serde_json::from_str(&json).expect("shouldn't have failed");
+            prop_assert_eq!(resource_record, roundtripped_record, "ResourceRecord JSON roundtrip test failed");
+        }
+    }
+}
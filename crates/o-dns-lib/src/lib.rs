@@ -2,23 +2,43 @@
 pub(crate) mod test_utils;
 
 mod buf;
+#[cfg(feature = "client")]
+mod client;
 mod dns_header;
+mod domain_name;
+#[cfg(feature = "edns")]
+mod edns;
+mod error;
+#[cfg(feature = "serde")]
+mod json;
+mod presentation;
 mod question;
 mod resource_record;
+mod svcb;
+mod tsig;
+mod type_bitmap;
 mod utils;
+mod zone;
 
-use core::str;
 use std::collections::HashMap;
 
 use anyhow::Context;
 use buf::EncodedSize;
 pub use buf::{ByteBuf, EncodeToBuf, FromBuf};
-use cfg_if::cfg_if;
+#[cfg(feature = "client")]
+pub use client::{read_tcp_message, write_tcp_message, DnsClient};
 pub use dns_header::{DnsHeader, QueryOpcode, ResponseCode};
+pub use domain_name::DomainName;
+#[cfg(feature = "edns")]
+pub use edns::EdnsOption;
+pub use error::DnsError;
 pub use question::{QueryType, Question};
 #[cfg(feature = "edns")]
 pub use resource_record::EdnsData;
 pub use resource_record::{ResourceData, ResourceRecord};
+pub use svcb::SvcParam;
+pub use tsig::{write_unsigned_tsig_error, TsigAlgorithm, TsigKey, TSIG_FUDGE};
+pub use zone::{parse_zone, print_zone};
 
 pub const IN_CLASS: u16 = 1;
 
@@ -38,10 +58,133 @@ impl<'a> DnsPacket<'a> {
     pub fn new() -> Self {
         DnsPacket::default()
     }
+
+    /// Copies all borrowed data, so that the packet no longer references the buffer it was parsed from
+    pub fn into_owned(self) -> DnsPacket<'static> {
+        DnsPacket {
+            header: self.header,
+            #[cfg(feature = "edns")]
+            edns: self.edns,
+            questions: self.questions.into_iter().map(Question::into_owned).collect(),
+            answers: self.answers.into_iter().map(ResourceRecord::into_owned).collect(),
+            authorities: self.authorities.into_iter().map(ResourceRecord::into_owned).collect(),
+            additionals: self.additionals.into_iter().map(ResourceRecord::into_owned).collect(),
+        }
+    }
+
+    pub fn add_question(&mut self, question: Question<'a>) {
+        self.questions.push(question);
+        self.header.question_count = self.header.question_count.saturating_add(1);
+    }
+
+    pub fn add_answer(&mut self, rr: ResourceRecord<'a>) {
+        self.answers.push(rr);
+        self.header.answer_rr_count = self.header.answer_rr_count.saturating_add(1);
+    }
+
+    pub fn add_authority(&mut self, rr: ResourceRecord<'a>) {
+        self.authorities.push(rr);
+        self.header.authority_rr_count = self.header.authority_rr_count.saturating_add(1);
+    }
+
+    /// Adds an additional RR, where an OPT RR replaces the packet's existing one (see [`DnsPacket::set_edns`])
+    pub fn add_additional(&mut self, rr: ResourceRecord<'a>) {
+        #[cfg(feature = "edns")]
+        if rr.resource_data.get_query_type() == QueryType::OPT {
+            self.set_edns(rr);
+            return;
+        }
+        self.additionals.push(rr);
+        self.header.additional_rr_count = self.header.additional_rr_count.saturating_add(1);
+    }
+
+    /// Sets the OPT RR, replacing the existing one if present
+    #[cfg(feature = "edns")]
+    pub fn set_edns(&mut self, opt_rr: ResourceRecord<'a>) {
+        debug_assert_eq!(opt_rr.resource_data.get_query_type(), QueryType::OPT);
+        match self.edns.and_then(|idx| self.additionals.get_mut(idx)) {
+            Some(rr) => *rr = opt_rr,
+            None => {
+                self.edns = Some(self.additionals.len());
+                self.additionals.push(opt_rr);
+                self.header.additional_rr_count = self.header.additional_rr_count.saturating_add(1);
+            }
+        }
+    }
+
+    /// Checks that the query doesn't use an unsupported EDNS version, a malformed EDNS Client Subnet option
+    /// or an unknown question class
+    pub fn validate_query(&self) -> Result<(), DnsError> {
+        #[cfg(feature = "edns")]
+        if let Some(edns_data) = self
+            .edns
+            .and_then(|idx| self.additionals.get(idx))
+            .and_then(ResourceRecord::get_edns_data)
+        {
+            if edns_data.version != 0 {
+                return Err(DnsError::BadEdnsVersion(edns_data.version));
+            }
+            // Options with invalid contents are read as unknown ones
+            if edns_data
+                .options
+                .iter()
+                .any(|option| matches!(option, EdnsOption::Unknown { code, .. } if *code == EdnsOption::CLIENT_SUBNET))
+            {
+                return Err(DnsError::BadClientSubnet);
+            }
+        }
+
+        // IN, CH, HS, NONE (RFC2136) and ANY
+        if let Some(question) = self
+            .questions
+            .iter()
+            .find(|question| !matches!(question.qclass, IN_CLASS | 3 | 4 | 254 | 255))
+        {
+            return Err(DnsError::UnknownClass(question.qclass));
+        }
+
+        // TSIG RR must be the last RR (RFC8945, section 5.1)
+        let is_tsig = |rr: &ResourceRecord| rr.resource_data.get_query_type() == QueryType::TSIG;
+        if self.answers.iter().chain(&self.authorities).any(is_tsig)
+            || self.additionals.iter().rev().skip(1).any(is_tsig)
+        {
+            return Err(DnsError::MisplacedTsig);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the TSIG RR if the packet is signed
+    pub fn get_tsig_rr(&self) -> Option<&ResourceRecord<'a>> {
+        self.additionals
+            .last()
+            .filter(|rr| rr.resource_data.get_query_type() == QueryType::TSIG)
+    }
+
+    /// Returns the lowest TTL of all RRs except OPT, if there are any.
+    ///
+    /// SOA RR in the authority section counts with the lower of its TTL and MINIMUM, as in negative responses (RFC2308)
+    pub fn get_minimum_ttl(&self) -> Option<u32> {
+        let authorities = self.authorities.iter().map(|rr| match rr.resource_data {
+            ResourceData::SOA { minimum, .. } => rr.ttl.min(minimum),
+            _ => rr.ttl,
+        });
+        let additionals = self.additionals.iter();
+        // TTL of the OPT RR holds the extended RCODE and flags instead
+        #[cfg(feature = "edns")]
+        let additionals = additionals.filter(|rr| rr.resource_data.get_query_type() != QueryType::OPT);
+
+        self.answers
+            .iter()
+            .map(|rr| rr.ttl)
+            .chain(authorities)
+            .chain(additionals.map(|rr| rr.ttl))
+            .min()
+    }
 }
 
-impl FromBuf for DnsPacket<'_> {
-    fn from_buf(buf: &mut ByteBuf<'_>) -> anyhow::Result<DnsPacket<'static>> {
+impl<'a> FromBuf<'a> for DnsPacket<'a> {
+    fn from_buf(buf: &mut ByteBuf<'a>) -> anyhow::Result<DnsPacket<'a>> {
         let header = DnsHeader::from_buf(buf).context("header parsing error")?;
 
         let mut questions = Vec::with_capacity(header.question_count as usize);
@@ -95,7 +238,7 @@ impl<'a> EncodeToBuf for DnsPacket<'a> {
     fn encode_to_buf_with_cache<'cache, 'r: 'cache>(
         &'r self,
         buf: &mut ByteBuf,
-        mut label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        mut label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
         max_size: Option<usize>,
     ) -> anyhow::Result<usize> {
         if max_size.is_some_and(|max_size| max_size < 12) {
@@ -114,15 +257,32 @@ impl<'a> EncodeToBuf for DnsPacket<'a> {
             dns_packet_encoded_size += opt_rr_size;
         }
 
-        // Clone the header in order to update RR counts
+        // TSIG RR must stay the last RR (RFC8945, section 5.1), so it's never dropped either
+        let (additionals, tsig_rr) = match self.additionals.split_last() {
+            Some((tsig_rr, additionals)) if tsig_rr.resource_data.get_query_type() == QueryType::TSIG => {
+                let tsig_rr_size = tsig_rr.get_encoded_size(None);
+                if max_size.is_some_and(|max_size| tsig_rr_size + dns_packet_encoded_size > max_size) {
+                    anyhow::bail!("max size is too low: can't fit TSIG RR");
+                }
+                dns_packet_encoded_size += tsig_rr_size;
+                (additionals, Some(tsig_rr))
+            }
+            _ => (self.additionals.as_slice(), None),
+        };
+
+        // Counts are derived from the sections, so that stale header fields can't corrupt the packet
         let mut header = self.header.clone();
+        header.question_count = get_section_count(&self.questions).context("too many questions")?;
+        header.answer_rr_count = get_section_count(&self.answers).context("too many answer RRs")?;
+        header.authority_rr_count = get_section_count(&self.authorities).context("too many authority RRs")?;
+        header.additional_rr_count = get_section_count(&self.additionals).context("too many additional RRs")?;
 
         // Remember header's position in order to update the truncation bit and RR counts
         let dns_header_pos = buf.len();
         // Header's size is already accounted for
-        self.header.encode_to_buf(buf, None).context("writing header")?;
+        header.encode_to_buf(buf, None).context("writing header")?;
 
-        // Track whether we truncated any RRs/questions while encoding
+        // Track whether we truncated any questions or answer/authority RRsets while encoding
         let mut truncation = false;
         self.questions
             .iter()
@@ -145,86 +305,71 @@ impl<'a> EncodeToBuf for DnsPacket<'a> {
             })
             .context("writing questions")?;
 
-        self.answers
-            .iter()
-            .enumerate()
-            .try_for_each(|(idx, answer)| {
-                let encoded_size = answer
-                    .encode_to_buf_with_cache(
-                        buf,
-                        label_cache.as_deref_mut(),
-                        max_size.map(|max_size| max_size - dns_packet_encoded_size),
-                    )
-                    .with_context(|| format!("writing answer RR at idx {}", idx))?;
-                if encoded_size == 0 {
-                    truncation = true;
-                    header.answer_rr_count -= 1;
+        // RFC2181: RRsets are never split, and once an answer or authority RRset is dropped,
+        // the rest of the message is dropped as well (apart from the OPT RR)
+        let sections = [
+            ("answer", &self.answers, &mut header.answer_rr_count),
+            ("authority", &self.authorities, &mut header.authority_rr_count),
+        ];
+        for (section_name, section, count) in sections {
+            for (idx, rrset) in get_rrsets(section).into_iter().enumerate() {
+                let encoded_size = if truncation {
+                    0
                 } else {
-                    dns_packet_encoded_size += encoded_size;
-                }
-                anyhow::Result::<()>::Ok(())
-            })
-            .context("writing answer RRs")?;
-
-        self.authorities
-            .iter()
-            .enumerate()
-            .try_for_each(|(idx, authority)| {
-                let encoded_size = authority
-                    .encode_to_buf_with_cache(
+                    encode_rrset(
+                        &rrset,
                         buf,
                         label_cache.as_deref_mut(),
                         max_size.map(|max_size| max_size - dns_packet_encoded_size),
                     )
-                    .with_context(|| format!("writing authority RR at idx {}", idx))?;
+                    .with_context(|| format!("writing {} RRset at idx {}", section_name, idx))?
+                };
                 if encoded_size == 0 {
                     truncation = true;
-                    header.authority_rr_count -= 1;
+                    *count -= rrset.len() as u16;
                 } else {
                     dns_packet_encoded_size += encoded_size;
                 }
-                anyhow::Result::<()>::Ok(())
-            })
-            .context("writing authority RRs")?;
+            }
+        }
 
-        self.additionals
-            .iter()
-            .enumerate()
-            .try_for_each(|(idx, additional)| {
-                #[cfg(feature = "edns")]
-                let max_size = match additional.resource_data.get_query_type() {
-                    // Already accounted for, so safe to write
-                    QueryType::OPT => None,
-                    // Otherwise process as every other RR type
-                    _ => max_size,
-                };
-                let encoded_size = additional
-                    .encode_to_buf_with_cache(
-                        buf,
-                        label_cache.as_deref_mut(),
-                        max_size.map(|max_size| max_size - dns_packet_encoded_size),
-                    )
-                    .with_context(|| format!("writing additional RR at idx {}", idx))?;
-                cfg_if! {
-                    if #[cfg(feature = "edns")] {
-                        // OPT RR is already accounted for
-                        if additional.resource_data.get_query_type() != QueryType::OPT {
-                            dns_packet_encoded_size += encoded_size;
-                        }
-                    } else {
-                        dns_packet_encoded_size += encoded_size;
-                    }
-                }
-                if encoded_size == 0 {
-                    truncation = true;
-                    header.additional_rr_count -= 1;
-                }
-                anyhow::Result::<()>::Ok(())
-            })
-            .context("writing additional RRs")?;
+        // Additional RRs aren't required, so dropping them doesn't set the truncation bit
+        let mut additionals_dropped = false;
+        for (idx, rrset) in get_rrsets(additionals).into_iter().enumerate() {
+            #[cfg(feature = "edns")]
+            if rrset[0].resource_data.get_query_type() == QueryType::OPT {
+                // Already accounted for, so safe to write
+                encode_rrset(&rrset, buf, label_cache.as_deref_mut(), None).context("writing OPT RR")?;
+                continue;
+            }
+            let encoded_size = if truncation || additionals_dropped {
+                0
+            } else {
+                encode_rrset(
+                    &rrset,
+                    buf,
+                    label_cache.as_deref_mut(),
+                    max_size.map(|max_size| max_size - dns_packet_encoded_size),
+                )
+                .with_context(|| format!("writing additional RRset at idx {}", idx))?
+            };
+            if encoded_size == 0 {
+                additionals_dropped = true;
+                header.additional_rr_count -= rrset.len() as u16;
+            } else {
+                dns_packet_encoded_size += encoded_size;
+            }
+        }
 
-        if truncation {
-            header.truncation = truncation;
+        if let Some(tsig_rr) = tsig_rr {
+            // Already accounted for, so safe to write
+            // Names in TSIG RR aren't compressed
+            tsig_rr.encode_to_buf(buf, None).context("writing TSIG RR")?;
+        }
+
+        if truncation || additionals_dropped {
+            // TC bit of the original header is kept
+            header.truncation |= truncation;
             // Update flags
             buf.set_u16(dns_header_pos + 2, header.get_flags())
                 .context("updating header flags")?;
@@ -251,8 +396,58 @@ impl<'a> EncodeToBuf for DnsPacket<'a> {
     }
 }
 
+/// Groups adjacent RRs into RRsets (RFC2181), so that the order of RRs in the section is kept
+fn get_rrsets<'r, 'a>(section: &'r [ResourceRecord<'a>]) -> Vec<Vec<&'r ResourceRecord<'a>>> {
+    let mut rrsets: Vec<Vec<&ResourceRecord>> = Vec::new();
+    for rr in section {
+        let rrset = rrsets.last_mut().filter(|rrset| {
+            rrset[0].name == rr.name
+                && rrset[0].class == rr.class
+                && rrset[0].resource_data.get_query_type() == rr.resource_data.get_query_type()
+        });
+        match rrset {
+            Some(rrset) => rrset.push(rr),
+            None => rrsets.push(vec![rr]),
+        }
+    }
+    rrsets
+}
+
+/// Encodes either the whole RRset or nothing, in which case 0 is returned
+fn encode_rrset<'cache, 'r: 'cache>(
+    rrset: &[&'r ResourceRecord<'_>],
+    buf: &mut ByteBuf,
+    mut label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
+    max_size: Option<usize>,
+) -> anyhow::Result<usize> {
+    let rrset_pos = buf.len();
+    let mut rrset_encoded_size = 0;
+    for rr in rrset {
+        let encoded_size = rr.encode_to_buf_with_cache(
+            buf,
+            label_cache.as_deref_mut(),
+            max_size.map(|max_size| max_size - rrset_encoded_size),
+        )?;
+        if encoded_size == 0 {
+            // Discard RRs that were already written along with the names that point to them
+            buf.get_inner_mut().truncate(rrset_pos);
+            if let Some(label_cache) = label_cache {
+                label_cache.retain(|_, pos| *pos < rrset_pos);
+            }
+            return Ok(0);
+        }
+        rrset_encoded_size += encoded_size;
+    }
+    Ok(rrset_encoded_size)
+}
+
+fn get_section_count<T>(section: &[T]) -> Result<u16, DnsError> {
+    u16::try_from(section.len()).map_err(|_| DnsError::TooManyEntries(section.len()))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
     use std::net::{Ipv4Addr, Ipv6Addr};
 
     use prop::collection::vec;
@@ -365,6 +560,254 @@ mod tests {
             let roundtripped_dns_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
             prop_assert_eq!(dns_packet, roundtripped_dns_packet, "DnsPacket roundtrip test failed");
         }
+
+        #[test]
+        fn borrowed_dns_packet_roundtrip(dns_packet in arb_dns_packet()) {
+            let mut buf = ByteBuf::new_empty(None);
+            dns_packet.encode_to_buf(&mut buf, None).expect("shouldn't have failed");
+            let encoded = buf.into_inner();
+            let borrowed_dns_packet = DnsPacket::from_buf(&mut ByteBuf::new(&encoded)).expect("shouldn't have failed");
+            prop_assert_eq!(&dns_packet, &borrowed_dns_packet, "borrowed DnsPacket roundtrip test failed");
+            prop_assert_eq!(dns_packet, borrowed_dns_packet.into_owned(), "owned DnsPacket roundtrip test failed");
+        }
+    }
+
+    #[test]
+    fn dns_packet_borrows_uncompressed_names() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        let qname: DomainName = "example.com".parse().unwrap();
+        dns_packet.header.question_count = 1;
+        dns_packet
+            .questions
+            .push(Question::new(qname.clone(), QueryType::A, None));
+        dns_packet.header.answer_rr_count = 1;
+        dns_packet.answers.push(ResourceRecord::new(
+            qname,
+            ResourceData::TXT {
+                txt_data: vec![b"text".as_slice().into()],
+            },
+            None,
+            None,
+        ));
+
+        let mut buf = ByteBuf::new_empty(None);
+        dns_packet.encode_to_buf(&mut buf, None).expect("shouldn't have failed");
+        let encoded = buf.into_inner();
+        let parsed_packet = DnsPacket::from_buf(&mut ByteBuf::new(&encoded)).expect("shouldn't have failed");
+
+        let encoded_range = encoded.as_ptr_range();
+        // QNAME is written in full, while the answer's NAME is a pointer to it
+        assert!(encoded_range.contains(&parsed_packet.questions[0].qname.as_wire().as_ptr()));
+        assert!(!encoded_range.contains(&parsed_packet.answers[0].name.as_wire().as_ptr()));
+        let ResourceData::TXT { txt_data } = &parsed_packet.answers[0].resource_data else {
+            panic!("expected a TXT record");
+        };
+        assert!(matches!(txt_data[0], Cow::Borrowed(_)));
+        assert_eq!(parsed_packet.into_owned(), dns_packet);
+    }
+
+    #[test]
+    fn dns_packet_with_pointer_loop() {
+        // Header with a single question, where QNAME points to itself
+        let packet = [
+            0x0, 0xa, 0x1, 0x0, 0x0, 0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0xc0, 0xc, 0x0, 0x1, 0x0, 0x1,
+        ];
+        let mut buf = ByteBuf::new(&packet);
+        let error = DnsPacket::from_buf(&mut buf).unwrap_err();
+        assert!(error.downcast_ref::<DnsError>().is_some());
+    }
+
+    #[test]
+    fn dns_packet_counts_are_derived_from_sections() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, None));
+        dns_packet.add_answer(ResourceRecord::new(
+            "test.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::LOCALHOST,
+            },
+            None,
+            None,
+        ));
+        #[cfg(feature = "edns")]
+        {
+            let opt_rr = || ResourceRecord::new(DomainName::root(), ResourceData::OPT { options: vec![] }, None, None);
+            dns_packet.set_edns(opt_rr());
+            // Should replace the existing OPT RR
+            dns_packet.add_additional(opt_rr());
+            assert_eq!(dns_packet.edns, Some(0));
+        }
+        assert_eq!(dns_packet.header.question_count, 1);
+        assert_eq!(dns_packet.header.answer_rr_count, 1);
+        assert_eq!(
+            dns_packet.header.additional_rr_count,
+            dns_packet.additionals.len() as u16
+        );
+
+        // Stale header counts should be ignored while encoding
+        let mut stale_dns_packet = dns_packet.clone();
+        stale_dns_packet.header.answer_rr_count = 5;
+        stale_dns_packet.header.authority_rr_count = 1;
+        let mut buf = ByteBuf::new_empty(None);
+        stale_dns_packet
+            .encode_to_buf(&mut buf, None)
+            .expect("shouldn't have failed");
+        let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
+        assert_eq!(parsed_packet, dns_packet);
+    }
+
+    #[test]
+    fn dns_packet_minimum_ttl() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        assert_eq!(dns_packet.get_minimum_ttl(), None);
+
+        let soa = ResourceData::SOA {
+            mname: "ns.test.com".parse().unwrap(),
+            rname: "admin.test.com".parse().unwrap(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        };
+        dns_packet.add_authority(ResourceRecord::new("test.com".parse().unwrap(), soa, Some(3600), None));
+        assert_eq!(dns_packet.get_minimum_ttl(), Some(300));
+
+        dns_packet.add_answer(ResourceRecord::new(
+            "test.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::LOCALHOST,
+            },
+            Some(60),
+            None,
+        ));
+        assert_eq!(dns_packet.get_minimum_ttl(), Some(60));
+    }
+
+    #[test]
+    fn dns_packet_encoding_errors() {
+        let encode = |dns_packet: &DnsPacket| {
+            let mut buf = ByteBuf::new_empty(None);
+            let error = dns_packet.encode_to_buf(&mut buf, None).unwrap_err();
+            error.downcast_ref::<DnsError>().copied()
+        };
+        let rr = |resource_data| ResourceRecord::new("test.com".parse().unwrap(), resource_data, None, None);
+
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_answer(rr(ResourceData::TXT {
+            txt_data: vec![vec![b'a'; 256].into()],
+        }));
+        assert_eq!(encode(&dns_packet), Some(DnsError::CharacterStringTooLong(256)));
+
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_answer(rr(ResourceData::UNKNOWN {
+            qtype: 65280,
+            rdata: vec![0; 65536].into(),
+        }));
+        assert_eq!(encode(&dns_packet), Some(DnsError::LengthOverflow(65536)));
+
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.questions = vec![Question::new("test.com".parse().unwrap(), QueryType::A, None); 65536];
+        assert_eq!(encode(&dns_packet), Some(DnsError::TooManyEntries(65536)));
+    }
+
+    #[test]
+    fn dns_packet_query_validation() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, Some(2)));
+        assert_eq!(dns_packet.validate_query(), Err(DnsError::UnknownClass(2)));
+
+        dns_packet.questions[0].qclass = IN_CLASS;
+        assert_eq!(dns_packet.validate_query(), Ok(()));
+
+        let tsig_rr = ResourceRecord::new(
+            "key.example".parse().unwrap(),
+            ResourceData::TSIG {
+                algorithm: "hmac-sha256".parse().unwrap(),
+                time_signed: 0,
+                fudge: 300,
+                mac: b"".as_slice().into(),
+                original_id: 0,
+                error: 0,
+                other_data: b"".as_slice().into(),
+            },
+            None,
+            Some(255),
+        );
+        dns_packet.add_additional(tsig_rr.clone());
+        assert_eq!(dns_packet.get_tsig_rr(), Some(&tsig_rr));
+        assert_eq!(dns_packet.validate_query(), Ok(()));
+        dns_packet.add_additional(ResourceRecord::new(
+            "test.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::LOCALHOST,
+            },
+            None,
+            None,
+        ));
+        assert_eq!(dns_packet.get_tsig_rr(), None);
+        assert_eq!(dns_packet.validate_query(), Err(DnsError::MisplacedTsig));
+        dns_packet.additionals.clear();
+
+        #[cfg(feature = "edns")]
+        {
+            // EDNS version is the second byte of TTL
+            dns_packet.set_edns(ResourceRecord::new(
+                DomainName::root(),
+                ResourceData::OPT { options: Vec::new() },
+                Some(1 << 16),
+                Some(1232),
+            ));
+            assert_eq!(dns_packet.validate_query(), Err(DnsError::BadEdnsVersion(1)));
+
+            // ADDRESS has bits set after SOURCE PREFIX-LENGTH, so the option is read as an unknown one
+            let client_subnet = EdnsOption::Unknown {
+                code: EdnsOption::CLIENT_SUBNET,
+                data: [0x0, 0x1, 0x18, 0x0, 0xc0, 0xa8, 0x10, 0x1].as_slice().into(),
+            };
+            dns_packet.set_edns(ResourceRecord::new(
+                DomainName::root(),
+                ResourceData::OPT {
+                    options: vec![client_subnet],
+                },
+                None,
+                Some(1232),
+            ));
+            assert_eq!(dns_packet.validate_query(), Err(DnsError::BadClientSubnet));
+        }
+    }
+
+    #[cfg(feature = "edns")]
+    #[test]
+    fn dns_packet_with_non_utf8_extended_dns_error() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.header.is_response = true;
+        dns_packet.header.response_code = ResponseCode::ServerFailure;
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, None));
+        let extended_dns_error = EdnsOption::Unknown {
+            code: EdnsOption::EXTENDED_DNS_ERROR,
+            data: [0x0, 0x17, b'o', b'k', 0xff].as_slice().into(),
+        };
+        dns_packet.set_edns(ResourceRecord::new(
+            DomainName::root(),
+            ResourceData::OPT {
+                options: vec![extended_dns_error],
+            },
+            None,
+            Some(1232),
+        ));
+
+        let mut buf = ByteBuf::new_empty(None);
+        dns_packet.encode_to_buf(&mut buf, None).expect("shouldn't have failed");
+        let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
+        let edns_data = parsed_packet.additionals[0].get_edns_data().unwrap();
+        assert_eq!(
+            edns_data.options,
+            [EdnsOption::ExtendedDnsError {
+                info_code: 23,
+                extra_text: "ok\u{fffd}".into(),
+            }]
+        );
     }
 
     #[should_panic(expected = "max size is too low: can't fit DNS header")]
@@ -385,7 +828,9 @@ mod tests {
         // Add counts
         dns_packet.header.question_count = 1;
         // Add questions
-        dns_packet.questions.push(Question::new("test.com", QueryType::A, None));
+        dns_packet
+            .questions
+            .push(Question::new("test.com".parse().unwrap(), QueryType::A, None));
 
         let mut buf = ByteBuf::new_empty(None);
         // 12 bytes are enough only for the header
@@ -410,10 +855,12 @@ mod tests {
         dns_packet.header.question_count = 1;
         dns_packet.header.answer_rr_count = 1;
         // Add questions
-        dns_packet.questions.push(Question::new("test.com", QueryType::A, None));
+        dns_packet
+            .questions
+            .push(Question::new("test.com".parse().unwrap(), QueryType::A, None));
         // Add answers
         dns_packet.answers.push(ResourceRecord::new(
-            "test.com".into(),
+            "test.com".parse().unwrap(),
             ResourceData::A {
                 address: Ipv4Addr::LOCALHOST,
             },
@@ -447,10 +894,12 @@ mod tests {
         dns_packet.header.answer_rr_count = 1;
         dns_packet.header.authority_rr_count = 1;
         // Add questions
-        dns_packet.questions.push(Question::new("test.com", QueryType::A, None));
+        dns_packet
+            .questions
+            .push(Question::new("test.com".parse().unwrap(), QueryType::A, None));
         // Add answers
         dns_packet.answers.push(ResourceRecord::new(
-            "test.com".into(),
+            "test.com".parse().unwrap(),
             ResourceData::A {
                 address: Ipv4Addr::LOCALHOST,
             },
@@ -489,10 +938,12 @@ mod tests {
         dns_packet.header.authority_rr_count = 1;
         dns_packet.header.additional_rr_count = 1;
         // Add questions
-        dns_packet.questions.push(Question::new("test.com", QueryType::A, None));
+        dns_packet
+            .questions
+            .push(Question::new("test.com".parse().unwrap(), QueryType::A, None));
         // Add answers
         dns_packet.answers.push(ResourceRecord::new(
-            "test.com".into(),
+            "test.com".parse().unwrap(),
             ResourceData::A {
                 address: Ipv4Addr::LOCALHOST,
             },
@@ -514,8 +965,8 @@ mod tests {
         let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
         // Check that header was correctly handled during truncation
         assert_common_dns_header_fields(&dns_packet.header, &parsed_packet.header);
-        // Should've been truncated
-        assert!(parsed_packet.header.truncation);
+        // Dropping additional RRs shouldn't set the truncation bit
+        assert!(!parsed_packet.header.truncation);
         assert_eq!(parsed_packet.header.question_count, 1);
         assert_eq!(parsed_packet.questions, dns_packet.questions);
         assert_eq!(parsed_packet.header.answer_rr_count, 1);
@@ -533,10 +984,12 @@ mod tests {
         dns_packet.header.question_count = 1;
         dns_packet.header.answer_rr_count = 2;
         // Add questions
-        dns_packet.questions.push(Question::new("test.com", QueryType::A, None));
+        dns_packet
+            .questions
+            .push(Question::new("test.com".parse().unwrap(), QueryType::A, None));
         // Add answers
         dns_packet.answers.push(ResourceRecord::new(
-            "test.com".into(),
+            "test.com".parse().unwrap(),
             ResourceData::A {
                 address: Ipv4Addr::LOCALHOST,
             },
@@ -544,7 +997,7 @@ mod tests {
             None,
         ));
         dns_packet.answers.push(ResourceRecord::new(
-            "test.com".into(),
+            "test.com".parse().unwrap(),
             ResourceData::AAAA {
                 address: Ipv6Addr::LOCALHOST,
             },
@@ -570,6 +1023,153 @@ mod tests {
         assert_eq!(parsed_packet.answers.first(), dns_packet.answers.first());
     }
 
+    #[test]
+    fn dns_packet_rrset_truncation() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, None));
+        let a_rr = |octet| {
+            ResourceRecord::new(
+                "test.com".parse().unwrap(),
+                ResourceData::A {
+                    address: Ipv4Addr::new(127, 0, 0, octet),
+                },
+                Some(16),
+                None,
+            )
+        };
+        dns_packet.add_answer(a_rr(1));
+        dns_packet.add_answer(a_rr(2));
+        // Would fit if the answer RRset wasn't dropped
+        dns_packet.add_authority(ResourceRecord::new(
+            DomainName::root(),
+            ResourceData::NS {
+                ns_domain_name: DomainName::root(),
+            },
+            Some(16),
+            None,
+        ));
+
+        let mut buf = ByteBuf::new_empty(None);
+        let encoded_size = dns_packet
+            // 12 /* header */ + 14 /* question */ + 16 /* first answer RR */
+            .encode_to_buf(&mut buf, Some(42))
+            .expect("shouldn't have failed");
+        // The first A RR would fit, but it's a part of the RRset that doesn't
+        assert_eq!(encoded_size, 26);
+        assert_eq!(buf.len(), 26);
+        let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
+        assert!(parsed_packet.header.truncation);
+        assert_eq!(parsed_packet.questions, dns_packet.questions);
+        assert!(parsed_packet.answers.is_empty());
+        assert!(parsed_packet.authorities.is_empty());
+    }
+
+    #[test]
+    fn dns_packet_encoding_should_keep_rr_order() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, None));
+        let a_rr = |octet| {
+            ResourceRecord::new(
+                "test.com".parse().unwrap(),
+                ResourceData::A {
+                    address: Ipv4Addr::new(127, 0, 0, octet),
+                },
+                Some(16),
+                None,
+            )
+        };
+        dns_packet.add_answer(a_rr(1));
+        dns_packet.add_answer(ResourceRecord::new(
+            "test.com".parse().unwrap(),
+            ResourceData::AAAA {
+                address: Ipv6Addr::LOCALHOST,
+            },
+            Some(16),
+            None,
+        ));
+        dns_packet.add_answer(a_rr(2));
+
+        let mut buf = ByteBuf::new_empty(None);
+        dns_packet.encode_to_buf(&mut buf, None).expect("shouldn't have failed");
+        let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
+        assert_eq!(parsed_packet.answers, dns_packet.answers);
+    }
+
+    #[test]
+    fn dns_packet_additional_truncation_should_keep_tsig_rr_and_tc_bit() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.header.truncation = true;
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, None));
+        dns_packet.add_additional(ResourceRecord::new(
+            "test.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::LOCALHOST,
+            },
+            Some(16),
+            None,
+        ));
+        let tsig_rr = ResourceRecord::new(
+            "key.example".parse().unwrap(),
+            ResourceData::TSIG {
+                algorithm: "hmac-sha256".parse().unwrap(),
+                time_signed: 0,
+                fudge: 300,
+                mac: b"".as_slice().into(),
+                original_id: 10,
+                error: 0,
+                other_data: b"".as_slice().into(),
+            },
+            None,
+            Some(255),
+        );
+        dns_packet.add_additional(tsig_rr.clone());
+
+        // 12 /* header */ + 14 /* question */ + TSIG RR, while the A RR would need 16 more bytes
+        let expected_size = 12 + 14 + tsig_rr.get_encoded_size(None);
+        let mut buf = ByteBuf::new_empty(None);
+        let encoded_size = dns_packet
+            .encode_to_buf(&mut buf, Some(expected_size + 10))
+            .expect("shouldn't have failed");
+        assert_eq!(encoded_size, expected_size);
+        let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
+        // TC bit of the original header is kept, even though only additional RRs were dropped
+        assert!(parsed_packet.header.truncation);
+        assert_eq!(parsed_packet.additionals, [tsig_rr]);
+    }
+
+    #[cfg(feature = "edns")]
+    #[test]
+    fn dns_packet_additional_truncation_should_keep_opt_rr() {
+        let mut dns_packet = get_empty_dns_packet(10);
+        dns_packet.add_question(Question::new("test.com".parse().unwrap(), QueryType::A, None));
+        dns_packet.add_additional(ResourceRecord::new(
+            "test.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::LOCALHOST,
+            },
+            Some(16),
+            None,
+        ));
+        dns_packet.set_edns(ResourceRecord::new(
+            DomainName::root(),
+            ResourceData::OPT { options: Vec::new() },
+            Some(1232),
+            None,
+        ));
+
+        let mut buf = ByteBuf::new_empty(None);
+        let encoded_size = dns_packet
+            // 12 /* header */ + 14 /* question */ + 11 /* OPT RR */
+            .encode_to_buf(&mut buf, Some(40))
+            .expect("shouldn't have failed");
+        assert_eq!(encoded_size, 37);
+        let parsed_packet = DnsPacket::from_buf(&mut buf).expect("shouldn't have failed");
+        assert!(!parsed_packet.header.truncation);
+        assert_eq!(parsed_packet.header.additional_rr_count, 1);
+        assert_eq!(parsed_packet.additionals, dns_packet.additionals[1..]);
+        assert_eq!(parsed_packet.edns, Some(0));
+    }
+
     #[cfg(feature = "edns")]
     #[should_panic(expected = "max size is too low: can't fit OPT RR")]
     #[test]
@@ -578,8 +1178,8 @@ mod tests {
         dns_packet.header.additional_rr_count = 1;
         // Add OPT RR
         dns_packet.additionals.push(ResourceRecord::new(
-            "test.com".into(),
-            ResourceData::OPT { options: None },
+            "test.com".parse().unwrap(),
+            ResourceData::OPT { options: Vec::new() },
             Some(1232),
             None,
         ));
@@ -597,11 +1197,13 @@ mod tests {
         dns_packet.header.question_count = 1;
         dns_packet.header.additional_rr_count = 1;
         // Add questions
-        dns_packet.questions.push(Question::new("test.com", QueryType::A, None));
+        dns_packet
+            .questions
+            .push(Question::new("test.com".parse().unwrap(), QueryType::A, None));
         // Add OPT RR
         dns_packet.additionals.push(ResourceRecord::new(
-            "".into(),
-            ResourceData::OPT { options: None },
+            DomainName::root(),
+            ResourceData::OPT { options: Vec::new() },
             Some(1232),
             None,
         ));
@@ -0,0 +1,913 @@
+//! Presentation (text) format of DNS data as defined in RFC1035 (section 5.1) and type-specific RFCs
+
+use std::fmt::{self, Write as _};
+
+use anyhow::Context;
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER};
+
+#[cfg(feature = "edns")]
+use crate::{ByteBuf, EdnsOption, EncodeToBuf};
+use crate::{
+    DnsHeader, DnsPacket, DomainName, QueryOpcode, QueryType, Question, ResourceData, ResourceRecord, ResponseCode,
+    SvcParam,
+};
+
+/// Absolute domain name with a trailing dot, where special characters are escaped
+pub(crate) struct Name<'a>(pub &'a DomainName<'a>);
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, self.0)?;
+        if !self.0.is_root() {
+            f.write_char('.')?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the name with special characters escaped and without the trailing dot, except for the root name
+pub(crate) fn write_name(f: &mut fmt::Formatter<'_>, name: &DomainName<'_>) -> fmt::Result {
+    if name.is_root() {
+        return f.write_char('.');
+    }
+    for (idx, label) in name.labels().enumerate() {
+        if idx != 0 {
+            f.write_char('.')?;
+        }
+        for byte in label {
+            match byte {
+                b'.' | b';' | b'(' | b')' | b'"' | b'\\' | b'@' | b'$' => write!(f, "\\{}", *byte as char)?,
+                0x21..=0x7e => f.write_char(*byte as char)?,
+                _ => write!(f, "\\{:03}", byte)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `\X` and `\DDD` escape sequences, marking which bytes were escaped
+pub(crate) fn unescape(text: &str) -> anyhow::Result<Vec<(u8, bool)>> {
+    let bytes = text.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[pos] != b'\\' {
+            unescaped.push((bytes[pos], false));
+            pos += 1;
+            continue;
+        }
+
+        match bytes.get(pos + 1..pos + 4) {
+            Some(digits) if digits.iter().all(u8::is_ascii_digit) => {
+                let byte = std::str::from_utf8(digits)
+                    .unwrap()
+                    .parse::<u8>()
+                    .with_context(|| format!("invalid escape sequence '\\{}'", std::str::from_utf8(digits).unwrap()))?;
+                unescaped.push((byte, true));
+                pos += 4;
+            }
+            _ => {
+                let byte = bytes.get(pos + 1).context("unterminated escape sequence")?;
+                if byte.is_ascii_digit() {
+                    anyhow::bail!("escape sequence '\\DDD' must have exactly 3 digits");
+                }
+                unescaped.push((*byte, true));
+                pos += 2;
+            }
+        }
+    }
+
+    Ok(unescaped)
+}
+
+/// Splits the domain name in the presentation format into unescaped labels.
+///
+/// Returns `true` along with the labels if the name is absolute, i.e. ends with an unescaped dot.
+pub(crate) fn parse_labels(text: &str) -> anyhow::Result<(Vec<Vec<u8>>, bool)> {
+    if text.is_empty() {
+        anyhow::bail!("empty name");
+    }
+    if text == "." {
+        return Ok((Vec::new(), true));
+    }
+
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut is_absolute = false;
+    for (byte, escaped) in unescape(text)? {
+        if byte == b'.' && !escaped {
+            if label.is_empty() {
+                anyhow::bail!("empty label");
+            }
+            labels.push(std::mem::take(&mut label));
+            is_absolute = true;
+        } else {
+            label.push(byte);
+            is_absolute = false;
+        }
+    }
+    if !label.is_empty() {
+        labels.push(label);
+    }
+
+    Ok((labels, is_absolute))
+}
+
+/// Quoted `<character-string>`
+pub(crate) struct CharacterString<'a>(pub &'a [u8]);
+
+impl fmt::Display for CharacterString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for byte in self.0 {
+            match byte {
+                b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                0x20..=0x7e => f.write_char(*byte as char)?,
+                _ => write!(f, "\\{:03}", byte)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Class mnemonic, or `CLASS<n>` (RFC3597) for unknown classes
+pub(crate) struct Class(pub u16);
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => f.write_str("IN"),
+            3 => f.write_str("CH"),
+            4 => f.write_str("HS"),
+            class => write!(f, "CLASS{}", class),
+        }
+    }
+}
+
+pub(crate) fn parse_class(text: &str) -> Option<u16> {
+    match text.to_ascii_uppercase().as_str() {
+        "IN" => Some(1),
+        "CH" => Some(3),
+        "HS" => Some(4),
+        class => class.strip_prefix("CLASS").and_then(|class| class.parse().ok()),
+    }
+}
+
+/// RRSIG timestamp in the `YYYYMMDDHHmmSS` format (RFC4034, section 3.2)
+pub(crate) struct Timestamp(pub u32);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0 as i64;
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+        let seconds_of_day = seconds.rem_euclid(86400);
+        write!(
+            f,
+            "{:04}{:02}{:02}{:02}{:02}{:02}",
+            year,
+            month,
+            day,
+            seconds_of_day / 3600,
+            seconds_of_day % 3600 / 60,
+            seconds_of_day % 60
+        )
+    }
+}
+
+/// Parses either a `YYYYMMDDHHmmSS` timestamp or the number of seconds since the UNIX epoch
+pub(crate) fn parse_timestamp(text: &str) -> anyhow::Result<u32> {
+    if text.len() != 14 {
+        return text
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid timestamp '{}'", text));
+    }
+    if !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        anyhow::bail!("invalid timestamp '{}'", text);
+    }
+    let field = |range: std::ops::Range<usize>| text[range].parse::<i64>().unwrap();
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hours, minutes, seconds) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 59 {
+        anyhow::bail!("invalid timestamp '{}'", text);
+    }
+    let timestamp = days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds;
+    // RFC4034: the value is a serial number that wraps around every 2^32 seconds
+    Ok(timestamp.rem_euclid(1 << 32) as u32)
+}
+
+// Both functions below are taken from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn write_type_bitmaps(f: &mut fmt::Formatter<'_>, types: &[QueryType]) -> fmt::Result {
+    types.iter().try_for_each(|qtype| write!(f, " {}", qtype))
+}
+
+/// Generic RDATA format from RFC3597 (section 5)
+fn write_generic_rdata(f: &mut fmt::Formatter<'_>, rdata: &[u8]) -> fmt::Result {
+    write!(f, "\\# {}", rdata.len())?;
+    if !rdata.is_empty() {
+        write!(f, " {}", HEXUPPER.encode(rdata))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for SvcParam<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvcParam::Mandatory { keys } => {
+                f.write_str("mandatory=")?;
+                for (idx, key) in keys.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_char(',')?;
+                    }
+                    f.write_str(&get_svc_param_key_name(*key))?;
+                }
+                Ok(())
+            }
+            SvcParam::Alpn { protocols } => {
+                // RFC9460 (Appendix A.1): commas and backslashes inside of the list items are escaped
+                let mut value = Vec::new();
+                for (idx, protocol) in protocols.iter().enumerate() {
+                    if idx != 0 {
+                        value.push(b',');
+                    }
+                    for byte in protocol.iter() {
+                        if matches!(byte, b',' | b'\\') {
+                            value.push(b'\\');
+                        }
+                        value.push(*byte);
+                    }
+                }
+                write!(f, "alpn={}", CharacterString(&value))
+            }
+            SvcParam::NoDefaultAlpn => f.write_str("no-default-alpn"),
+            SvcParam::Port { port } => write!(f, "port={}", port),
+            SvcParam::Ipv4Hint { addresses } => {
+                f.write_str("ipv4hint=")?;
+                for (idx, address) in addresses.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", address)?;
+                }
+                Ok(())
+            }
+            SvcParam::Ech { config } => write!(f, "ech={}", BASE64.encode(config)),
+            SvcParam::Ipv6Hint { addresses } => {
+                f.write_str("ipv6hint=")?;
+                for (idx, address) in addresses.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", address)?;
+                }
+                Ok(())
+            }
+            SvcParam::Unknown { key, value } => {
+                write!(f, "key{}", key)?;
+                if !value.is_empty() {
+                    write!(f, "={}", CharacterString(value))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) fn get_svc_param_key_name(key: u16) -> String {
+    match key {
+        0 => "mandatory".to_owned(),
+        1 => "alpn".to_owned(),
+        2 => "no-default-alpn".to_owned(),
+        3 => "port".to_owned(),
+        4 => "ipv4hint".to_owned(),
+        5 => "ech".to_owned(),
+        6 => "ipv6hint".to_owned(),
+        key => format!("key{}", key),
+    }
+}
+
+pub(crate) fn parse_svc_param_key(name: &str) -> anyhow::Result<u16> {
+    Ok(match name {
+        "mandatory" => 0,
+        "alpn" => 1,
+        "no-default-alpn" => 2,
+        "port" => 3,
+        "ipv4hint" => 4,
+        "ech" => 5,
+        "ipv6hint" => 6,
+        name => name
+            .strip_prefix("key")
+            .and_then(|key| key.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("unknown SvcParamKey '{}'", name))?,
+    })
+}
+
+/// Formats RDATA in presentation format
+impl fmt::Display for ResourceData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceData::UNKNOWN { rdata, .. } => write_generic_rdata(f, rdata),
+            ResourceData::A { address } => write!(f, "{}", address),
+            ResourceData::NS { ns_domain_name } => write!(f, "{}", Name(ns_domain_name)),
+            ResourceData::CNAME { cname } => write!(f, "{}", Name(cname)),
+            ResourceData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                Name(mname),
+                Name(rname),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            ResourceData::PTR { ptr_domain_name } => write!(f, "{}", Name(ptr_domain_name)),
+            ResourceData::MX { preference, exchange } => write!(f, "{} {}", preference, Name(exchange)),
+            ResourceData::TXT { txt_data } => {
+                for (idx, data) in txt_data.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_char(' ')?;
+                    }
+                    write!(f, "{}", CharacterString(data))?;
+                }
+                Ok(())
+            }
+            ResourceData::AAAA { address } => write!(f, "{}", address),
+            ResourceData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, Name(target)),
+            ResourceData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                CharacterString(flags),
+                CharacterString(services),
+                CharacterString(regexp),
+                Name(replacement)
+            ),
+            #[cfg(feature = "edns")]
+            ResourceData::OPT { .. } => {
+                // OPT RR is a pseudo-RR that doesn't have a presentation format
+                let mut buf = ByteBuf::new_empty(None);
+                self.encode_to_buf(&mut buf, None).map_err(|_| fmt::Error)?;
+                write_generic_rdata(f, &buf[2..])
+            }
+            ResourceData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                key_tag,
+                algorithm,
+                digest_type,
+                HEXUPPER.encode(digest)
+            ),
+            ResourceData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => write!(f, "{} {} {}", algorithm, fingerprint_type, HEXUPPER.encode(fingerprint)),
+            ResourceData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                Timestamp(*signature_expiration),
+                Timestamp(*signature_inception),
+                key_tag,
+                Name(signer_name),
+                BASE64.encode(signature)
+            ),
+            ResourceData::NSEC {
+                next_domain_name,
+                type_bitmaps,
+            } => {
+                write!(f, "{}", Name(next_domain_name))?;
+                write_type_bitmaps(f, type_bitmaps)
+            }
+            ResourceData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => write!(f, "{} {} {} {}", flags, protocol, algorithm, BASE64.encode(public_key)),
+            ResourceData::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner_name,
+                type_bitmaps,
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {} {}",
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    Salt(salt),
+                    BASE32HEX_NOPAD.encode(next_hashed_owner_name)
+                )?;
+                write_type_bitmaps(f, type_bitmaps)
+            }
+            ResourceData::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => write!(f, "{} {} {} {}", hash_algorithm, flags, iterations, Salt(salt)),
+            ResourceData::TLSA {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_association_data,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                certificate_usage,
+                selector,
+                matching_type,
+                HEXUPPER.encode(certificate_association_data)
+            ),
+            ResourceData::SVCB {
+                priority,
+                target_name,
+                params,
+            }
+            | ResourceData::HTTPS {
+                priority,
+                target_name,
+                params,
+            } => {
+                write!(f, "{} {}", priority, Name(target_name))?;
+                params.iter().try_for_each(|param| write!(f, " {}", param))
+            }
+            ResourceData::CAA { flags, tag, value } => write!(f, "{} {} {}", flags, tag, CharacterString(value)),
+            // TSIG RR is a meta-RR without a standard presentation format, so mimic `dig`
+            ResourceData::TSIG {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other_data,
+            } => {
+                write!(f, "{} {} {} {}", Name(algorithm), time_signed, fudge, mac.len())?;
+                if !mac.is_empty() {
+                    write!(f, " {}", BASE64.encode(mac))?;
+                }
+                write!(f, " {} {} {}", original_id, TsigError(*error), other_data.len())?;
+                if !other_data.is_empty() {
+                    write!(f, " {}", BASE64.encode(other_data))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// NSEC3 salt, where the empty salt is represented by `-` (RFC5155, section 3.3)
+struct Salt<'a>(&'a [u8]);
+
+impl fmt::Display for Salt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            f.write_char('-')
+        } else {
+            f.write_str(&HEXUPPER.encode(self.0))
+        }
+    }
+}
+
+/// TSIG error mnemonic (RFC8945, section 4.3), or the code itself for unknown errors
+struct TsigError(u16);
+
+impl fmt::Display for TsigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => f.write_str("NOERROR"),
+            16 => f.write_str("BADSIG"),
+            17 => f.write_str("BADKEY"),
+            18 => f.write_str("BADTIME"),
+            22 => f.write_str("BADTRUNC"),
+            error => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Formats the RR as a single line of a zone file
+impl fmt::Display for ResourceRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            Name(&self.name),
+            self.ttl,
+            Class(self.class),
+            self.resource_data.get_query_type(),
+            self.resource_data
+        )
+    }
+}
+
+impl fmt::Display for QueryOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QueryOpcode::QUERY => "QUERY",
+            QueryOpcode::IQUERY => "IQUERY",
+            QueryOpcode::STATUS => "STATUS",
+            QueryOpcode::UNKNOWN => "RESERVED",
+        })
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResponseCode::Success => "NOERROR",
+            ResponseCode::FormatError => "FORMERR",
+            ResponseCode::ServerFailure => "SERVFAIL",
+            ResponseCode::NameError => "NXDOMAIN",
+            ResponseCode::NotImplemented => "NOTIMP",
+            ResponseCode::Refused => "REFUSED",
+            ResponseCode::NotAuth => "NOTAUTH",
+            ResponseCode::Unknown(rcode) => return write!(f, "RESERVED{}", rcode),
+        })
+    }
+}
+
+/// Response code that may include the upper 8 bits from the OPT RR (RFC6891)
+struct ExtendedResponseCode(u16);
+
+impl fmt::Display for ExtendedResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            rcode @ 0..=15 => write!(f, "{}", ResponseCode::from(rcode as u8)),
+            16 => f.write_str("BADVERS"),
+            17 => f.write_str("BADKEY"),
+            18 => f.write_str("BADTIME"),
+            19 => f.write_str("BADMODE"),
+            20 => f.write_str("BADNAME"),
+            21 => f.write_str("BADALG"),
+            22 => f.write_str("BADTRUNC"),
+            23 => f.write_str("BADCOOKIE"),
+            rcode => write!(f, "RESERVED{}", rcode),
+        }
+    }
+}
+
+/// Formats the header the same way as `dig` does
+impl fmt::Display for DnsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_header(f, self, ExtendedResponseCode(u8::from(self.response_code).into()))
+    }
+}
+
+fn write_header(f: &mut fmt::Formatter<'_>, header: &DnsHeader, response_code: ExtendedResponseCode) -> fmt::Result {
+    writeln!(
+        f,
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        header.opcode, response_code, header.id
+    )?;
+    f.write_str(";; flags:")?;
+    let flags = [
+        (header.is_response, "qr"),
+        (header.is_authoritative, "aa"),
+        (header.truncation, "tc"),
+        (header.recursion_desired, "rd"),
+        (header.recursion_available, "ra"),
+        (header.z[0], "z"),
+        (header.z[1], "ad"),
+        (header.z[2], "cd"),
+    ];
+    for (_, flag) in flags.iter().filter(|(is_set, _)| *is_set) {
+        write!(f, " {}", flag)?;
+    }
+    write!(
+        f,
+        "; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        header.question_count, header.answer_rr_count, header.authority_rr_count, header.additional_rr_count
+    )
+}
+
+/// Formats the question as a commented out zone file line without a TTL
+impl fmt::Display for Question<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            ";{}\t\t{}\t{}",
+            Name(&self.qname),
+            Class(self.qclass),
+            self.query_type
+        )
+    }
+}
+
+#[cfg(feature = "edns")]
+impl fmt::Display for EdnsOption<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdnsOption::Nsid { nsid } => {
+                write!(f, "NSID: {}", HEXUPPER.encode(nsid))?;
+                if !nsid.is_empty() && nsid.iter().all(|byte| (0x20..=0x7e).contains(byte)) {
+                    write!(f, " ({})", CharacterString(nsid))?;
+                }
+                Ok(())
+            }
+            EdnsOption::ClientSubnet {
+                source_prefix_length,
+                scope_prefix_length,
+                address,
+            } => write!(
+                f,
+                "CLIENT-SUBNET: {}/{}/{}",
+                address, source_prefix_length, scope_prefix_length
+            ),
+            EdnsOption::Cookie {
+                client_cookie,
+                server_cookie,
+            } => {
+                write!(f, "COOKIE: {}", HEXUPPER.encode(client_cookie))?;
+                if let Some(server_cookie) = server_cookie {
+                    f.write_str(&HEXUPPER.encode(server_cookie))?;
+                }
+                Ok(())
+            }
+            EdnsOption::TcpKeepalive { timeout: Some(timeout) } => {
+                write!(f, "TCP-KEEPALIVE: {}.{} secs", timeout / 10, timeout % 10)
+            }
+            EdnsOption::TcpKeepalive { timeout: None } => f.write_str("TCP-KEEPALIVE"),
+            EdnsOption::Padding { length } => write!(f, "PADDING: {} bytes", length),
+            EdnsOption::ExtendedDnsError { info_code, extra_text } => {
+                write!(f, "EDE: {}", info_code)?;
+                if let Some(purpose) = get_extended_dns_error_purpose(*info_code) {
+                    write!(f, " ({})", purpose)?;
+                }
+                if !extra_text.is_empty() {
+                    write!(f, ": ({})", extra_text)?;
+                }
+                Ok(())
+            }
+            EdnsOption::Unknown { code, data } => write!(f, "OPT={}: {}", code, HEXUPPER.encode(data)),
+        }
+    }
+}
+
+/// Purposes of the INFO-CODEs from RFC8914 (section 4)
+#[cfg(feature = "edns")]
+fn get_extended_dns_error_purpose(info_code: u16) -> Option<&'static str> {
+    Some(match info_code {
+        0 => "Other Error",
+        1 => "Unsupported DNSKEY Algorithm",
+        2 => "Unsupported DS Digest Type",
+        3 => "Stale Answer",
+        4 => "Forged Answer",
+        5 => "DNSSEC Indeterminate",
+        6 => "DNSSEC Bogus",
+        7 => "Signature Expired",
+        8 => "Signature Not Yet Valid",
+        9 => "DNSKEY Missing",
+        10 => "RRSIGs Missing",
+        11 => "No Zone Key Bit Set",
+        12 => "NSEC Missing",
+        13 => "Cached Error",
+        14 => "Not Ready",
+        15 => "Blocked",
+        16 => "Censored",
+        17 => "Filtered",
+        18 => "Prohibited",
+        19 => "Stale NXDomain Answer",
+        20 => "Not Authoritative",
+        21 => "Not Supported",
+        22 => "No Reachable Authority",
+        23 => "Network Error",
+        24 => "Invalid Data",
+        _ => return None,
+    })
+}
+
+/// Formats the packet the same way as `dig` does, where the OPT RR is shown as a pseudosection
+impl fmt::Display for DnsPacket<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let response_code = u16::from(u8::from(self.header.response_code));
+        // Upper 8 bits of the extended RCODE are stored in the OPT RR's TTL
+        #[cfg(feature = "edns")]
+        let response_code = match self
+            .edns
+            .and_then(|idx| self.additionals.get(idx))
+            .and_then(|rr| rr.get_edns_data())
+            .and_then(|edns_data| edns_data.extended_rcode)
+        {
+            Some(extended_rcode) => (u16::from(extended_rcode.get()) << 4) | response_code,
+            None => response_code,
+        };
+        write_header(f, &self.header, ExtendedResponseCode(response_code))?;
+        writeln!(f)?;
+
+        #[cfg(feature = "edns")]
+        if let Some(edns_data) = self
+            .edns
+            .and_then(|idx| self.additionals.get(idx))
+            .and_then(|rr| rr.get_edns_data())
+        {
+            f.write_str("\n;; OPT PSEUDOSECTION:\n")?;
+            write!(f, "; EDNS: version: {}, flags:", edns_data.version)?;
+            if edns_data.dnssec_ok_bit {
+                f.write_str(" do")?;
+            }
+            writeln!(f, "; udp: {}", edns_data.udp_payload_size)?;
+            for option in edns_data.options {
+                writeln!(f, "; {}", option)?;
+            }
+        }
+
+        if !self.questions.is_empty() {
+            f.write_str("\n;; QUESTION SECTION:\n")?;
+            for question in self.questions.iter() {
+                writeln!(f, "{}", question)?;
+            }
+        }
+        write_section(f, "ANSWER", &self.answers)?;
+        write_section(f, "AUTHORITY", &self.authorities)?;
+        let additionals = self.additionals.iter();
+        // OPT RR is already shown as a pseudosection
+        #[cfg(feature = "edns")]
+        let additionals = additionals.filter(|rr| rr.resource_data.get_query_type() != QueryType::OPT);
+        write_section(f, "ADDITIONAL", additionals)
+    }
+}
+
+fn write_section<'r, 'a: 'r>(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    records: impl IntoIterator<Item = &'r ResourceRecord<'a>>,
+) -> fmt::Result {
+    let mut records = records.into_iter().peekable();
+    if records.peek().is_none() {
+        return Ok(());
+    }
+    writeln!(f, "\n;; {} SECTION:", name)?;
+    records.try_for_each(|record| writeln!(f, "{}", record))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn unescape_sequences() {
+        let unescaped = unescape("a\\.\\046\\\\").expect("shouldn't have failed");
+        assert_eq!(unescaped, [(b'a', false), (b'.', true), (b'.', true), (b'\\', true)]);
+    }
+
+    #[test]
+    fn timestamp_roundtrip() {
+        // Taken from RFC4034 (section 3.3)
+        let timestamp = parse_timestamp("20030322173103").expect("shouldn't have failed");
+        assert_eq!(timestamp, 1048354263);
+        assert_eq!(Timestamp(timestamp).to_string(), "20030322173103");
+    }
+
+    #[test]
+    fn escaped_name_and_character_string() {
+        assert_eq!(Name(&DomainName::root()).to_string(), ".");
+        let name = DomainName::from_labels([b"a\"b".as_slice(), b"example"]).unwrap();
+        assert_eq!(Name(&name).to_string(), "a\\\"b.example.");
+        let name = DomainName::from_labels(["caf\u{e9}".as_bytes(), b"example"]).unwrap();
+        assert_eq!(Name(&name).to_string(), "caf\\195\\169.example.");
+        assert_eq!(CharacterString(b"say \"hi\"\n").to_string(), "\"say \\\"hi\\\"\\010\"");
+    }
+
+    #[test]
+    fn dig_style_packet() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 4660;
+        packet.header.is_response = true;
+        packet.header.recursion_desired = true;
+        packet.header.recursion_available = true;
+        packet.header.question_count = 1;
+        packet.header.answer_rr_count = 1;
+        packet
+            .questions
+            .push(Question::new("example.com".parse().unwrap(), QueryType::A, None));
+        packet.answers.push(ResourceRecord::new(
+            "example.com".parse().unwrap(),
+            ResourceData::A {
+                address: Ipv4Addr::new(192, 0, 2, 1),
+            },
+            Some(300),
+            None,
+        ));
+
+        let mut expected = String::from(
+            ";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660\n\
+             ;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0\n",
+        );
+        #[cfg(feature = "edns")]
+        {
+            packet.header.additional_rr_count = 1;
+            packet.additionals.push(ResourceRecord::new(
+                DomainName::root(),
+                ResourceData::OPT {
+                    options: vec![EdnsOption::ExtendedDnsError {
+                        info_code: 15,
+                        extra_text: "".into(),
+                    }],
+                },
+                Some(0),
+                Some(1232),
+            ));
+            packet.edns = Some(0);
+            expected = expected.replace("ADDITIONAL: 0", "ADDITIONAL: 1")
+                + "\n;; OPT PSEUDOSECTION:\n; EDNS: version: 0, flags:; udp: 1232\n; EDE: 15 (Blocked)\n";
+        }
+        expected += "\n;; QUESTION SECTION:\n;example.com.\t\tIN\tA\n\
+                     \n;; ANSWER SECTION:\nexample.com.\t300\tIN\tA\t192.0.2.1\n";
+
+        assert_eq!(packet.to_string(), expected);
+    }
+
+    #[cfg(feature = "edns")]
+    #[test]
+    fn dig_style_extended_response_code() {
+        let mut packet = DnsPacket::new();
+        packet.header.is_response = true;
+        // BADVERS is 16, so only its upper bits are stored in the OPT RR's TTL
+        packet.set_edns(ResourceRecord::new(
+            DomainName::root(),
+            ResourceData::OPT { options: Vec::new() },
+            Some(1 << 24),
+            Some(1232),
+        ));
+        assert!(packet
+            .to_string()
+            .starts_with(";; ->>HEADER<<- opcode: QUERY, status: BADVERS, id: 0\n"));
+
+        packet.header.response_code = ResponseCode::FormatError;
+        assert!(packet
+            .to_string()
+            .starts_with(";; ->>HEADER<<- opcode: QUERY, status: BADKEY, id: 0\n"));
+        assert!(packet
+            .header
+            .to_string()
+            .starts_with(";; ->>HEADER<<- opcode: QUERY, status: FORMERR, id: 0\n"));
+    }
+}
@@ -1,11 +1,12 @@
-use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 use anyhow::Context;
 
 use crate::buf::EncodedSize;
 use crate::utils::get_max_encoded_qname_size;
-use crate::{ByteBuf, EncodeToBuf, FromBuf, IN_CLASS};
+use crate::{ByteBuf, DomainName, EncodeToBuf, FromBuf, IN_CLASS};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
@@ -14,10 +15,38 @@ pub enum QueryType {
     A,
     NS,
     CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
     AAAA,
+    SRV,
+    NAPTR,
     #[cfg(feature = "edns")]
     OPT,
+    DS,
+    SSHFP,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    NSEC3PARAM,
+    TLSA,
+    SVCB,
+    HTTPS,
+    TSIG,
     ANY,
+    CAA,
+}
+
+impl QueryType {
+    /// Returns `true` for RR types that should be included in responses only if DNSSEC was requested
+    pub fn is_dnssec(&self) -> bool {
+        matches!(
+            self,
+            QueryType::DS | QueryType::RRSIG | QueryType::NSEC | QueryType::DNSKEY | QueryType::NSEC3
+        )
+    }
 }
 
 impl From<u16> for QueryType {
@@ -26,10 +55,28 @@ impl From<u16> for QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
             #[cfg(feature = "edns")]
             41 => QueryType::OPT,
+            43 => QueryType::DS,
+            44 => QueryType::SSHFP,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            51 => QueryType::NSEC3PARAM,
+            52 => QueryType::TLSA,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            250 => QueryType::TSIG,
             255 => QueryType::ANY,
+            257 => QueryType::CAA,
             _ => QueryType::UNKNOWN(value),
         }
     }
@@ -41,26 +88,94 @@ impl From<QueryType> for u16 {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
             #[cfg(feature = "edns")]
             QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::SSHFP => 44,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::NSEC3PARAM => 51,
+            QueryType::TLSA => 52,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::TSIG => 250,
             QueryType::ANY => 255,
+            QueryType::CAA => 257,
             QueryType::UNKNOWN(qtype) => qtype,
         }
     }
 }
 
+/// Formats the type mnemonic, or `TYPE<n>` (RFC3597) for unknown types
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryType::UNKNOWN(qtype) => write!(f, "TYPE{}", qtype),
+            qtype => write!(f, "{:?}", qtype),
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let qtype = match s.to_ascii_uppercase().as_str() {
+            "A" => QueryType::A,
+            "NS" => QueryType::NS,
+            "CNAME" => QueryType::CNAME,
+            "SOA" => QueryType::SOA,
+            "PTR" => QueryType::PTR,
+            "MX" => QueryType::MX,
+            "TXT" => QueryType::TXT,
+            "AAAA" => QueryType::AAAA,
+            "SRV" => QueryType::SRV,
+            "NAPTR" => QueryType::NAPTR,
+            #[cfg(feature = "edns")]
+            "OPT" => QueryType::OPT,
+            "DS" => QueryType::DS,
+            "SSHFP" => QueryType::SSHFP,
+            "RRSIG" => QueryType::RRSIG,
+            "NSEC" => QueryType::NSEC,
+            "DNSKEY" => QueryType::DNSKEY,
+            "NSEC3" => QueryType::NSEC3,
+            "NSEC3PARAM" => QueryType::NSEC3PARAM,
+            "TLSA" => QueryType::TLSA,
+            "SVCB" => QueryType::SVCB,
+            "HTTPS" => QueryType::HTTPS,
+            "TSIG" => QueryType::TSIG,
+            "ANY" => QueryType::ANY,
+            "CAA" => QueryType::CAA,
+            other => other
+                .strip_prefix("TYPE")
+                .and_then(|qtype| qtype.parse::<u16>().ok())
+                .map(QueryType::from)
+                .with_context(|| format!("unknown type '{}'", s))?,
+        };
+        Ok(qtype)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Question<'a> {
-    pub qname: Cow<'a, str>,
+    pub qname: DomainName<'a>,
     pub query_type: QueryType,
     pub qclass: u16,
 }
 
 impl<'a> Question<'a> {
-    pub fn new(qname: &'a str, query_type: QueryType, qclass: Option<u16>) -> Self {
+    pub fn new(qname: DomainName<'a>, query_type: QueryType, qclass: Option<u16>) -> Self {
         Self {
-            qname: Cow::Borrowed(qname),
+            qname,
             query_type,
             qclass: qclass.unwrap_or(IN_CLASS),
         }
@@ -68,15 +183,15 @@ impl<'a> Question<'a> {
 
     pub fn into_owned(self) -> Question<'static> {
         Question {
-            qname: self.qname.into_owned().into(),
+            qname: self.qname.into_owned(),
             query_type: self.query_type,
             qclass: self.qclass,
         }
     }
 }
 
-impl<'a> FromBuf for Question<'a> {
-    fn from_buf(buf: &mut ByteBuf) -> anyhow::Result<Question<'static>> {
+impl<'a> FromBuf<'a> for Question<'a> {
+    fn from_buf(buf: &mut ByteBuf<'a>) -> anyhow::Result<Question<'a>> {
         let qname = buf.read_qname().context("QNAME is missing")?;
         let qtype_raw = buf.read_u16().context("QTYPE is missing")?;
         let class = buf.read_u16().context("QCLASS is missing")?;
@@ -93,7 +208,7 @@ impl<'a> EncodeToBuf for Question<'a> {
     fn encode_to_buf_with_cache<'cache, 'r: 'cache>(
         &'r self,
         buf: &mut ByteBuf,
-        label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
         max_size: Option<usize>,
     ) -> anyhow::Result<usize> {
         let encoded_size = self.get_encoded_size(label_cache.as_deref());
@@ -109,7 +224,7 @@ impl<'a> EncodeToBuf for Question<'a> {
 }
 
 impl EncodedSize for Question<'_> {
-    fn get_encoded_size(&self, label_cache: Option<&HashMap<&str, usize>>) -> usize {
+    fn get_encoded_size(&self, label_cache: Option<&HashMap<&[u8], usize>>) -> usize {
         let qname_size = get_max_encoded_qname_size(&self.qname, label_cache);
         qname_size + 2 /* QTYPE */ + 2 /* CLASS */
     }
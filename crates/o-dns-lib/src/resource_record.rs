@@ -7,12 +7,18 @@ use std::num::NonZero;
 use anyhow::Context;
 
 use crate::buf::EncodedSize;
-use crate::utils::get_max_encoded_qname_size;
-use crate::{ByteBuf, EncodeToBuf, FromBuf, QueryType};
+#[cfg(feature = "edns")]
+use crate::edns::{get_edns_options_size, read_edns_options, write_edns_options};
+use crate::svcb::{get_svc_params_size, read_svc_params, write_svc_params};
+use crate::type_bitmap::{get_type_bitmaps_size, read_type_bitmaps, write_type_bitmaps};
+use crate::utils::{cow_bytes_into_str, get_max_encoded_qname_size, get_u16_length};
+#[cfg(feature = "edns")]
+use crate::EdnsOption;
+use crate::{ByteBuf, DomainName, EncodeToBuf, FromBuf, QueryType, SvcParam};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ResourceRecord<'a> {
-    pub name: Cow<'a, str>,
+    pub name: DomainName<'a>,
     pub class: u16,
     pub ttl: u32,
     pub resource_data: ResourceData<'a>,
@@ -20,7 +26,7 @@ pub struct ResourceRecord<'a> {
 
 impl<'a> ResourceRecord<'a> {
     pub fn new<'s: 'a>(
-        name: Cow<'s, str>,
+        name: DomainName<'s>,
         resource_data: ResourceData<'a>,
         ttl: Option<u32>,
         class: Option<u16>,
@@ -33,10 +39,19 @@ impl<'a> ResourceRecord<'a> {
         }
     }
 
+    pub fn into_owned(self) -> ResourceRecord<'static> {
+        ResourceRecord {
+            name: self.name.into_owned(),
+            class: self.class,
+            ttl: self.ttl,
+            resource_data: self.resource_data.into_owned(),
+        }
+    }
+
     #[cfg(feature = "edns")]
-    pub fn get_edns_data(&self) -> Option<EdnsData> {
-        match self.resource_data.get_query_type() {
-            QueryType::OPT => {
+    pub fn get_edns_data(&self) -> Option<EdnsData<'_>> {
+        match &self.resource_data {
+            ResourceData::OPT { options } => {
                 let udp_payload_size = self.class as usize;
                 let ttl_bytes = self.ttl.to_be_bytes();
                 let extended_rcode = NonZero::new(ttl_bytes[0]);
@@ -47,6 +62,7 @@ impl<'a> ResourceRecord<'a> {
                     extended_rcode,
                     dnssec_ok_bit,
                     version,
+                    options,
                 })
             }
             _ => None,
@@ -54,8 +70,8 @@ impl<'a> ResourceRecord<'a> {
     }
 }
 
-impl FromBuf for ResourceRecord<'_> {
-    fn from_buf(buf: &mut ByteBuf<'_>) -> anyhow::Result<ResourceRecord<'static>> {
+impl<'a> FromBuf<'a> for ResourceRecord<'a> {
+    fn from_buf(buf: &mut ByteBuf<'a>) -> anyhow::Result<ResourceRecord<'a>> {
         let name = buf.read_qname().context("NAME is missing")?;
         let query_type: QueryType = buf.read_u16().context("TYPE is missing")?.into();
         let class = buf.read_u16().context("CLASS is missing")?;
@@ -77,13 +93,14 @@ impl<'a> EncodeToBuf for ResourceRecord<'a> {
     fn encode_to_buf_with_cache<'cache, 'r: 'cache>(
         &'r self,
         buf: &mut ByteBuf,
-        mut label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        mut label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
         max_size: Option<usize>,
     ) -> anyhow::Result<usize> {
         let encoded_size = self.get_encoded_size(label_cache.as_deref());
         if max_size.is_some_and(|max_size| encoded_size > max_size) {
             return Ok(0);
         }
+        let start = buf.len();
         buf.write_qname(&self.name, label_cache.as_deref_mut())
             .context("writing NAME")?;
         buf.write_u16(self.resource_data.get_query_type().into())
@@ -95,12 +112,13 @@ impl<'a> EncodeToBuf for ResourceRecord<'a> {
             .encode_to_buf_with_cache(buf, label_cache, max_size)
             .context("writing RDATA")?;
 
-        Ok(encoded_size)
+        // Compression can make the actual size smaller than the estimated one
+        Ok(buf.len() - start)
     }
 }
 
 impl EncodedSize for ResourceRecord<'_> {
-    fn get_encoded_size(&self, label_cache: Option<&HashMap<&str, usize>>) -> usize {
+    fn get_encoded_size(&self, label_cache: Option<&HashMap<&[u8], usize>>) -> usize {
         let qname_size = get_max_encoded_qname_size(&self.name, label_cache);
         qname_size + 2 /* TYPE */ + 2 /* CLASS */ + 4 /* TTL */ + self.resource_data.get_encoded_size(label_cache)
     }
@@ -108,12 +126,13 @@ impl EncodedSize for ResourceRecord<'_> {
 
 #[derive(Debug)]
 #[cfg(feature = "edns")]
-pub struct EdnsData {
+pub struct EdnsData<'a> {
     pub udp_payload_size: usize,
     pub extended_rcode: Option<NonZero<u8>>,
     /// Is set to `true` by DNSSEC-aware clients
     pub dnssec_ok_bit: bool,
     pub version: u8,
+    pub options: &'a [EdnsOption<'a>],
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -126,31 +145,373 @@ pub enum ResourceData<'a> {
         address: Ipv4Addr,
     },
     NS {
-        ns_domain_name: Cow<'a, str>,
+        ns_domain_name: DomainName<'a>,
     },
     CNAME {
-        cname: Cow<'a, str>,
+        cname: DomainName<'a>,
+    },
+    SOA {
+        /// Domain name of the primary source of data for this zone
+        mname: DomainName<'a>,
+        /// Mailbox of the person responsible for this zone
+        rname: DomainName<'a>,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        /// Used as the TTL for negative responses (RFC2308)
+        minimum: u32,
+    },
+    PTR {
+        ptr_domain_name: DomainName<'a>,
+    },
+    MX {
+        preference: u16,
+        exchange: DomainName<'a>,
+    },
+    TXT {
+        /// One or more `<character-string>`s, 255 bytes each at most
+        txt_data: Vec<Cow<'a, [u8]>>,
     },
     AAAA {
         address: Ipv6Addr,
     },
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName<'a>,
+    },
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: Cow<'a, [u8]>,
+        services: Cow<'a, [u8]>,
+        regexp: Cow<'a, [u8]>,
+        replacement: DomainName<'a>,
+    },
     #[cfg(feature = "edns")]
     OPT {
-        options: Option<HashMap<u16, Cow<'a, [u8]>>>,
+        options: Vec<EdnsOption<'a>>,
+    },
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Cow<'a, [u8]>,
+    },
+    SSHFP {
+        algorithm: u8,
+        fingerprint_type: u8,
+        fingerprint: Cow<'a, [u8]>,
+    },
+    RRSIG {
+        type_covered: QueryType,
+        algorithm: u8,
+        /// Number of labels in the original RRSIG RR owner name
+        labels: u8,
+        original_ttl: u32,
+        /// Seconds since the UNIX epoch (modulo 2^32, see RFC4034)
+        signature_expiration: u32,
+        /// Seconds since the UNIX epoch (modulo 2^32, see RFC4034)
+        signature_inception: u32,
+        key_tag: u16,
+        signer_name: DomainName<'a>,
+        signature: Cow<'a, [u8]>,
+    },
+    NSEC {
+        next_domain_name: DomainName<'a>,
+        type_bitmaps: Vec<QueryType>,
+    },
+    DNSKEY {
+        flags: u16,
+        /// Always 3 (RFC4034)
+        protocol: u8,
+        algorithm: u8,
+        public_key: Cow<'a, [u8]>,
+    },
+    NSEC3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Cow<'a, [u8]>,
+        next_hashed_owner_name: Cow<'a, [u8]>,
+        type_bitmaps: Vec<QueryType>,
+    },
+    NSEC3PARAM {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Cow<'a, [u8]>,
+    },
+    TLSA {
+        certificate_usage: u8,
+        selector: u8,
+        matching_type: u8,
+        certificate_association_data: Cow<'a, [u8]>,
+    },
+    SVCB {
+        /// 0 means AliasMode, while all other values represent ServiceMode
+        priority: u16,
+        target_name: DomainName<'a>,
+        params: Vec<SvcParam<'a>>,
+    },
+    /// Same as SVCB, but for HTTPS origins
+    HTTPS {
+        priority: u16,
+        target_name: DomainName<'a>,
+        params: Vec<SvcParam<'a>>,
+    },
+    CAA {
+        /// Bit 0 is the `Issuer Critical` flag, other bits are reserved
+        flags: u8,
+        /// Non-empty ASCII alphanumeric string, 15 characters at most
+        tag: Cow<'a, str>,
+        value: Cow<'a, [u8]>,
+    },
+    /// Transaction signature (RFC8945), which must be the last RR of a message
+    TSIG {
+        algorithm: DomainName<'a>,
+        /// Seconds since the UNIX epoch, 48 bits at most
+        time_signed: u64,
+        /// Permitted difference in seconds between `time_signed` and the verifier's clock
+        fudge: u16,
+        mac: Cow<'a, [u8]>,
+        original_id: u16,
+        /// TSIG error code, where 0 means no error
+        error: u16,
+        other_data: Cow<'a, [u8]>,
     },
 }
 
 impl<'a> ResourceData<'a> {
-    pub fn from_buf_with_type(buf: &mut ByteBuf<'a>, query_type: QueryType) -> anyhow::Result<ResourceData<'static>> {
+    pub fn into_owned(self) -> ResourceData<'static> {
+        let owned = |data: Cow<'_, [u8]>| Cow::Owned(data.into_owned());
+        match self {
+            ResourceData::UNKNOWN { qtype, rdata } => ResourceData::UNKNOWN {
+                qtype,
+                rdata: owned(rdata),
+            },
+            ResourceData::A { address } => ResourceData::A { address },
+            ResourceData::NS { ns_domain_name } => ResourceData::NS {
+                ns_domain_name: ns_domain_name.into_owned(),
+            },
+            ResourceData::CNAME { cname } => ResourceData::CNAME {
+                cname: cname.into_owned(),
+            },
+            ResourceData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => ResourceData::SOA {
+                mname: mname.into_owned(),
+                rname: rname.into_owned(),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            },
+            ResourceData::PTR { ptr_domain_name } => ResourceData::PTR {
+                ptr_domain_name: ptr_domain_name.into_owned(),
+            },
+            ResourceData::MX { preference, exchange } => ResourceData::MX {
+                preference,
+                exchange: exchange.into_owned(),
+            },
+            ResourceData::TXT { txt_data } => ResourceData::TXT {
+                txt_data: txt_data.into_iter().map(owned).collect(),
+            },
+            ResourceData::AAAA { address } => ResourceData::AAAA { address },
+            ResourceData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => ResourceData::SRV {
+                priority,
+                weight,
+                port,
+                target: target.into_owned(),
+            },
+            ResourceData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => ResourceData::NAPTR {
+                order,
+                preference,
+                flags: owned(flags),
+                services: owned(services),
+                regexp: owned(regexp),
+                replacement: replacement.into_owned(),
+            },
+            #[cfg(feature = "edns")]
+            ResourceData::OPT { options } => ResourceData::OPT {
+                options: options.into_iter().map(EdnsOption::into_owned).collect(),
+            },
+            ResourceData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => ResourceData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest: owned(digest),
+            },
+            ResourceData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => ResourceData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint: owned(fingerprint),
+            },
+            ResourceData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => ResourceData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name: signer_name.into_owned(),
+                signature: owned(signature),
+            },
+            ResourceData::NSEC {
+                next_domain_name,
+                type_bitmaps,
+            } => ResourceData::NSEC {
+                next_domain_name: next_domain_name.into_owned(),
+                type_bitmaps,
+            },
+            ResourceData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => ResourceData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key: owned(public_key),
+            },
+            ResourceData::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner_name,
+                type_bitmaps,
+            } => ResourceData::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt: owned(salt),
+                next_hashed_owner_name: owned(next_hashed_owner_name),
+                type_bitmaps,
+            },
+            ResourceData::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => ResourceData::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt: owned(salt),
+            },
+            ResourceData::TLSA {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_association_data,
+            } => ResourceData::TLSA {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_association_data: owned(certificate_association_data),
+            },
+            ResourceData::SVCB {
+                priority,
+                target_name,
+                params,
+            } => ResourceData::SVCB {
+                priority,
+                target_name: target_name.into_owned(),
+                params: params.into_iter().map(SvcParam::into_owned).collect(),
+            },
+            ResourceData::HTTPS {
+                priority,
+                target_name,
+                params,
+            } => ResourceData::HTTPS {
+                priority,
+                target_name: target_name.into_owned(),
+                params: params.into_iter().map(SvcParam::into_owned).collect(),
+            },
+            ResourceData::CAA { flags, tag, value } => ResourceData::CAA {
+                flags,
+                tag: Cow::Owned(tag.into_owned()),
+                value: owned(value),
+            },
+            ResourceData::TSIG {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other_data,
+            } => ResourceData::TSIG {
+                algorithm: algorithm.into_owned(),
+                time_signed,
+                fudge,
+                mac: owned(mac),
+                original_id,
+                error,
+                other_data: owned(other_data),
+            },
+        }
+    }
+
+    pub fn from_buf_with_type(buf: &mut ByteBuf<'a>, query_type: QueryType) -> anyhow::Result<ResourceData<'a>> {
         let rd_length = buf.read_u16().context("RDLENGTH is missing")?;
-        Ok(match query_type {
+        let rdata_start = buf.get_pos();
+        let remaining_rd_length = |buf: &ByteBuf| {
+            (rd_length as usize)
+                .checked_sub(buf.get_pos() - rdata_start)
+                .with_context(|| format!("{:?} record: RDATA exceeds RDLENGTH {}", query_type, rd_length))
+        };
+        let resource_data = match query_type {
             QueryType::UNKNOWN(query_type) => {
                 let data = buf
-                    .read_bytes(rd_length as usize)
+                    .read_cow_bytes(rd_length as usize)
                     .context("UNKNOWN record: RDATA is missing")?;
                 ResourceData::UNKNOWN {
                     qtype: query_type,
-                    rdata: data.to_vec().into(),
+                    rdata: data,
                 }
             }
             QueryType::A => {
@@ -169,6 +530,46 @@ impl<'a> ResourceData<'a> {
                 let cname = buf.read_qname().context("CNAME record: CNAME is missing")?;
                 ResourceData::CNAME { cname }
             }
+            QueryType::SOA => {
+                let mname = buf.read_qname().context("SOA record: MNAME is missing")?;
+                let rname = buf.read_qname().context("SOA record: RNAME is missing")?;
+                let serial = buf.read_u32().context("SOA record: SERIAL is missing")?;
+                let refresh = buf.read_u32().context("SOA record: REFRESH is missing")?;
+                let retry = buf.read_u32().context("SOA record: RETRY is missing")?;
+                let expire = buf.read_u32().context("SOA record: EXPIRE is missing")?;
+                let minimum = buf.read_u32().context("SOA record: MINIMUM is missing")?;
+                ResourceData::SOA {
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                }
+            }
+            QueryType::PTR => {
+                let ptr_domain_name = buf.read_qname().context("PTR record: PTRDNAME is missing")?;
+                ResourceData::PTR { ptr_domain_name }
+            }
+            QueryType::MX => {
+                let preference = buf.read_u16().context("MX record: PREFERENCE is missing")?;
+                let exchange = buf.read_qname().context("MX record: EXCHANGE is missing")?;
+                ResourceData::MX { preference, exchange }
+            }
+            QueryType::TXT => {
+                if rd_length == 0 {
+                    anyhow::bail!("TXT record: expected at least one character-string");
+                }
+                let mut txt_data = Vec::new();
+                while buf.get_pos() - rdata_start < rd_length as usize {
+                    let data = buf.read_cow_character_string().with_context(|| {
+                        format!("TXT record: character-string at idx {} is missing", txt_data.len())
+                    })?;
+                    txt_data.push(data);
+                }
+                ResourceData::TXT { txt_data }
+            }
             QueryType::AAAA => {
                 if rd_length != 16 {
                     anyhow::bail!("AAAA record: unexpected RDLENGTH {}", rd_length);
@@ -177,35 +578,243 @@ impl<'a> ResourceData<'a> {
                 let address = Ipv6Addr::from(TryInto::<[u8; 16]>::try_into(address_raw).unwrap());
                 ResourceData::AAAA { address }
             }
+            QueryType::SRV => {
+                let priority = buf.read_u16().context("SRV record: PRIORITY is missing")?;
+                let weight = buf.read_u16().context("SRV record: WEIGHT is missing")?;
+                let port = buf.read_u16().context("SRV record: PORT is missing")?;
+                let target = buf.read_qname().context("SRV record: TARGET is missing")?;
+                ResourceData::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                }
+            }
+            QueryType::NAPTR => {
+                let order = buf.read_u16().context("NAPTR record: ORDER is missing")?;
+                let preference = buf.read_u16().context("NAPTR record: PREFERENCE is missing")?;
+                let flags = buf
+                    .read_cow_character_string()
+                    .context("NAPTR record: FLAGS are missing")?;
+                let services = buf
+                    .read_cow_character_string()
+                    .context("NAPTR record: SERVICES are missing")?;
+                let regexp = buf
+                    .read_cow_character_string()
+                    .context("NAPTR record: REGEXP is missing")?;
+                let replacement = buf.read_qname().context("NAPTR record: REPLACEMENT is missing")?;
+                ResourceData::NAPTR {
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                }
+            }
             #[cfg(feature = "edns")]
             QueryType::OPT => {
-                let mut remaining_rd_length = rd_length;
-                let mut options: Option<HashMap<_, _>> = None;
-                while remaining_rd_length != 0 {
-                    let option = buf.read_u16().with_context(|| {
-                        format!(
-                            "OPT record: option code is missing at RDLENGTH offset {}",
-                            rd_length - remaining_rd_length
-                        )
-                    })?;
-                    let option_length = buf
-                        .read_u16()
-                        .with_context(|| format!("OPT record: option length is missing for option {}", option))?;
-                    let option_data = buf.read_bytes(option_length as usize).with_context(|| {
-                        format!(
-                            "OPT record: option data of length {} is missing for option {}",
-                            option_length, option
-                        )
-                    })?;
-                    options
-                        .get_or_insert_with(Default::default)
-                        .insert(option, option_data.to_vec().into());
-                    remaining_rd_length -= 4 + option_length;
-                }
+                let options = read_edns_options(buf, rd_length as usize).context("OPT record: reading options")?;
                 ResourceData::OPT { options }
             }
+            QueryType::DS => {
+                let key_tag = buf.read_u16().context("DS record: key tag is missing")?;
+                let algorithm = buf.read_u8().context("DS record: algorithm is missing")?;
+                let digest_type = buf.read_u8().context("DS record: digest type is missing")?;
+                let digest = buf
+                    .read_cow_bytes(remaining_rd_length(buf)?)
+                    .context("DS record: digest is missing")?;
+                ResourceData::DS {
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest,
+                }
+            }
+            QueryType::SSHFP => {
+                let algorithm = buf.read_u8().context("SSHFP record: algorithm is missing")?;
+                let fingerprint_type = buf.read_u8().context("SSHFP record: fingerprint type is missing")?;
+                let fingerprint = buf
+                    .read_cow_bytes(remaining_rd_length(buf)?)
+                    .context("SSHFP record: fingerprint is missing")?;
+                ResourceData::SSHFP {
+                    algorithm,
+                    fingerprint_type,
+                    fingerprint,
+                }
+            }
+            QueryType::RRSIG => {
+                let type_covered = buf.read_u16().context("RRSIG record: type covered is missing")?.into();
+                let algorithm = buf.read_u8().context("RRSIG record: algorithm is missing")?;
+                let labels = buf.read_u8().context("RRSIG record: labels are missing")?;
+                let original_ttl = buf.read_u32().context("RRSIG record: original TTL is missing")?;
+                let signature_expiration = buf
+                    .read_u32()
+                    .context("RRSIG record: signature expiration is missing")?;
+                let signature_inception = buf.read_u32().context("RRSIG record: signature inception is missing")?;
+                let key_tag = buf.read_u16().context("RRSIG record: key tag is missing")?;
+                let signer_name = buf.read_qname().context("RRSIG record: signer's name is missing")?;
+                let signature = buf
+                    .read_cow_bytes(remaining_rd_length(buf)?)
+                    .context("RRSIG record: signature is missing")?;
+                ResourceData::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    signature_expiration,
+                    signature_inception,
+                    key_tag,
+                    signer_name,
+                    signature,
+                }
+            }
+            QueryType::NSEC => {
+                let next_domain_name = buf.read_qname().context("NSEC record: next domain name is missing")?;
+                let type_bitmaps =
+                    read_type_bitmaps(buf, remaining_rd_length(buf)?).context("NSEC record: reading type bitmaps")?;
+                ResourceData::NSEC {
+                    next_domain_name,
+                    type_bitmaps,
+                }
+            }
+            QueryType::DNSKEY => {
+                let flags = buf.read_u16().context("DNSKEY record: flags are missing")?;
+                let protocol = buf.read_u8().context("DNSKEY record: protocol is missing")?;
+                let algorithm = buf.read_u8().context("DNSKEY record: algorithm is missing")?;
+                let public_key = buf
+                    .read_cow_bytes(remaining_rd_length(buf)?)
+                    .context("DNSKEY record: public key is missing")?;
+                ResourceData::DNSKEY {
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                }
+            }
+            QueryType::NSEC3 => {
+                let hash_algorithm = buf.read_u8().context("NSEC3 record: hash algorithm is missing")?;
+                let flags = buf.read_u8().context("NSEC3 record: flags are missing")?;
+                let iterations = buf.read_u16().context("NSEC3 record: iterations are missing")?;
+                let salt = buf
+                    .read_cow_character_string()
+                    .context("NSEC3 record: salt is missing")?;
+                let next_hashed_owner_name = buf
+                    .read_cow_character_string()
+                    .context("NSEC3 record: next hashed owner name is missing")?;
+                let type_bitmaps =
+                    read_type_bitmaps(buf, remaining_rd_length(buf)?).context("NSEC3 record: reading type bitmaps")?;
+                ResourceData::NSEC3 {
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed_owner_name,
+                    type_bitmaps,
+                }
+            }
+            QueryType::NSEC3PARAM => {
+                let hash_algorithm = buf.read_u8().context("NSEC3PARAM record: hash algorithm is missing")?;
+                let flags = buf.read_u8().context("NSEC3PARAM record: flags are missing")?;
+                let iterations = buf.read_u16().context("NSEC3PARAM record: iterations are missing")?;
+                let salt = buf
+                    .read_cow_character_string()
+                    .context("NSEC3PARAM record: salt is missing")?;
+                ResourceData::NSEC3PARAM {
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                }
+            }
+            QueryType::TLSA => {
+                let certificate_usage = buf.read_u8().context("TLSA record: certificate usage is missing")?;
+                let selector = buf.read_u8().context("TLSA record: selector is missing")?;
+                let matching_type = buf.read_u8().context("TLSA record: matching type is missing")?;
+                let certificate_association_data = buf
+                    .read_cow_bytes(remaining_rd_length(buf)?)
+                    .context("TLSA record: certificate association data is missing")?;
+                ResourceData::TLSA {
+                    certificate_usage,
+                    selector,
+                    matching_type,
+                    certificate_association_data,
+                }
+            }
+            QueryType::SVCB | QueryType::HTTPS => {
+                let priority = buf
+                    .read_u16()
+                    .with_context(|| format!("{:?} record: SvcPriority is missing", query_type))?;
+                let target_name = buf
+                    .read_qname()
+                    .with_context(|| format!("{:?} record: TargetName is missing", query_type))?;
+                let params = read_svc_params(buf, remaining_rd_length(buf)?)
+                    .with_context(|| format!("{:?} record: reading SvcParams", query_type))?;
+                if query_type == QueryType::SVCB {
+                    ResourceData::SVCB {
+                        priority,
+                        target_name,
+                        params,
+                    }
+                } else {
+                    ResourceData::HTTPS {
+                        priority,
+                        target_name,
+                        params,
+                    }
+                }
+            }
             QueryType::ANY => anyhow::bail!("ANY record doesn't exist"),
-        })
+            QueryType::CAA => {
+                let flags = buf.read_u8().context("CAA record: flags are missing")?;
+                let tag = buf.read_cow_character_string().context("CAA record: tag is missing")?;
+                validate_caa_tag(&tag)?;
+                // Tag is guaranteed to be ASCII after the validation
+                let tag = cow_bytes_into_str(tag).unwrap();
+                let value = buf
+                    .read_cow_bytes(remaining_rd_length(buf)?)
+                    .context("CAA record: value is missing")?;
+                ResourceData::CAA { flags, tag, value }
+            }
+            QueryType::TSIG => {
+                let algorithm = buf.read_qname().context("TSIG record: algorithm name is missing")?;
+                let time_signed_high = buf.read_u16().context("TSIG record: time signed is missing")?;
+                let time_signed_low = buf.read_u32().context("TSIG record: time signed is missing")?;
+                let fudge = buf.read_u16().context("TSIG record: fudge is missing")?;
+                let mac_size = buf.read_u16().context("TSIG record: MAC size is missing")?;
+                let mac = buf
+                    .read_cow_bytes(mac_size as usize)
+                    .context("TSIG record: MAC is missing")?;
+                let original_id = buf.read_u16().context("TSIG record: original ID is missing")?;
+                let error = buf.read_u16().context("TSIG record: error is missing")?;
+                let other_length = buf.read_u16().context("TSIG record: other length is missing")?;
+                let other_data = buf
+                    .read_cow_bytes(other_length as usize)
+                    .context("TSIG record: other data is missing")?;
+                ResourceData::TSIG {
+                    algorithm,
+                    time_signed: (time_signed_high as u64) << 32 | time_signed_low as u64,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other_data,
+                }
+            }
+        };
+
+        // Names inside RDATA can be compressed, so make sure that we consumed exactly RDLENGTH bytes
+        let consumed = buf.get_pos() - rdata_start;
+        if consumed != rd_length as usize {
+            anyhow::bail!(
+                "{:?} record: RDLENGTH is {}, but RDATA took {} bytes",
+                resource_data.get_query_type(),
+                rd_length,
+                consumed
+            );
+        }
+
+        Ok(resource_data)
     }
 
     pub fn get_query_type(&self) -> QueryType {
@@ -214,9 +823,64 @@ impl<'a> ResourceData<'a> {
             ResourceData::A { .. } => QueryType::A,
             ResourceData::NS { .. } => QueryType::NS,
             ResourceData::CNAME { .. } => QueryType::CNAME,
+            ResourceData::SOA { .. } => QueryType::SOA,
+            ResourceData::PTR { .. } => QueryType::PTR,
+            ResourceData::MX { .. } => QueryType::MX,
+            ResourceData::TXT { .. } => QueryType::TXT,
             ResourceData::AAAA { .. } => QueryType::AAAA,
+            ResourceData::SRV { .. } => QueryType::SRV,
+            ResourceData::NAPTR { .. } => QueryType::NAPTR,
             #[cfg(feature = "edns")]
             ResourceData::OPT { .. } => QueryType::OPT,
+            ResourceData::DS { .. } => QueryType::DS,
+            ResourceData::SSHFP { .. } => QueryType::SSHFP,
+            ResourceData::RRSIG { .. } => QueryType::RRSIG,
+            ResourceData::NSEC { .. } => QueryType::NSEC,
+            ResourceData::DNSKEY { .. } => QueryType::DNSKEY,
+            ResourceData::NSEC3 { .. } => QueryType::NSEC3,
+            ResourceData::NSEC3PARAM { .. } => QueryType::NSEC3PARAM,
+            ResourceData::TLSA { .. } => QueryType::TLSA,
+            ResourceData::SVCB { .. } => QueryType::SVCB,
+            ResourceData::HTTPS { .. } => QueryType::HTTPS,
+            ResourceData::CAA { .. } => QueryType::CAA,
+            ResourceData::TSIG { .. } => QueryType::TSIG,
+        }
+    }
+
+    /// Returns the key tag of DNSKEY, DS and RRSIG records.
+    ///
+    /// The key tag is calculated for DNSKEY records using the algorithm from RFC4034 (Appendix B).
+    pub fn get_key_tag(&self) -> Option<u16> {
+        match self {
+            ResourceData::DS { key_tag, .. } | ResourceData::RRSIG { key_tag, .. } => Some(*key_tag),
+            ResourceData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                // RSA/MD5 uses the most significant 16 bits of the least significant 24 bits of the modulus
+                if *algorithm == 1 {
+                    let len = public_key.len();
+                    return (len >= 3).then(|| u16::from_be_bytes([public_key[len - 3], public_key[len - 2]]));
+                }
+
+                let [flags_msb, flags_lsb] = flags.to_be_bytes();
+                let mut acc = [flags_msb, flags_lsb, *protocol, *algorithm]
+                    .iter()
+                    .chain(public_key.iter())
+                    .enumerate()
+                    .fold(0u32, |acc, (idx, byte)| {
+                        acc + if idx & 1 == 1 {
+                            *byte as u32
+                        } else {
+                            (*byte as u32) << 8
+                        }
+                    });
+                acc += (acc >> 16) & 0xffff;
+                Some((acc & 0xffff) as u16)
+            }
+            _ => None,
         }
     }
 }
@@ -225,16 +889,17 @@ impl<'a> EncodeToBuf for ResourceData<'a> {
     fn encode_to_buf_with_cache<'cache, 'r: 'cache>(
         &'r self,
         buf: &mut ByteBuf,
-        label_cache: Option<&mut HashMap<&'cache str, usize>>,
+        mut label_cache: Option<&mut HashMap<&'cache [u8], usize>>,
         max_size: Option<usize>,
     ) -> anyhow::Result<usize> {
         let encoded_size = self.get_encoded_size(label_cache.as_deref());
         if max_size.is_some_and(|max_size| encoded_size > max_size) {
             return Ok(0);
         }
+        let start = buf.len();
         match self {
             ResourceData::UNKNOWN { rdata: data, .. } => {
-                buf.write_u16(data.len() as u16)
+                buf.write_u16(get_u16_length(data.len())?)
                     .context("UNKNOWN record: writing RDLENGTH")?;
                 buf.write_bytes(data, None).context("UNKNOWN record: writing RDATA")?;
             }
@@ -267,54 +932,362 @@ impl<'a> EncodeToBuf for ResourceData<'a> {
                 buf.set_u16(rdata_pos, qname_length as u16)
                     .context("CNAME record: writing RDLENGTH")?;
             }
+            ResourceData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("SOA record: writing stub RDLENGTH")?;
+                let mname_length = buf
+                    .write_qname(mname, label_cache.as_deref_mut())
+                    .context("SOA record: writing MNAME")?;
+                let rname_length = buf
+                    .write_qname(rname, label_cache)
+                    .context("SOA record: writing RNAME")?;
+                [serial, refresh, retry, expire, minimum]
+                    .into_iter()
+                    .try_for_each(|value| buf.write_u32(*value))
+                    .context("SOA record: writing timers")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, (mname_length + rname_length + 5 * 4) as u16)
+                    .context("SOA record: writing RDLENGTH")?;
+            }
+            ResourceData::PTR { ptr_domain_name } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("PTR record: writing stub RDLENGTH")?;
+                let qname_length = buf
+                    .write_qname(ptr_domain_name, label_cache)
+                    .context("PTR record: writing PTRDNAME")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, qname_length as u16)
+                    .context("PTR record: writing RDLENGTH")?;
+            }
+            ResourceData::MX { preference, exchange } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("MX record: writing stub RDLENGTH")?;
+                buf.write_u16(*preference).context("MX record: writing PREFERENCE")?;
+                let qname_length = buf
+                    .write_qname(exchange, label_cache)
+                    .context("MX record: writing EXCHANGE")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, (2 + qname_length) as u16)
+                    .context("MX record: writing RDLENGTH")?;
+            }
+            ResourceData::TXT { txt_data } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("TXT record: writing stub RDLENGTH")?;
+                let mut rd_length = 0;
+                for (idx, data) in txt_data.iter().enumerate() {
+                    rd_length += buf
+                        .write_character_string(data)
+                        .with_context(|| format!("TXT record: writing character-string at idx {}", idx))?;
+                }
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, get_u16_length(rd_length)?)
+                    .context("TXT record: writing RDLENGTH")?;
+            }
             ResourceData::AAAA { address } => {
                 buf.write_u16(16).context("AAAA record: writing RDLENGTH")?;
                 buf.write_bytes(&address.octets(), None)
                     .context("AAAA record: writing ADDRESS")?;
             }
+            ResourceData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("SRV record: writing stub RDLENGTH")?;
+                buf.write_u16(*priority).context("SRV record: writing PRIORITY")?;
+                buf.write_u16(*weight).context("SRV record: writing WEIGHT")?;
+                buf.write_u16(*port).context("SRV record: writing PORT")?;
+                // RFC2782: name compression is not to be used for this field
+                let qname_length = buf.write_qname(target, None).context("SRV record: writing TARGET")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, (6 + qname_length) as u16)
+                    .context("SRV record: writing RDLENGTH")?;
+            }
+            ResourceData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("NAPTR record: writing stub RDLENGTH")?;
+                buf.write_u16(*order).context("NAPTR record: writing ORDER")?;
+                buf.write_u16(*preference).context("NAPTR record: writing PREFERENCE")?;
+                let flags_length = buf
+                    .write_character_string(flags)
+                    .context("NAPTR record: writing FLAGS")?;
+                let services_length = buf
+                    .write_character_string(services)
+                    .context("NAPTR record: writing SERVICES")?;
+                let regexp_length = buf
+                    .write_character_string(regexp)
+                    .context("NAPTR record: writing REGEXP")?;
+                // RFC3403: name compression is not to be used for this field
+                let qname_length = buf
+                    .write_qname(replacement, None)
+                    .context("NAPTR record: writing REPLACEMENT")?;
+                // Set actual RDLENGTH
+                buf.set_u16(
+                    rdata_pos,
+                    (4 + flags_length + services_length + regexp_length + qname_length) as u16,
+                )
+                .context("NAPTR record: writing RDLENGTH")?;
+            }
             #[cfg(feature = "edns")]
             ResourceData::OPT { options } => {
                 let rdata_pos = buf.len();
                 // We don't know how many bytes options will take in advance,
                 // so we can just write a stub value and replace it later
                 buf.write_u16(0).context("OPT record: writing stub RDLENGTH")?;
-
-                let mut rd_length = 0;
-                if let Some(options) = options {
-                    options
-                        .iter()
-                        .try_for_each(|(&option_code, option_data)| {
-                            buf.write_u16(option_code).with_context(|| {
-                                format!("OPT record: error while writing option code {}", option_code)
-                            })?;
-                            buf.write_u16(option_data.len() as u16).with_context(|| {
-                                format!(
-                                    "OPT record: error while writing option length for option {}",
-                                    option_code
-                                )
-                            })?;
-                            buf.write_bytes(option_data, None).with_context(|| {
-                                format!("OPT record: error while writing option data for option {}", option_code)
-                            })?;
-                            rd_length += 4 + option_data.len();
-
-                            anyhow::Result::<()>::Ok(())
-                        })
-                        .context("OPT record: writing options")?;
-                }
-
+                let rd_length = write_edns_options(buf, options).context("OPT record: writing options")?;
                 // Set actual RDLENGTH
-                buf.set_u16(rdata_pos, rd_length as u16)
+                buf.set_u16(rdata_pos, get_u16_length(rd_length)?)
                     .context("OPT record: writing RDLENGTH")?;
             }
+            ResourceData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                buf.write_u16(get_u16_length(4 + digest.len())?)
+                    .context("DS record: writing RDLENGTH")?;
+                buf.write_u16(*key_tag).context("DS record: writing key tag")?;
+                buf.write_u8(*algorithm);
+                buf.write_u8(*digest_type);
+                buf.write_bytes(digest, None).context("DS record: writing digest")?;
+            }
+            ResourceData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => {
+                buf.write_u16(get_u16_length(2 + fingerprint.len())?)
+                    .context("SSHFP record: writing RDLENGTH")?;
+                buf.write_u8(*algorithm);
+                buf.write_u8(*fingerprint_type);
+                buf.write_bytes(fingerprint, None)
+                    .context("SSHFP record: writing fingerprint")?;
+            }
+            ResourceData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("RRSIG record: writing stub RDLENGTH")?;
+                buf.write_u16((*type_covered).into())
+                    .context("RRSIG record: writing type covered")?;
+                buf.write_u8(*algorithm);
+                buf.write_u8(*labels);
+                buf.write_u32(*original_ttl)
+                    .context("RRSIG record: writing original TTL")?;
+                buf.write_u32(*signature_expiration)
+                    .context("RRSIG record: writing signature expiration")?;
+                buf.write_u32(*signature_inception)
+                    .context("RRSIG record: writing signature inception")?;
+                buf.write_u16(*key_tag).context("RRSIG record: writing key tag")?;
+                // RFC4034: signer's name must not be compressed
+                let qname_length = buf
+                    .write_qname(signer_name, None)
+                    .context("RRSIG record: writing signer's name")?;
+                buf.write_bytes(signature, None)
+                    .context("RRSIG record: writing signature")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, get_u16_length(18 + qname_length + signature.len())?)
+                    .context("RRSIG record: writing RDLENGTH")?;
+            }
+            ResourceData::NSEC {
+                next_domain_name,
+                type_bitmaps,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("NSEC record: writing stub RDLENGTH")?;
+                // RFC4034: next domain name must not be compressed
+                let qname_length = buf
+                    .write_qname(next_domain_name, None)
+                    .context("NSEC record: writing next domain name")?;
+                let type_bitmaps_length =
+                    write_type_bitmaps(buf, type_bitmaps).context("NSEC record: writing type bitmaps")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, (qname_length + type_bitmaps_length) as u16)
+                    .context("NSEC record: writing RDLENGTH")?;
+            }
+            ResourceData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                buf.write_u16(get_u16_length(4 + public_key.len())?)
+                    .context("DNSKEY record: writing RDLENGTH")?;
+                buf.write_u16(*flags).context("DNSKEY record: writing flags")?;
+                buf.write_u8(*protocol);
+                buf.write_u8(*algorithm);
+                buf.write_bytes(public_key, None)
+                    .context("DNSKEY record: writing public key")?;
+            }
+            ResourceData::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner_name,
+                type_bitmaps,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("NSEC3 record: writing stub RDLENGTH")?;
+                buf.write_u8(*hash_algorithm);
+                buf.write_u8(*flags);
+                buf.write_u16(*iterations).context("NSEC3 record: writing iterations")?;
+                let salt_length = buf.write_character_string(salt).context("NSEC3 record: writing salt")?;
+                let next_hashed_owner_name_length = buf
+                    .write_character_string(next_hashed_owner_name)
+                    .context("NSEC3 record: writing next hashed owner name")?;
+                let type_bitmaps_length =
+                    write_type_bitmaps(buf, type_bitmaps).context("NSEC3 record: writing type bitmaps")?;
+                // Set actual RDLENGTH
+                buf.set_u16(
+                    rdata_pos,
+                    (4 + salt_length + next_hashed_owner_name_length + type_bitmaps_length) as u16,
+                )
+                .context("NSEC3 record: writing RDLENGTH")?;
+            }
+            ResourceData::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => {
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("NSEC3PARAM record: writing stub RDLENGTH")?;
+                buf.write_u8(*hash_algorithm);
+                buf.write_u8(*flags);
+                buf.write_u16(*iterations)
+                    .context("NSEC3PARAM record: writing iterations")?;
+                let salt_length = buf
+                    .write_character_string(salt)
+                    .context("NSEC3PARAM record: writing salt")?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, (4 + salt_length) as u16)
+                    .context("NSEC3PARAM record: writing RDLENGTH")?;
+            }
+            ResourceData::TLSA {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_association_data,
+            } => {
+                buf.write_u16(get_u16_length(3 + certificate_association_data.len())?)
+                    .context("TLSA record: writing RDLENGTH")?;
+                buf.write_u8(*certificate_usage);
+                buf.write_u8(*selector);
+                buf.write_u8(*matching_type);
+                buf.write_bytes(certificate_association_data, None)
+                    .context("TLSA record: writing certificate association data")?;
+            }
+            ResourceData::SVCB {
+                priority,
+                target_name,
+                params,
+            }
+            | ResourceData::HTTPS {
+                priority,
+                target_name,
+                params,
+            } => {
+                let qtype = self.get_query_type();
+                let rdata_pos = buf.len();
+                buf.write_u16(0)
+                    .with_context(|| format!("{:?} record: writing stub RDLENGTH", qtype))?;
+                buf.write_u16(*priority)
+                    .with_context(|| format!("{:?} record: writing SvcPriority", qtype))?;
+                // RFC9460: TargetName must not be compressed
+                let qname_length = buf
+                    .write_qname(target_name, None)
+                    .with_context(|| format!("{:?} record: writing TargetName", qtype))?;
+                let params_length =
+                    write_svc_params(buf, params).with_context(|| format!("{:?} record: writing SvcParams", qtype))?;
+                // Set actual RDLENGTH
+                buf.set_u16(rdata_pos, get_u16_length(2 + qname_length + params_length)?)
+                    .with_context(|| format!("{:?} record: writing RDLENGTH", qtype))?;
+            }
+            ResourceData::CAA { flags, tag, value } => {
+                validate_caa_tag(tag.as_bytes())?;
+                buf.write_u16(get_u16_length(2 + tag.len() + value.len())?)
+                    .context("CAA record: writing RDLENGTH")?;
+                buf.write_u8(*flags);
+                buf.write_character_string(tag.as_bytes())
+                    .context("CAA record: writing tag")?;
+                buf.write_bytes(value, None).context("CAA record: writing value")?;
+            }
+            ResourceData::TSIG {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other_data,
+            } => {
+                if *time_signed >> 48 != 0 {
+                    anyhow::bail!("TSIG record: time signed {} doesn't fit into 48 bits", time_signed);
+                }
+                let rdata_pos = buf.len();
+                buf.write_u16(0).context("TSIG record: writing stub RDLENGTH")?;
+                // RFC8945: algorithm name must not be compressed
+                let qname_length = buf
+                    .write_qname(algorithm, None)
+                    .context("TSIG record: writing algorithm name")?;
+                buf.write_u16((time_signed >> 32) as u16)
+                    .context("TSIG record: writing time signed")?;
+                buf.write_u32(*time_signed as u32)
+                    .context("TSIG record: writing time signed")?;
+                buf.write_u16(*fudge).context("TSIG record: writing fudge")?;
+                buf.write_u16(get_u16_length(mac.len())?)
+                    .context("TSIG record: writing MAC size")?;
+                buf.write_bytes(mac, None).context("TSIG record: writing MAC")?;
+                buf.write_u16(*original_id)
+                    .context("TSIG record: writing original ID")?;
+                buf.write_u16(*error).context("TSIG record: writing error")?;
+                buf.write_u16(get_u16_length(other_data.len())?)
+                    .context("TSIG record: writing other length")?;
+                buf.write_bytes(other_data, None)
+                    .context("TSIG record: writing other data")?;
+                // Set actual RDLENGTH
+                buf.set_u16(
+                    rdata_pos,
+                    get_u16_length(qname_length + 16 + mac.len() + other_data.len())?,
+                )
+                .context("TSIG record: writing RDLENGTH")?;
+            }
         };
 
-        Ok(encoded_size)
+        // Compression can make the actual size smaller than the estimated one
+        Ok(buf.len() - start)
     }
 }
 
 impl EncodedSize for ResourceData<'_> {
-    fn get_encoded_size(&self, label_cache: Option<&HashMap<&str, usize>>) -> usize {
+    fn get_encoded_size(&self, label_cache: Option<&HashMap<&[u8], usize>>) -> usize {
         let mut size = 2 /* RDLENGTH */;
         match self {
             ResourceData::UNKNOWN { rdata, .. } => {
@@ -329,22 +1302,130 @@ impl EncodedSize for ResourceData<'_> {
             ResourceData::CNAME { cname } => {
                 size += get_max_encoded_qname_size(cname, label_cache);
             }
+            ResourceData::SOA { mname, rname, .. } => {
+                size += get_max_encoded_qname_size(mname, label_cache)
+                    + get_max_encoded_qname_size(rname, label_cache)
+                    + 5 * 4 /* SERIAL, REFRESH, RETRY, EXPIRE and MINIMUM */;
+            }
+            ResourceData::PTR { ptr_domain_name } => {
+                size += get_max_encoded_qname_size(ptr_domain_name, label_cache);
+            }
+            ResourceData::MX { exchange, .. } => {
+                size += 2 /* PREFERENCE */ + get_max_encoded_qname_size(exchange, label_cache);
+            }
+            ResourceData::TXT { txt_data } => {
+                size += txt_data.iter().map(|data| 1 /* length */ + data.len()).sum::<usize>();
+            }
             ResourceData::AAAA { .. } => {
                 size += 16 /* Ipv6Addr */;
             }
+            ResourceData::SRV { target, .. } => {
+                // TARGET is never compressed
+                size += 2 /* PRIORITY */ + 2 /* WEIGHT */ + 2 /* PORT */ + get_max_encoded_qname_size(target, None);
+            }
+            ResourceData::NAPTR {
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => {
+                // REPLACEMENT is never compressed
+                size += 2 /* ORDER */ + 2 /* PREFERENCE */
+                    + 1 /* FLAGS length */ + flags.len()
+                    + 1 /* SERVICES length */ + services.len()
+                    + 1 /* REGEXP length */ + regexp.len()
+                    + get_max_encoded_qname_size(replacement, None);
+            }
             #[cfg(feature = "edns")]
             ResourceData::OPT { options } => {
-                options.iter().for_each(|options| {
-                    options.values().for_each(|option| {
-                        size += 2 /* option code */ + 2 /* option length */ + option.len();
-                    })
-                });
+                size += get_edns_options_size(options);
+            }
+            ResourceData::DS { digest, .. } => {
+                size += 2 /* key tag */ + 1 /* algorithm */ + 1 /* digest type */ + digest.len();
+            }
+            ResourceData::SSHFP { fingerprint, .. } => {
+                size += 1 /* algorithm */ + 1 /* fingerprint type */ + fingerprint.len();
+            }
+            ResourceData::RRSIG {
+                signer_name, signature, ..
+            } => {
+                // Signer's name is never compressed
+                size += 18 /* fixed-size fields */ + get_max_encoded_qname_size(signer_name, None) + signature.len();
+            }
+            ResourceData::NSEC {
+                next_domain_name,
+                type_bitmaps,
+            } => {
+                // Next domain name is never compressed
+                size += get_max_encoded_qname_size(next_domain_name, None) + get_type_bitmaps_size(type_bitmaps);
+            }
+            ResourceData::DNSKEY { public_key, .. } => {
+                size += 2 /* flags */ + 1 /* protocol */ + 1 /* algorithm */ + public_key.len();
+            }
+            ResourceData::NSEC3 {
+                salt,
+                next_hashed_owner_name,
+                type_bitmaps,
+                ..
+            } => {
+                size += 1 /* hash algorithm */ + 1 /* flags */ + 2 /* iterations */
+                    + 1 /* salt length */ + salt.len()
+                    + 1 /* hash length */ + next_hashed_owner_name.len()
+                    + get_type_bitmaps_size(type_bitmaps);
+            }
+            ResourceData::NSEC3PARAM { salt, .. } => {
+                size += 1 /* hash algorithm */ + 1 /* flags */ + 2 /* iterations */ + 1 /* salt length */ + salt.len();
+            }
+            ResourceData::TLSA {
+                certificate_association_data,
+                ..
+            } => {
+                size += 1 /* certificate usage */ + 1 /* selector */ + 1 /* matching type */
+                    + certificate_association_data.len();
+            }
+            ResourceData::SVCB {
+                target_name, params, ..
+            }
+            | ResourceData::HTTPS {
+                target_name, params, ..
+            } => {
+                // TargetName is never compressed
+                size +=
+                    2 /* SvcPriority */ + get_max_encoded_qname_size(target_name, None) + get_svc_params_size(params);
+            }
+            ResourceData::CAA { tag, value, .. } => {
+                size += 1 /* flags */ + 1 /* tag length */ + tag.len() + value.len();
+            }
+            ResourceData::TSIG {
+                algorithm,
+                mac,
+                other_data,
+                ..
+            } => {
+                // Algorithm name is never compressed
+                size += get_max_encoded_qname_size(algorithm, None)
+                    + 6 /* time signed */ + 2 /* fudge */
+                    + 2 /* MAC size */ + mac.len()
+                    + 2 /* original ID */ + 2 /* error */
+                    + 2 /* other length */ + other_data.len();
             }
         }
         size
     }
 }
 
+/// RFC8659: tags are non-empty ASCII alphanumeric strings of 15 characters at most
+pub(crate) fn validate_caa_tag(tag: &[u8]) -> anyhow::Result<()> {
+    if tag.is_empty() || tag.len() > 15 {
+        anyhow::bail!("CAA record: unexpected tag length {}", tag.len());
+    }
+    if !tag.iter().all(u8::is_ascii_alphanumeric) {
+        anyhow::bail!("CAA record: tag must be alphanumeric");
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use proptest::prelude::*;
@@ -372,4 +1453,75 @@ mod tests {
             prop_assert_eq!(resource_record, roundtripped_rr, "ResourceRecord roundtrip test failed");
         }
     }
+
+    #[test]
+    fn mx_record_with_compressed_exchange() {
+        // 'google.com' that is referenced by the jump PTR, followed by MX RDATA
+        let data = &[
+            0x6, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x3, 0x63, 0x6f, 0x6d, 0x0, /* RDLENGTH */ 0x0, 0x4,
+            /* PREFERENCE */ 0x0, 0xa, /* EXCHANGE */ 0xc0, 0x0,
+        ];
+        let mut buf = ByteBuf::new(data);
+        buf.read_qname().expect("shouldn't have failed");
+        let resource_data = ResourceData::from_buf_with_type(&mut buf, QueryType::MX).expect("shouldn't have failed");
+        assert_eq!(
+            resource_data,
+            ResourceData::MX {
+                preference: 10,
+                exchange: "google.com".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn dnskey_key_tag() {
+        let dnskey = ResourceData::DNSKEY {
+            flags: 257,
+            protocol: 3,
+            algorithm: 8,
+            public_key: (1..=16).collect::<Vec<u8>>().into(),
+        };
+        assert_eq!(dnskey.get_key_tag(), Some(17489));
+    }
+
+    #[test]
+    fn caa_record() {
+        // 0 issue "ca.example.net"
+        let data = &[
+            /* RDLENGTH */ 0x0, 0x15, /* flags */ 0x0, /* tag */ 0x5, 0x69, 0x73, 0x73, 0x75, 0x65,
+            /* value */ 0x63, 0x61, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x6e, 0x65, 0x74,
+        ];
+        let mut buf = ByteBuf::new(data);
+        let resource_data = ResourceData::from_buf_with_type(&mut buf, QueryType::CAA).expect("shouldn't have failed");
+        assert_eq!(
+            resource_data,
+            ResourceData::CAA {
+                flags: 0,
+                tag: "issue".into(),
+                value: b"ca.example.net".as_slice().into()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "CAA record: tag must be alphanumeric")]
+    fn caa_record_with_invalid_tag() {
+        let resource_data = ResourceData::CAA {
+            flags: 0,
+            tag: "is-sue".into(),
+            value: b"ca.example.net".as_slice().into(),
+        };
+        let mut buf = ByteBuf::new_empty(None);
+        resource_data.encode_to_buf(&mut buf, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "RDLENGTH is 6, but RDATA took 5 bytes")]
+    fn rdata_with_wrong_rdlength() {
+        let data = &[
+            /* RDLENGTH */ 0x0, 0x6, /* PREFERENCE */ 0x0, 0xa, /* EXCHANGE */ 0x1, 0x61, 0x0, 0x0,
+        ];
+        let mut buf = ByteBuf::new(data);
+        ResourceData::from_buf_with_type(&mut buf, QueryType::MX).unwrap();
+    }
 }
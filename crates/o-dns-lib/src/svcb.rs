@@ -0,0 +1,326 @@
+use std::borrow::Cow;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::Context;
+
+use crate::utils::get_u16_length;
+use crate::ByteBuf;
+
+/// A single SvcParam of SVCB/HTTPS records as defined in RFC9460
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SvcParam<'a> {
+    /// Keys that must be supported by the client in order to use this record
+    Mandatory {
+        keys: Vec<u16>,
+    },
+    /// Additional supported protocols
+    Alpn {
+        protocols: Vec<Cow<'a, [u8]>>,
+    },
+    /// No support for the default protocol
+    NoDefaultAlpn,
+    /// Port for alternative endpoint
+    Port {
+        port: u16,
+    },
+    Ipv4Hint {
+        addresses: Vec<Ipv4Addr>,
+    },
+    /// Encrypted ClientHello configuration
+    Ech {
+        config: Cow<'a, [u8]>,
+    },
+    Ipv6Hint {
+        addresses: Vec<Ipv6Addr>,
+    },
+    /// Any other key that we don't handle
+    Unknown {
+        key: u16,
+        value: Cow<'a, [u8]>,
+    },
+}
+
+impl SvcParam<'_> {
+    pub fn get_key(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory { .. } => 0,
+            SvcParam::Alpn { .. } => 1,
+            SvcParam::NoDefaultAlpn => 2,
+            SvcParam::Port { .. } => 3,
+            SvcParam::Ipv4Hint { .. } => 4,
+            SvcParam::Ech { .. } => 5,
+            SvcParam::Ipv6Hint { .. } => 6,
+            SvcParam::Unknown { key, .. } => *key,
+        }
+    }
+
+    pub fn into_owned(self) -> SvcParam<'static> {
+        match self {
+            SvcParam::Mandatory { keys } => SvcParam::Mandatory { keys },
+            SvcParam::Alpn { protocols } => SvcParam::Alpn {
+                protocols: protocols
+                    .into_iter()
+                    .map(|protocol| Cow::Owned(protocol.into_owned()))
+                    .collect(),
+            },
+            SvcParam::NoDefaultAlpn => SvcParam::NoDefaultAlpn,
+            SvcParam::Port { port } => SvcParam::Port { port },
+            SvcParam::Ipv4Hint { addresses } => SvcParam::Ipv4Hint { addresses },
+            SvcParam::Ech { config } => SvcParam::Ech {
+                config: Cow::Owned(config.into_owned()),
+            },
+            SvcParam::Ipv6Hint { addresses } => SvcParam::Ipv6Hint { addresses },
+            SvcParam::Unknown { key, value } => SvcParam::Unknown {
+                key,
+                value: Cow::Owned(value.into_owned()),
+            },
+        }
+    }
+
+    /// Checks the constraints of RFC9460 (section 7) that both read and written values must meet
+    fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            SvcParam::Mandatory { keys } if keys.is_empty() => anyhow::bail!("'mandatory' can't be empty"),
+            SvcParam::Alpn { protocols } if protocols.is_empty() => anyhow::bail!("'alpn' can't be empty"),
+            SvcParam::Alpn { protocols } if protocols.iter().any(|protocol| protocol.is_empty()) => {
+                anyhow::bail!("'alpn' contains an empty protocol ID")
+            }
+            SvcParam::Ipv4Hint { addresses } if addresses.is_empty() => anyhow::bail!("'ipv4hint' can't be empty"),
+            SvcParam::Ipv6Hint { addresses } if addresses.is_empty() => anyhow::bail!("'ipv6hint' can't be empty"),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn get_value_length(&self) -> usize {
+        match self {
+            SvcParam::Mandatory { keys } => keys.len() * 2,
+            SvcParam::Alpn { protocols } => protocols.iter().map(|protocol| 1 + protocol.len()).sum(),
+            SvcParam::NoDefaultAlpn => 0,
+            SvcParam::Port { .. } => 2,
+            SvcParam::Ipv4Hint { addresses } => addresses.len() * 4,
+            SvcParam::Ech { config } => config.len(),
+            SvcParam::Ipv6Hint { addresses } => addresses.len() * 16,
+            SvcParam::Unknown { value, .. } => value.len(),
+        }
+    }
+}
+
+/// Reads SvcParams that take exactly `length` bytes
+pub(crate) fn read_svc_params<'a>(buf: &mut ByteBuf<'a>, length: usize) -> anyhow::Result<Vec<SvcParam<'a>>> {
+    let mut params = Vec::new();
+    let mut remaining_length = length;
+    let mut previous_key = None;
+    while remaining_length != 0 {
+        let key = buf.read_u16().context("SvcParams: key is missing")?;
+        // RFC9460: SvcParamKeys should appear in strictly increasing numeric order
+        if previous_key.is_some_and(|previous_key| key <= previous_key) {
+            anyhow::bail!("SvcParams: key {} is out of order", key);
+        }
+        previous_key = Some(key);
+
+        let value_length = buf
+            .read_u16()
+            .with_context(|| format!("SvcParams: value length is missing for key {}", key))?;
+        remaining_length = remaining_length
+            .checked_sub(4 + value_length as usize)
+            .with_context(|| format!("SvcParams: value for key {} exceeds RDLENGTH", key))?;
+        let value = buf
+            .read_cow_bytes(value_length as usize)
+            .with_context(|| format!("SvcParams: value of length {} is missing for key {}", value_length, key))?;
+
+        let param = parse_svc_param_value(key, value)
+            .and_then(|param| param.validate().map(|_| param))
+            .with_context(|| format!("SvcParams: invalid key {}", key))?;
+        params.push(param);
+    }
+
+    Ok(params)
+}
+
+fn parse_svc_param_value(key: u16, value: Cow<'_, [u8]>) -> anyhow::Result<SvcParam<'_>> {
+    Ok(match key {
+        0 => {
+            if !value.len().is_multiple_of(2) {
+                anyhow::bail!("unexpected 'mandatory' length {}", value.len());
+            }
+            let keys = value
+                .chunks_exact(2)
+                .map(|key| u16::from_be_bytes([key[0], key[1]]))
+                .collect();
+            SvcParam::Mandatory { keys }
+        }
+        1 => {
+            let mut protocols = Vec::new();
+            let value_length = value.len();
+            let mut value_buf = ByteBuf::new_from_cow(value);
+            while value_buf.get_pos() < value_length {
+                let protocol = value_buf.read_cow_character_string().context("malformed 'alpn'")?;
+                protocols.push(protocol);
+            }
+            SvcParam::Alpn { protocols }
+        }
+        2 => {
+            if !value.is_empty() {
+                anyhow::bail!("'no-default-alpn' must have an empty value");
+            }
+            SvcParam::NoDefaultAlpn
+        }
+        3 => {
+            let port = TryInto::<[u8; 2]>::try_into(value.as_ref())
+                .map_err(|_| anyhow::anyhow!("unexpected 'port' length {}", value.len()))?;
+            SvcParam::Port {
+                port: u16::from_be_bytes(port),
+            }
+        }
+        4 => {
+            if !value.len().is_multiple_of(4) {
+                anyhow::bail!("unexpected 'ipv4hint' length {}", value.len());
+            }
+            let addresses = value
+                .chunks_exact(4)
+                .map(|address| Ipv4Addr::from(TryInto::<[u8; 4]>::try_into(address).unwrap()))
+                .collect();
+            SvcParam::Ipv4Hint { addresses }
+        }
+        5 => SvcParam::Ech { config: value },
+        6 => {
+            if !value.len().is_multiple_of(16) {
+                anyhow::bail!("unexpected 'ipv6hint' length {}", value.len());
+            }
+            let addresses = value
+                .chunks_exact(16)
+                .map(|address| Ipv6Addr::from(TryInto::<[u8; 16]>::try_into(address).unwrap()))
+                .collect();
+            SvcParam::Ipv6Hint { addresses }
+        }
+        key => SvcParam::Unknown { key, value },
+    })
+}
+
+pub(crate) fn write_svc_params(buf: &mut ByteBuf, params: &[SvcParam<'_>]) -> anyhow::Result<usize> {
+    let mut written = 0;
+    let mut previous_key = None;
+    for param in params {
+        let key = param.get_key();
+        if previous_key.is_some_and(|previous_key| key <= previous_key) {
+            anyhow::bail!("SvcParams: key {} is out of order", key);
+        }
+        previous_key = Some(key);
+        param
+            .validate()
+            .with_context(|| format!("SvcParams: invalid key {}", key))?;
+
+        let value_length = param.get_value_length();
+        buf.write_u16(key)
+            .with_context(|| format!("SvcParams: writing key {}", key))?;
+        let encoded_length = get_u16_length(value_length)
+            .with_context(|| format!("SvcParams: value for key {} is too long ({})", key, value_length))?;
+        buf.write_u16(encoded_length)
+            .with_context(|| format!("SvcParams: writing value length for key {}", key))?;
+        match param {
+            SvcParam::Mandatory { keys } => keys.iter().try_for_each(|key| buf.write_u16(*key))?,
+            SvcParam::Alpn { protocols } => {
+                for protocol in protocols {
+                    buf.write_character_string(protocol)
+                        .context("SvcParams: writing 'alpn'")?;
+                }
+            }
+            SvcParam::NoDefaultAlpn => {}
+            SvcParam::Port { port } => buf.write_u16(*port)?,
+            SvcParam::Ipv4Hint { addresses } => addresses
+                .iter()
+                .try_for_each(|address| buf.write_bytes(&address.octets(), None))?,
+            SvcParam::Ech { config } => buf.write_bytes(config, None)?,
+            SvcParam::Ipv6Hint { addresses } => addresses
+                .iter()
+                .try_for_each(|address| buf.write_bytes(&address.octets(), None))?,
+            SvcParam::Unknown { value, .. } => buf.write_bytes(value, None)?,
+        }
+        written += 4 + value_length;
+    }
+
+    Ok(written)
+}
+
+pub(crate) fn get_svc_params_size(params: &[SvcParam<'_>]) -> usize {
+    params
+        .iter()
+        .map(|param| 2 /* key */ + 2 /* value length */ + param.get_value_length())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QueryType, ResourceData};
+
+    #[test]
+    fn read_rfc9460_service_mode_record() {
+        // Taken from RFC9460 (Appendix D.2, figure 5)
+        let data = &[
+            /* RDLENGTH */ 0x00, 0x30, /* SvcPriority */ 0x00, 0x10, /* TargetName */ 0x03, 0x66, 0x6f,
+            0x6f, 0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x6f, 0x72, 0x67, 0x00,
+            /* mandatory */ 0x00, 0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x04, /* alpn */ 0x00, 0x01, 0x00,
+            0x09, 0x02, 0x68, 0x32, 0x05, 0x68, 0x33, 0x2d, 0x31, 0x39, /* ipv4hint */ 0x00, 0x04, 0x00, 0x04,
+            0xc0, 0x00, 0x02, 0x01,
+        ];
+        let mut buf = ByteBuf::new(data);
+        let resource_data =
+            ResourceData::from_buf_with_type(&mut buf, QueryType::HTTPS).expect("shouldn't have failed");
+        assert_eq!(
+            resource_data,
+            ResourceData::HTTPS {
+                priority: 16,
+                target_name: "foo.example.org".parse().unwrap(),
+                params: vec![
+                    SvcParam::Mandatory { keys: vec![1, 4] },
+                    SvcParam::Alpn {
+                        protocols: vec![b"h2".as_slice().into(), b"h3-19".as_slice().into()]
+                    },
+                    SvcParam::Ipv4Hint {
+                        addresses: vec![Ipv4Addr::new(192, 0, 2, 1)]
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "SvcParams: key 2 is out of order")]
+    fn write_unsorted_svc_params() {
+        let params = [
+            SvcParam::Port { port: 443 },
+            SvcParam::NoDefaultAlpn,
+            SvcParam::Alpn {
+                protocols: vec![b"h2".as_slice().into()],
+            },
+        ];
+        let mut buf = ByteBuf::new_empty(None);
+        write_svc_params(&mut buf, &params).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "SvcParams: value for key 5 is too long (65536)")]
+    fn write_too_long_svc_param() {
+        let config = vec![0; 65536];
+        let params = [SvcParam::Ech {
+            config: config.as_slice().into(),
+        }];
+        let mut buf = ByteBuf::new_empty(None);
+        write_svc_params(&mut buf, &params).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "'alpn' contains an empty protocol ID")]
+    fn write_invalid_svc_param() {
+        let params = [SvcParam::Alpn {
+            protocols: vec![b"h2".as_slice().into(), b"".as_slice().into()],
+        }];
+        let mut buf = ByteBuf::new_empty(None);
+        write_svc_params(&mut buf, &params)
+            .map_err(|e| format!("{:#}", e))
+            .unwrap();
+    }
+}
@@ -1,13 +1,15 @@
 use std::borrow::Cow;
+#[cfg(feature = "edns")]
+use std::net::IpAddr;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 use prop::strategy::Union;
-#[cfg(feature = "edns")]
-use proptest::collection::hash_map;
-use proptest::collection::vec;
+use proptest::collection::{btree_map, btree_set, vec, SizeRange};
 use proptest::prelude::*;
 
-use crate::{QueryType, Question, ResourceData, ResourceRecord};
+#[cfg(feature = "edns")]
+use crate::EdnsOption;
+use crate::{DomainName, QueryType, Question, ResourceData, ResourceRecord, SvcParam};
 
 prop_compose! {
     pub fn arb_question()(qname in arb_qname(), query_type: QueryType, qclass: u16) -> Question<'static> {
@@ -40,24 +42,286 @@ pub fn arb_resource_data() -> impl Strategy<Value = ResourceData<'static>> {
         arb_qname()
             .prop_map(|qname| ResourceData::CNAME { cname: qname })
             .boxed(),
+        (arb_qname(), arb_qname(), any::<[u32; 5]>())
+            .prop_map(
+                |(mname, rname, [serial, refresh, retry, expire, minimum])| ResourceData::SOA {
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                },
+            )
+            .boxed(),
+        arb_qname()
+            .prop_map(|qname| ResourceData::PTR { ptr_domain_name: qname })
+            .boxed(),
+        (any::<u16>(), arb_qname())
+            .prop_map(|(preference, exchange)| ResourceData::MX { preference, exchange })
+            .boxed(),
+        vec(vec(any::<u8>(), 0..=255).prop_map(Cow::Owned), 1..5)
+            .prop_map(|txt_data| ResourceData::TXT { txt_data })
+            .boxed(),
         any::<Ipv6Addr>()
             .prop_map(|address| ResourceData::AAAA { address })
             .boxed(),
+        (any::<u16>(), any::<u16>(), any::<u16>(), arb_qname())
+            .prop_map(|(priority, weight, port, target)| ResourceData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            })
+            .boxed(),
+        (
+            any::<[u16; 2]>(),
+            arb_bytes(0..10),
+            arb_bytes(0..20),
+            arb_bytes(0..50),
+            arb_qname(),
+        )
+            .prop_map(
+                |([order, preference], flags, services, regexp, replacement)| ResourceData::NAPTR {
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                },
+            )
+            .boxed(),
         #[cfg(feature = "edns")]
-        proptest::option::of(hash_map(
+        vec(arb_edns_option(), 0..5)
+            .prop_map(|options| ResourceData::OPT { options })
+            .boxed(),
+        (any::<u16>(), any::<u8>(), any::<u8>(), arb_bytes(1..64))
+            .prop_map(|(key_tag, algorithm, digest_type, digest)| ResourceData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            })
+            .boxed(),
+        (any::<[u8; 2]>(), arb_bytes(1..64))
+            .prop_map(|([algorithm, fingerprint_type], fingerprint)| ResourceData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            })
+            .boxed(),
+        (
+            any::<QueryType>(),
+            any::<[u8; 2]>(),
+            any::<[u32; 3]>(),
+            any::<u16>(),
+            arb_qname(),
+            arb_bytes(1..256),
+        )
+            .prop_map(
+                |(
+                    type_covered,
+                    [algorithm, labels],
+                    [original_ttl, signature_expiration, signature_inception],
+                    key_tag,
+                    signer_name,
+                    signature,
+                )| ResourceData::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    signature_expiration,
+                    signature_inception,
+                    key_tag,
+                    signer_name,
+                    signature,
+                },
+            )
+            .boxed(),
+        (arb_qname(), arb_type_bitmaps())
+            .prop_map(|(next_domain_name, type_bitmaps)| ResourceData::NSEC {
+                next_domain_name,
+                type_bitmaps,
+            })
+            .boxed(),
+        (any::<u16>(), any::<u8>(), any::<u8>(), arb_bytes(1..256))
+            .prop_map(|(flags, protocol, algorithm, public_key)| ResourceData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            })
+            .boxed(),
+        (
+            any::<[u8; 2]>(),
+            any::<u16>(),
+            arb_bytes(0..=255),
+            arb_bytes(1..=255),
+            arb_type_bitmaps(),
+        )
+            .prop_map(
+                |([hash_algorithm, flags], iterations, salt, next_hashed_owner_name, type_bitmaps)| {
+                    ResourceData::NSEC3 {
+                        hash_algorithm,
+                        flags,
+                        iterations,
+                        salt,
+                        next_hashed_owner_name,
+                        type_bitmaps,
+                    }
+                },
+            )
+            .boxed(),
+        (any::<[u8; 2]>(), any::<u16>(), arb_bytes(0..=255))
+            .prop_map(|([hash_algorithm, flags], iterations, salt)| ResourceData::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            })
+            .boxed(),
+        (any::<[u8; 3]>(), arb_bytes(1..64))
+            .prop_map(
+                |([certificate_usage, selector, matching_type], certificate_association_data)| ResourceData::TLSA {
+                    certificate_usage,
+                    selector,
+                    matching_type,
+                    certificate_association_data,
+                },
+            )
+            .boxed(),
+        (any::<u16>(), arb_qname(), arb_svc_params())
+            .prop_map(|(priority, target_name, params)| ResourceData::SVCB {
+                priority,
+                target_name,
+                params,
+            })
+            .boxed(),
+        (any::<u16>(), arb_qname(), arb_svc_params())
+            .prop_map(|(priority, target_name, params)| ResourceData::HTTPS {
+                priority,
+                target_name,
+                params,
+            })
+            .boxed(),
+        (any::<u8>(), "[a-z0-9]{1,15}", arb_bytes(0..100))
+            .prop_map(|(flags, tag, value)| ResourceData::CAA {
+                flags,
+                tag: tag.into(),
+                value,
+            })
+            .boxed(),
+        (
+            arb_qname(),
+            0..(1u64 << 48),
+            any::<u16>(),
+            arb_bytes(0..64),
+            any::<u16>(),
             any::<u16>(),
-            vec(any::<u8>(), 1..100).prop_map(Cow::Owned),
-            1..10,
-        ))
-        .prop_map(|options| ResourceData::OPT { options })
-        .boxed(),
+            arb_bytes(0..16),
+        )
+            .prop_map(
+                |(algorithm, time_signed, fudge, mac, original_id, error, other_data)| ResourceData::TSIG {
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other_data,
+                },
+            )
+            .boxed(),
     ];
 
     Union::new(variants)
 }
 
-fn arb_qname() -> impl Strategy<Value = Cow<'static, str>> {
-    proptest::string::string_regex(r"(([a-za-z0-9][a-za-z0-9-]{1,62}\.)+[a-za-z0-9]{2,63})|")
-        .expect("regex should be valid")
-        .prop_map(Cow::Owned)
+fn arb_bytes(size: impl Into<SizeRange>) -> impl Strategy<Value = Cow<'static, [u8]>> {
+    vec(any::<u8>(), size).prop_map(Cow::Owned)
+}
+
+/// Type bitmaps are always decoded as a sorted list of unique types
+fn arb_type_bitmaps() -> impl Strategy<Value = Vec<QueryType>> {
+    btree_set(any::<u16>(), 0..20).prop_map(|types| types.into_iter().map(QueryType::from).collect())
+}
+
+/// SvcParams are always sorted by their keys
+fn arb_svc_params() -> impl Strategy<Value = Vec<SvcParam<'static>>> {
+    (
+        proptest::option::of(vec(any::<u16>(), 1..5).prop_map(|keys| SvcParam::Mandatory { keys })),
+        proptest::option::of(vec(arb_bytes(1..10), 1..5).prop_map(|protocols| SvcParam::Alpn { protocols })),
+        any::<bool>().prop_map(|no_default_alpn| no_default_alpn.then_some(SvcParam::NoDefaultAlpn)),
+        proptest::option::of(any::<u16>().prop_map(|port| SvcParam::Port { port })),
+        proptest::option::of(vec(any::<Ipv4Addr>(), 1..5).prop_map(|addresses| SvcParam::Ipv4Hint { addresses })),
+        proptest::option::of(arb_bytes(0..64).prop_map(|config| SvcParam::Ech { config })),
+        proptest::option::of(vec(any::<Ipv6Addr>(), 1..5).prop_map(|addresses| SvcParam::Ipv6Hint { addresses })),
+        btree_map(7..u16::MAX, arb_bytes(0..32), 0..3),
+    )
+        .prop_map(
+            |(mandatory, alpn, no_default_alpn, port, ipv4hint, ech, ipv6hint, unknown)| {
+                [mandatory, alpn, no_default_alpn, port, ipv4hint, ech, ipv6hint]
+                    .into_iter()
+                    .flatten()
+                    .chain(unknown.into_iter().map(|(key, value)| SvcParam::Unknown { key, value }))
+                    .collect()
+            },
+        )
+}
+
+#[cfg(feature = "edns")]
+fn arb_edns_option() -> impl Strategy<Value = EdnsOption<'static>> {
+    // Zero out address bits after the source prefix
+    let arb_client_subnet_v4 =
+        (0..=32u8, any::<u32>(), 0..=32u8).prop_map(|(source, address, scope)| EdnsOption::ClientSubnet {
+            source_prefix_length: source,
+            scope_prefix_length: scope,
+            address: IpAddr::V4(Ipv4Addr::from(
+                address & u32::MAX.checked_shl(32 - source as u32).unwrap_or(0),
+            )),
+        });
+    let arb_client_subnet_v6 =
+        (0..=128u8, any::<u128>(), 0..=128u8).prop_map(|(source, address, scope)| EdnsOption::ClientSubnet {
+            source_prefix_length: source,
+            scope_prefix_length: scope,
+            address: IpAddr::V6(Ipv6Addr::from(
+                address & u128::MAX.checked_shl(128 - source as u32).unwrap_or(0),
+            )),
+        });
+    prop_oneof![
+        arb_bytes(0..32).prop_map(|nsid| EdnsOption::Nsid { nsid }),
+        arb_client_subnet_v4,
+        arb_client_subnet_v6,
+        (any::<[u8; 8]>(), proptest::option::of(arb_bytes(8..=32))).prop_map(|(client_cookie, server_cookie)| {
+            EdnsOption::Cookie {
+                client_cookie,
+                server_cookie,
+            }
+        }),
+        proptest::option::of(any::<u16>()).prop_map(|timeout| EdnsOption::TcpKeepalive { timeout }),
+        (0..128u16).prop_map(|length| EdnsOption::Padding { length }),
+        (any::<u16>(), ".{0,32}").prop_map(|(info_code, extra_text)| EdnsOption::ExtendedDnsError {
+            info_code,
+            extra_text: extra_text.into(),
+        }),
+        // Use codes that aren't handled to avoid collisions
+        (16..u16::MAX, arb_bytes(0..64)).prop_map(|(code, data)| EdnsOption::Unknown { code, data }),
+    ]
+}
+
+fn arb_qname() -> impl Strategy<Value = DomainName<'static>> {
+    let label = Union::new([
+        proptest::string::string_regex("[a-zA-Z0-9][a-zA-Z0-9-]{0,62}")
+            .expect("regex should be valid")
+            .prop_map(String::into_bytes)
+            .boxed(),
+        // Labels can contain arbitrary bytes
+        vec(any::<u8>(), 1..=63).boxed(),
+    ]);
+    vec(label, 0..5).prop_filter_map("name is too long", |labels| {
+        DomainName::from_labels(labels.iter().map(Vec::as_slice)).ok()
+    })
 }
@@ -0,0 +1,450 @@
+//! Transaction signatures (TSIG) as defined in RFC8945
+
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+use data_encoding::BASE64;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+
+use crate::{
+    ByteBuf, DnsError, DnsHeader, DomainName, EncodeToBuf, FromBuf, QueryType, Question, ResourceData, ResourceRecord,
+};
+
+/// Recommended permitted time difference in seconds (RFC8945, section 10)
+pub const TSIG_FUDGE: u16 = 300;
+
+/// TSIG RRs always use the ANY class
+const ANY_CLASS: u16 = 255;
+/// Position of ARCOUNT in the header
+const ADDITIONAL_COUNT_POS: usize = 10;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    pub fn get_name(&self) -> DomainName<'static> {
+        let name = match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        };
+        name.parse().unwrap()
+    }
+
+    pub fn from_name(name: &DomainName<'_>) -> Option<Self> {
+        [TsigAlgorithm::HmacSha256, TsigAlgorithm::HmacSha512]
+            .into_iter()
+            .find(|algorithm| algorithm.get_name() == *name)
+    }
+
+    fn get_mac_size(&self) -> usize {
+        match self {
+            TsigAlgorithm::HmacSha256 => 32,
+            TsigAlgorithm::HmacSha512 => 64,
+        }
+    }
+}
+
+impl FromStr for TsigAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name: DomainName = s.parse().with_context(|| format!("invalid TSIG algorithm '{}'", s))?;
+        TsigAlgorithm::from_name(&name).with_context(|| format!("unsupported TSIG algorithm '{}'", s))
+    }
+}
+
+/// Shared secret used to sign and verify messages
+#[derive(Clone)]
+pub struct TsigKey {
+    pub name: DomainName<'static>,
+    pub algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl TsigKey {
+    pub fn new(name: DomainName<'static>, algorithm: TsigAlgorithm, secret: Vec<u8>) -> Self {
+        TsigKey {
+            name,
+            algorithm,
+            secret,
+        }
+    }
+
+    /// Signs the encoded message in `buf` by appending the TSIG RR and incrementing ARCOUNT.
+    ///
+    /// `request_mac` must be set when signing a response. Returns the MAC, which is needed to verify the response.
+    pub fn sign(&self, buf: &mut ByteBuf<'_>, request_mac: Option<&[u8]>, time_signed: u64) -> anyhow::Result<Vec<u8>> {
+        self.sign_with_error(buf, request_mac, time_signed, 0, Vec::new())
+    }
+
+    /// Signs the BADTIME response, where Other Data contains the server's time (RFC8945, section 5.2.3)
+    pub fn sign_bad_time(&self, buf: &mut ByteBuf<'_>, request_mac: &[u8], now: u64) -> anyhow::Result<Vec<u8>> {
+        let other_data = now.to_be_bytes()[2..].to_vec();
+        self.sign_with_error(buf, Some(request_mac), now, 18, other_data)
+    }
+
+    fn sign_with_error(
+        &self,
+        buf: &mut ByteBuf<'_>,
+        request_mac: Option<&[u8]>,
+        time_signed: u64,
+        error: u16,
+        other_data: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let original_id = buf.peek_u16(0).context("message header is missing")?;
+        let mut tsig_rr = ResourceRecord {
+            name: self.name.clone(),
+            class: ANY_CLASS,
+            ttl: 0,
+            resource_data: ResourceData::TSIG {
+                algorithm: self.algorithm.get_name(),
+                time_signed,
+                fudge: TSIG_FUDGE,
+                mac: Cow::Borrowed(&[]),
+                original_id,
+                error,
+                other_data: other_data.into(),
+            },
+        };
+
+        let variables = get_tsig_variables(&tsig_rr)?;
+        let mac = self.compute_mac(request_mac, buf, &variables);
+        if let ResourceData::TSIG { mac: tsig_mac, .. } = &mut tsig_rr.resource_data {
+            *tsig_mac = Cow::Owned(mac.clone());
+        }
+        append_tsig_rr(buf, &tsig_rr)?;
+
+        Ok(mac)
+    }
+
+    /// Verifies the TSIG RR, which must be the last RR of the message.
+    ///
+    /// `request_mac` must be set when verifying a response. Returns the MAC, which is needed to sign the response.
+    /// Verification failures are returned as [`DnsError`].
+    pub fn verify(&self, message: &[u8], request_mac: Option<&[u8]>, now: u64) -> anyhow::Result<Vec<u8>> {
+        let (tsig_pos, tsig_rr) = read_tsig_rr(message)?;
+        let ResourceData::TSIG {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            ..
+        } = &tsig_rr.resource_data
+        else {
+            unreachable!()
+        };
+
+        if tsig_rr.name != self.name || TsigAlgorithm::from_name(algorithm) != Some(self.algorithm) {
+            return Err(DnsError::BadTsigKey.into());
+        }
+
+        // Truncated MACs can't be shorter than 10 bytes or half of the full MAC (RFC8945, section 5.2.2.1)
+        let mac_size = self.algorithm.get_mac_size();
+        if mac.len() > mac_size || mac.len() < (mac_size / 2).max(10) {
+            return Err(DnsError::BadTsigSignature.into());
+        }
+
+        // MAC is computed over the message as it was before the TSIG RR was added
+        let mut unsigned_message = message[..tsig_pos].to_vec();
+        unsigned_message[..2].copy_from_slice(&original_id.to_be_bytes());
+        let additional_count = u16::from_be_bytes([
+            unsigned_message[ADDITIONAL_COUNT_POS],
+            unsigned_message[ADDITIONAL_COUNT_POS + 1],
+        ]);
+        unsigned_message[ADDITIONAL_COUNT_POS..ADDITIONAL_COUNT_POS + 2]
+            .copy_from_slice(&(additional_count - 1).to_be_bytes());
+
+        let variables = get_tsig_variables(&tsig_rr)?;
+        if !self.verify_mac(request_mac, &unsigned_message, &variables, mac) {
+            return Err(DnsError::BadTsigSignature.into());
+        }
+
+        if now.abs_diff(*time_signed) > *fudge as u64 {
+            return Err(DnsError::BadTsigTime {
+                time_signed: *time_signed,
+                now,
+            }
+            .into());
+        }
+
+        Ok(mac.to_vec())
+    }
+
+    fn compute_mac(&self, request_mac: Option<&[u8]>, message: &[u8], variables: &[u8]) -> Vec<u8> {
+        let request_mac_size = request_mac.map(|mac| (mac.len() as u16).to_be_bytes());
+        let parts = [
+            request_mac_size.as_ref().map_or(&[][..], |size| size.as_slice()),
+            request_mac.unwrap_or_default(),
+            message,
+            variables,
+        ];
+        match self.algorithm {
+            TsigAlgorithm::HmacSha256 => new_mac::<Hmac<Sha256>>(&self.secret, &parts)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+            TsigAlgorithm::HmacSha512 => new_mac::<Hmac<Sha512>>(&self.secret, &parts)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+        }
+    }
+
+    fn verify_mac(&self, request_mac: Option<&[u8]>, message: &[u8], variables: &[u8], mac: &[u8]) -> bool {
+        let request_mac_size = request_mac.map(|mac| (mac.len() as u16).to_be_bytes());
+        let parts = [
+            request_mac_size.as_ref().map_or(&[][..], |size| size.as_slice()),
+            request_mac.unwrap_or_default(),
+            message,
+            variables,
+        ];
+        // Comparison is done in constant time
+        match self.algorithm {
+            TsigAlgorithm::HmacSha256 => new_mac::<Hmac<Sha256>>(&self.secret, &parts)
+                .verify_truncated_left(mac)
+                .is_ok(),
+            TsigAlgorithm::HmacSha512 => new_mac::<Hmac<Sha512>>(&self.secret, &parts)
+                .verify_truncated_left(mac)
+                .is_ok(),
+        }
+    }
+}
+
+/// Doesn't show the secret
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parses the key in the `algorithm:name:secret` format used by `dig -y`, where the secret is base64-encoded
+impl FromStr for TsigKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(algorithm), Some(name), Some(secret)) = (parts.next(), parts.next(), parts.next()) else {
+            anyhow::bail!("TSIG key must be in the 'algorithm:name:secret' format");
+        };
+        let algorithm = algorithm.parse()?;
+        let name = name
+            .parse()
+            .with_context(|| format!("invalid TSIG key name '{}'", name))?;
+        let secret = BASE64
+            .decode(secret.as_bytes())
+            .context("TSIG key secret is not valid base64")?;
+
+        Ok(TsigKey::new(name, algorithm, secret))
+    }
+}
+
+/// Appends the unsigned TSIG RR that reports why the request couldn't be verified (RFC8945, section 5.3.2)
+pub fn write_unsigned_tsig_error(
+    buf: &mut ByteBuf<'_>,
+    request_tsig_rr: &ResourceRecord<'_>,
+    error: &DnsError,
+) -> anyhow::Result<()> {
+    let ResourceData::TSIG {
+        algorithm,
+        time_signed,
+        fudge,
+        ..
+    } = &request_tsig_rr.resource_data
+    else {
+        anyhow::bail!("bug: request RR isn't a TSIG RR");
+    };
+    let tsig_rr = ResourceRecord {
+        name: request_tsig_rr.name.clone(),
+        class: ANY_CLASS,
+        ttl: 0,
+        resource_data: ResourceData::TSIG {
+            algorithm: algorithm.clone(),
+            time_signed: *time_signed,
+            fudge: *fudge,
+            mac: Cow::Borrowed(&[]),
+            original_id: buf.peek_u16(0).context("message header is missing")?,
+            error: error.get_tsig_error().context("bug: not a TSIG error")?,
+            other_data: Cow::Borrowed(&[]),
+        },
+    };
+
+    append_tsig_rr(buf, &tsig_rr)
+}
+
+fn new_mac<M: Mac + hmac::digest::KeyInit>(secret: &[u8], parts: &[&[u8]]) -> M {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    parts.iter().for_each(|part| mac.update(part));
+    mac
+}
+
+/// Returns the TSIG RR fields that are covered by the MAC (RFC8945, section 4.3.3)
+fn get_tsig_variables(tsig_rr: &ResourceRecord<'_>) -> anyhow::Result<Vec<u8>> {
+    let ResourceData::TSIG {
+        algorithm,
+        time_signed,
+        fudge,
+        error,
+        other_data,
+        ..
+    } = &tsig_rr.resource_data
+    else {
+        anyhow::bail!("bug: not a TSIG RR");
+    };
+
+    let mut variables = Vec::new();
+    // Names are in the canonical form, i.e. uncompressed and lowercase
+    variables.extend_from_slice(tsig_rr.name.to_ascii_lowercase().as_wire());
+    variables.extend_from_slice(&tsig_rr.class.to_be_bytes());
+    variables.extend_from_slice(&tsig_rr.ttl.to_be_bytes());
+    variables.extend_from_slice(algorithm.to_ascii_lowercase().as_wire());
+    variables.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    variables.extend_from_slice(&fudge.to_be_bytes());
+    variables.extend_from_slice(&error.to_be_bytes());
+    variables.extend_from_slice(&(other_data.len() as u16).to_be_bytes());
+    variables.extend_from_slice(other_data);
+
+    Ok(variables)
+}
+
+fn append_tsig_rr(buf: &mut ByteBuf<'_>, tsig_rr: &ResourceRecord<'_>) -> anyhow::Result<()> {
+    let additional_count = buf
+        .peek_u16(ADDITIONAL_COUNT_POS)
+        .context("message header is missing")?;
+    buf.set_u16(
+        ADDITIONAL_COUNT_POS,
+        additional_count.checked_add(1).context("too many additional RRs")?,
+    )?;
+    tsig_rr.encode_to_buf(buf, None).context("writing TSIG RR")?;
+
+    Ok(())
+}
+
+/// Returns the position of the TSIG RR along with the RR itself
+fn read_tsig_rr(message: &[u8]) -> anyhow::Result<(usize, ResourceRecord<'_>)> {
+    let mut buf = ByteBuf::new_from_cow(Cow::Borrowed(message));
+    let header = DnsHeader::from_buf(&mut buf).context("header parsing error")?;
+    for idx in 0..header.question_count {
+        Question::from_buf(&mut buf).with_context(|| format!("question parsing error at idx {}", idx))?;
+    }
+
+    let rr_count =
+        header.answer_rr_count as usize + header.authority_rr_count as usize + header.additional_rr_count as usize;
+    if header.additional_rr_count == 0 {
+        return Err(DnsError::MisplacedTsig.into());
+    }
+    for idx in 0..rr_count - 1 {
+        ResourceRecord::from_buf(&mut buf).with_context(|| format!("RR parsing error at idx {}", idx))?;
+    }
+
+    let tsig_pos = buf.get_pos();
+    let tsig_rr = ResourceRecord::from_buf(&mut buf).context("TSIG RR parsing error")?;
+    if tsig_rr.resource_data.get_query_type() != QueryType::TSIG {
+        return Err(DnsError::MisplacedTsig.into());
+    }
+
+    Ok((tsig_pos, tsig_rr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DnsPacket;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn get_key() -> TsigKey {
+        "hmac-sha256:key.example:c2VjcmV0"
+            .parse()
+            .expect("shouldn't have failed")
+    }
+
+    fn get_encoded_query() -> ByteBuf<'static> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 1234;
+        packet.add_question(Question::new("example.com".parse().unwrap(), QueryType::A, None));
+        let mut buf = ByteBuf::new_empty(None);
+        packet.encode_to_buf(&mut buf, None).expect("shouldn't have failed");
+        buf
+    }
+
+    #[test]
+    fn tsig_sign_and_verify() {
+        let key = get_key();
+        let mut query = get_encoded_query();
+        let request_mac = key.sign(&mut query, None, NOW).expect("shouldn't have failed");
+
+        let packet = DnsPacket::from_buf(&mut ByteBuf::new(&query)).expect("shouldn't have failed");
+        assert_eq!(packet.additionals.len(), 1);
+        assert_eq!(packet.additionals[0].resource_data.get_query_type(), QueryType::TSIG);
+        assert_eq!(
+            key.verify(&query, None, NOW + 10).expect("shouldn't have failed"),
+            request_mac
+        );
+
+        // Response MAC covers the request MAC
+        let mut response = get_encoded_query();
+        key.sign(&mut response, Some(&request_mac), NOW)
+            .expect("shouldn't have failed");
+        key.verify(&response, Some(&request_mac), NOW)
+            .expect("shouldn't have failed");
+        let error = key.verify(&response, None, NOW).unwrap_err();
+        assert_eq!(error.downcast_ref::<DnsError>(), Some(&DnsError::BadTsigSignature));
+    }
+
+    #[test]
+    fn tsig_verification_errors() {
+        let key = get_key();
+        let mut query = get_encoded_query();
+        key.sign(&mut query, None, NOW).expect("shouldn't have failed");
+
+        let error = key.verify(&query, None, NOW + 301).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DnsError>(),
+            Some(&DnsError::BadTsigTime {
+                time_signed: NOW,
+                now: NOW + 301
+            })
+        );
+
+        let other_key: TsigKey = "hmac-sha512:key.example:c2VjcmV0".parse().unwrap();
+        let error = other_key.verify(&query, None, NOW).unwrap_err();
+        assert_eq!(error.downcast_ref::<DnsError>(), Some(&DnsError::BadTsigKey));
+
+        // Change the question's type
+        let mut tampered_query = query.to_vec();
+        tampered_query[12 + 13] = 28;
+        let error = key.verify(&tampered_query, None, NOW).unwrap_err();
+        assert_eq!(error.downcast_ref::<DnsError>(), Some(&DnsError::BadTsigSignature));
+
+        let error = key.verify(&get_encoded_query(), None, NOW).unwrap_err();
+        assert_eq!(error.downcast_ref::<DnsError>(), Some(&DnsError::MisplacedTsig));
+    }
+
+    #[test]
+    fn tsig_unsigned_error() {
+        let key = get_key();
+        let mut query = get_encoded_query();
+        key.sign(&mut query, None, NOW).expect("shouldn't have failed");
+        let query_packet = DnsPacket::from_buf(&mut ByteBuf::new(&query)).expect("shouldn't have failed");
+
+        let mut response = get_encoded_query();
+        write_unsigned_tsig_error(&mut response, &query_packet.additionals[0], &DnsError::BadTsigKey)
+            .expect("shouldn't have failed");
+        let response_packet = DnsPacket::from_buf(&mut ByteBuf::new(&response)).expect("shouldn't have failed");
+        let ResourceData::TSIG { mac, error, .. } = &response_packet.additionals[0].resource_data else {
+            panic!("TSIG RR is missing");
+        };
+        assert!(mac.is_empty());
+        assert_eq!(*error, 17);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::{ByteBuf, QueryType};
+
+/// Reads `Type Bit Maps` as defined in RFC4034 (section 4.1.2) that take exactly `length` bytes
+pub(crate) fn read_type_bitmaps(buf: &mut ByteBuf, length: usize) -> anyhow::Result<Vec<QueryType>> {
+    let mut types = Vec::new();
+    let mut remaining_length = length;
+    let mut previous_window = None;
+    while remaining_length != 0 {
+        let window = buf.read_u8().context("type bitmaps: window block number is missing")?;
+        if previous_window.is_some_and(|previous_window| window <= previous_window) {
+            anyhow::bail!("type bitmaps: window block {} is out of order", window);
+        }
+        previous_window = Some(window);
+
+        let bitmap_length = buf.read_u8().context("type bitmaps: bitmap length is missing")?;
+        if !(1..=32).contains(&bitmap_length) {
+            anyhow::bail!("type bitmaps: invalid bitmap length {}", bitmap_length);
+        }
+        remaining_length = remaining_length
+            .checked_sub(2 + bitmap_length as usize)
+            .context("type bitmaps: bitmap exceeds RDLENGTH")?;
+
+        let bitmap = buf
+            .read_bytes(bitmap_length as usize)
+            .with_context(|| format!("type bitmaps: bitmap for window block {} is missing", window))?;
+        for (byte_idx, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let qtype = (window as u16) << 8 | (byte_idx * 8 + bit) as u16;
+                    types.push(qtype.into());
+                }
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+/// Writes `Type Bit Maps` as defined in RFC4034 (section 4.1.2).
+///
+/// Types are sorted and deduplicated, so the order of the passed types doesn't matter.
+pub(crate) fn write_type_bitmaps(buf: &mut ByteBuf, types: &[QueryType]) -> anyhow::Result<usize> {
+    let mut written = 0;
+    for (window, bitmap) in get_window_blocks(types) {
+        buf.write_u8(window);
+        buf.write_u8(bitmap.len() as u8);
+        buf.write_bytes(&bitmap, None)
+            .with_context(|| format!("type bitmaps: writing bitmap for window block {}", window))?;
+        written += 2 + bitmap.len();
+    }
+
+    Ok(written)
+}
+
+pub(crate) fn get_type_bitmaps_size(types: &[QueryType]) -> usize {
+    get_window_blocks(types)
+        .into_values()
+        .map(|bitmap| 2 /* window block number + bitmap length */ + bitmap.len())
+        .sum()
+}
+
+fn get_window_blocks(types: &[QueryType]) -> BTreeMap<u8, Vec<u8>> {
+    let mut windows: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+    for qtype in types {
+        let [window, low_byte] = u16::from(*qtype).to_be_bytes();
+        let bitmap = windows.entry(window).or_default();
+        let byte_idx = low_byte as usize / 8;
+        if bitmap.len() <= byte_idx {
+            // Bitmaps never have trailing zero octets, so it's enough to grow them on demand
+            bitmap.resize(byte_idx + 1, 0);
+        }
+        bitmap[byte_idx] |= 0x80 >> (low_byte % 8);
+    }
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Taken from RFC4034 (section 4.3): "A MX RRSIG NSEC TYPE1234"
+    const RFC4034_TYPE_BITMAPS: &[u8] = &[
+        0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x20,
+    ];
+
+    #[test]
+    fn read_rfc4034_type_bitmaps() {
+        let mut buf = ByteBuf::new(&RFC4034_TYPE_BITMAPS);
+        let types = read_type_bitmaps(&mut buf, RFC4034_TYPE_BITMAPS.len()).expect("shouldn't have failed");
+        assert_eq!(
+            types,
+            [
+                QueryType::A,
+                QueryType::MX,
+                QueryType::RRSIG,
+                QueryType::NSEC,
+                QueryType::UNKNOWN(1234)
+            ]
+        );
+    }
+
+    #[test]
+    fn write_rfc4034_type_bitmaps() {
+        let mut buf = ByteBuf::new_empty(None);
+        let types = [
+            QueryType::NSEC,
+            QueryType::UNKNOWN(1234),
+            QueryType::A,
+            QueryType::RRSIG,
+            QueryType::MX,
+            QueryType::A,
+        ];
+        let written = write_type_bitmaps(&mut buf, &types).expect("shouldn't have failed");
+        assert_eq!(written, RFC4034_TYPE_BITMAPS.len());
+        assert_eq!(get_type_bitmaps_size(&types), RFC4034_TYPE_BITMAPS.len());
+        assert_eq!(&*buf, RFC4034_TYPE_BITMAPS);
+    }
+
+    #[test]
+    #[should_panic(expected = "type bitmaps: window block 0 is out of order")]
+    fn read_out_of_order_type_bitmaps() {
+        let data = &[0x01, 0x01, 0x80, 0x00, 0x01, 0x40];
+        let mut buf = ByteBuf::new(data);
+        read_type_bitmaps(&mut buf, data.len()).unwrap();
+    }
+}
@@ -1,18 +1,41 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::str::Utf8Error;
 
-pub fn get_max_encoded_qname_size(qname: &str, label_cache: Option<&HashMap<&str, usize>>) -> usize {
-    let mut size = 0;
-    for (idx, label) in qname.split(".").enumerate() {
-        if !label.is_empty() {
-            if let Some(cache) = label_cache {
-                let remaining_qname = qname.splitn(idx + 1, '.').last().unwrap();
-                if cache.contains_key(remaining_qname) {
-                    return size + 2 /* JUMP PTR bytes */;
-                }
-            }
-            size += 1 /* label length */ + label.len();
+use crate::{DnsError, DomainName};
+
+pub fn get_max_encoded_qname_size(qname: &DomainName<'_>, label_cache: Option<&HashMap<&[u8], usize>>) -> usize {
+    let wire = qname.as_wire();
+    let mut pos = 0;
+    while wire[pos] != 0 {
+        if label_cache.is_some_and(|cache| cache.contains_key(&wire[pos..])) {
+            return pos + 2 /* JUMP PTR bytes */;
         }
+        pos += 1 /* label length */ + wire[pos] as usize;
     }
     // Account for the null byte
-    size + 1
+    pos + 1
+}
+
+/// Converts bytes into a string without copying them
+pub(crate) fn cow_bytes_into_str(data: Cow<'_, [u8]>) -> Result<Cow<'_, str>, Utf8Error> {
+    Ok(match data {
+        Cow::Borrowed(data) => Cow::Borrowed(std::str::from_utf8(data)?),
+        Cow::Owned(data) => Cow::Owned(String::from_utf8(data).map_err(|e| e.utf8_error())?),
+    })
+}
+
+/// Converts bytes into a string, replacing invalid UTF-8 sequences and copying only if there are any
+pub(crate) fn cow_bytes_into_str_lossy(data: Cow<'_, [u8]>) -> Cow<'_, str> {
+    match data {
+        Cow::Borrowed(data) => String::from_utf8_lossy(data),
+        Cow::Owned(data) => {
+            Cow::Owned(String::from_utf8(data).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+        }
+    }
+}
+
+/// Converts the length of data that's prefixed with a 16-bit length, e.g. RDATA
+pub(crate) fn get_u16_length(length: usize) -> Result<u16, DnsError> {
+    u16::try_from(length).map_err(|_| DnsError::LengthOverflow(length))
 }
@@ -0,0 +1,296 @@
+//! Zone (master) file format as defined in RFC1035 (section 5)
+
+mod rdata;
+mod tokenizer;
+
+use anyhow::Context;
+use rdata::parse_rdata;
+use tokenizer::tokenize;
+
+use crate::presentation::{parse_class, parse_labels};
+#[cfg(feature = "serde")]
+use crate::ResourceData;
+use crate::{DomainName, QueryType, ResourceRecord, IN_CLASS};
+
+/// Parses records from the zone file contents.
+///
+/// Supports `$ORIGIN` and `$TTL` directives, relative names, `@`, parentheses and `\X`/`\DDD` escapes.
+/// `origin` is used for relative names until the first `$ORIGIN` directive.
+pub fn parse_zone(input: &str, origin: Option<&str>) -> anyhow::Result<Vec<ResourceRecord<'static>>> {
+    let mut origin = match origin {
+        Some(origin) => parse_name(origin, &DomainName::root()).context("invalid origin")?,
+        None => DomainName::root(),
+    };
+    let mut default_ttl = None;
+    let mut last_owner: Option<DomainName> = None;
+    let mut last_ttl = None;
+    let mut last_class = None;
+
+    let mut records = Vec::new();
+    for entry in tokenize(input)? {
+        let line = entry.line;
+        let mut tokens = entry.tokens.iter().peekable();
+
+        // Directives
+        if let Some(directive) = entry
+            .has_owner
+            .then(|| entry.tokens[0].text)
+            .filter(|text| text.starts_with('$'))
+        {
+            let argument = entry.tokens.get(1).map(|token| token.text);
+            match directive.to_ascii_uppercase().as_str() {
+                "$ORIGIN" => {
+                    let argument = argument.with_context(|| format!("line {}: $ORIGIN is missing a name", line))?;
+                    origin =
+                        parse_name(argument, &origin).with_context(|| format!("line {}: invalid $ORIGIN", line))?;
+                }
+                "$TTL" => {
+                    let argument = argument.with_context(|| format!("line {}: $TTL is missing a value", line))?;
+                    default_ttl = Some(parse_ttl(argument).with_context(|| format!("line {}: invalid $TTL", line))?);
+                }
+                directive => anyhow::bail!("line {}: unsupported directive {}", line, directive),
+            }
+            if entry.tokens.len() > 2 {
+                anyhow::bail!("line {}: unexpected trailing data '{}'", line, entry.tokens[2].text);
+            }
+            continue;
+        }
+
+        let owner = if entry.has_owner {
+            let text = tokens.next().unwrap().text;
+            parse_name(text, &origin).with_context(|| format!("line {}: invalid owner name '{}'", line, text))?
+        } else {
+            last_owner
+                .clone()
+                .with_context(|| format!("line {}: owner name is missing", line))?
+        };
+
+        // TTL and CLASS are optional and can appear in any order
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = tokens.peek().filter(|token| !token.quoted) {
+            if ttl.is_none() && token.text.starts_with(|char: char| char.is_ascii_digit()) {
+                ttl = Some(parse_ttl(token.text).with_context(|| format!("line {}: invalid TTL", line))?);
+            } else if let Some(parsed_class) = class.is_none().then(|| parse_class(token.text)).flatten() {
+                class = Some(parsed_class);
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+
+        let type_text = tokens
+            .next()
+            .with_context(|| format!("line {}: TYPE is missing", line))?
+            .text;
+        let query_type: QueryType = type_text.parse().with_context(|| format!("line {}", line))?;
+
+        // RFC2308: use $TTL if present, otherwise fall back to the previous TTL (RFC1035)
+        let ttl = ttl
+            .or(default_ttl)
+            .or(last_ttl)
+            .with_context(|| format!("line {}: TTL is missing and there is no $TTL directive", line))?;
+        let class = class.or(last_class).unwrap_or(IN_CLASS);
+
+        let rdata_tokens = &entry.tokens[entry.tokens.len() - tokens.count()..];
+        let resource_data = parse_rdata(query_type, rdata_tokens, &origin).with_context(|| format!("line {}", line))?;
+
+        records.push(ResourceRecord::new(
+            owner.clone(),
+            resource_data,
+            Some(ttl),
+            Some(class),
+        ));
+        last_owner = Some(owner);
+        last_ttl = Some(ttl);
+        last_class = Some(class);
+    }
+
+    Ok(records)
+}
+
+/// Prints records in the zone file format, one record per line, using absolute names only
+pub fn print_zone(records: &[ResourceRecord<'_>]) -> String {
+    records.iter().map(|record| format!("{}\n", record)).collect()
+}
+
+/// Parses RDATA of a single record from its presentation format, where all names must be absolute
+#[cfg(feature = "serde")]
+pub(crate) fn parse_rdata_text(query_type: QueryType, text: &str) -> anyhow::Result<ResourceData<'static>> {
+    let entries = tokenize(text)?;
+    let tokens = match entries.as_slice() {
+        [] => &[][..],
+        [entry] => &entry.tokens[..],
+        _ => anyhow::bail!("RDATA must be on a single line"),
+    };
+    parse_rdata(query_type, tokens, &DomainName::root())
+}
+
+/// Parses the domain name, where relative names are appended to `origin`
+fn parse_name(text: &str, origin: &DomainName<'_>) -> anyhow::Result<DomainName<'static>> {
+    if text == "@" {
+        return Ok(origin.clone().into_owned());
+    }
+
+    let (labels, is_absolute) = parse_labels(text)?;
+    let labels = labels.iter().map(Vec::as_slice);
+    if is_absolute {
+        DomainName::from_labels(labels)
+    } else {
+        DomainName::from_labels(labels.chain(origin.labels()))
+    }
+}
+
+/// Parses TTL either as a number of seconds or in the `1w2d3h4m5s` format
+fn parse_ttl(text: &str) -> anyhow::Result<u32> {
+    if let Ok(ttl) = text.parse() {
+        return Ok(ttl);
+    }
+
+    let mut ttl = 0u32;
+    let mut value: Option<u32> = None;
+    for char in text.chars() {
+        if let Some(digit) = char.to_digit(10) {
+            value = Some(
+                value
+                    .unwrap_or_default()
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add(digit))
+                    .context("TTL is too large")?,
+            );
+            continue;
+        }
+        let multiplier = match char.to_ascii_lowercase() {
+            'w' => 7 * 24 * 60 * 60,
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => anyhow::bail!("invalid TTL '{}'", text),
+        };
+        let value = value.take().with_context(|| format!("invalid TTL '{}'", text))?;
+        ttl = value
+            .checked_mul(multiplier)
+            .and_then(|value| ttl.checked_add(value))
+            .context("TTL is too large")?;
+    }
+    if value.is_some() {
+        anyhow::bail!("invalid TTL '{}'", text);
+    }
+
+    Ok(ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::test_utils::arb_resource_record;
+    use crate::ResourceData;
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@       IN  SOA ns1 hostmaster (
+                2024010101 ; serial
+                1d 2h 4w 5m )
+        IN  NS  ns1
+ns1  300    A   192.0.2.1
+mail        MX  10 mail.example.net.
+txt         TXT "hello \"world\"" unquoted\032text
+"#;
+
+    #[test]
+    fn parse_simple_zone() {
+        let records = parse_zone(ZONE, None).expect("shouldn't have failed");
+        assert_eq!(
+            records,
+            [
+                ResourceRecord::new(
+                    "example.com".parse().unwrap(),
+                    ResourceData::SOA {
+                        mname: "ns1.example.com".parse().unwrap(),
+                        rname: "hostmaster.example.com".parse().unwrap(),
+                        serial: 2024010101,
+                        refresh: 86400,
+                        retry: 7200,
+                        expire: 2419200,
+                        minimum: 300,
+                    },
+                    Some(3600),
+                    None
+                ),
+                ResourceRecord::new(
+                    "example.com".parse().unwrap(),
+                    ResourceData::NS {
+                        ns_domain_name: "ns1.example.com".parse().unwrap()
+                    },
+                    Some(3600),
+                    None
+                ),
+                ResourceRecord::new(
+                    "ns1.example.com".parse().unwrap(),
+                    ResourceData::A {
+                        address: Ipv4Addr::new(192, 0, 2, 1)
+                    },
+                    Some(300),
+                    None
+                ),
+                ResourceRecord::new(
+                    "mail.example.com".parse().unwrap(),
+                    ResourceData::MX {
+                        preference: 10,
+                        exchange: "mail.example.net".parse().unwrap()
+                    },
+                    Some(3600),
+                    None
+                ),
+                ResourceRecord::new(
+                    "txt.example.com".parse().unwrap(),
+                    ResourceData::TXT {
+                        txt_data: vec![b"hello \"world\"".as_slice().into(), b"unquoted text".as_slice().into()]
+                    },
+                    Some(3600),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "line 3: TTL is missing and there is no $TTL directive")]
+    fn parse_zone_without_ttl() {
+        parse_zone("$ORIGIN example.com.\n\n@ IN A 192.0.2.1\n", None).unwrap();
+    }
+
+    #[test]
+    fn parse_generic_rdata() {
+        let records = parse_zone("a.example. 60 CLASS1 TYPE1 \\# 4 C0000201\n", None).expect("shouldn't have failed");
+        assert_eq!(
+            records[0].resource_data,
+            ResourceData::A {
+                address: Ipv4Addr::new(192, 0, 2, 1)
+            }
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn zone_roundtrip(resource_record in arb_resource_record().prop_filter("OPT and TSIG RRs are not allowed in zone files", |rr| {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "edns")] {
+                    !matches!(rr.resource_data.get_query_type(), QueryType::OPT | QueryType::TSIG)
+                } else {
+                    rr.resource_data.get_query_type() != QueryType::TSIG
+                }
+            }
+        })) {
+            let zone = print_zone(std::slice::from_ref(&resource_record));
+            let roundtripped_records = parse_zone(&zone, None).expect("shouldn't have failed");
+            prop_assert_eq!(vec![resource_record], roundtripped_records, "zone roundtrip test failed");
+        }
+    }
+}
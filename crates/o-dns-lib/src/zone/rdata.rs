@@ -0,0 +1,408 @@
+use std::borrow::Cow;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::Context;
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXLOWER_PERMISSIVE};
+
+use super::tokenizer::Token;
+use super::{parse_name, parse_ttl};
+use crate::presentation::{parse_svc_param_key, parse_timestamp, unescape};
+use crate::resource_record::validate_caa_tag;
+use crate::{ByteBuf, DomainName, QueryType, ResourceData, SvcParam};
+
+/// Parses RDATA of the specific type from its presentation format
+pub(super) fn parse_rdata(
+    query_type: QueryType,
+    tokens: &[Token<'_>],
+    origin: &DomainName<'_>,
+) -> anyhow::Result<ResourceData<'static>> {
+    let mut tokens = Tokens {
+        tokens,
+        pos: 0,
+        query_type,
+        origin,
+    };
+
+    // Generic format from RFC3597 can be used for any type
+    if tokens
+        .tokens
+        .first()
+        .is_some_and(|token| !token.quoted && token.text == "\\#")
+    {
+        tokens.pos += 1;
+        let length = tokens.next_u16("RDATA length")?;
+        let data = tokens.rest_hex("RDATA")?;
+        if data.len() != length as usize {
+            anyhow::bail!(
+                "{} record: RDATA length is {}, but got {} bytes",
+                query_type,
+                length,
+                data.len()
+            );
+        }
+        let mut buf = ByteBuf::new_empty(Some(2 + data.len()));
+        buf.write_u16(length)?;
+        buf.write_bytes(&data, None)?;
+        return ResourceData::from_buf_with_type(&mut buf, query_type);
+    }
+
+    let resource_data = match query_type {
+        QueryType::UNKNOWN(_) => anyhow::bail!("{} record: only the generic '\\#' format is supported", query_type),
+        QueryType::A => ResourceData::A {
+            address: tokens.next_parsed::<Ipv4Addr>("ADDRESS")?,
+        },
+        QueryType::NS => ResourceData::NS {
+            ns_domain_name: tokens.next_name("NSDNAME")?,
+        },
+        QueryType::CNAME => ResourceData::CNAME {
+            cname: tokens.next_name("CNAME")?,
+        },
+        QueryType::SOA => ResourceData::SOA {
+            mname: tokens.next_name("MNAME")?,
+            rname: tokens.next_name("RNAME")?,
+            serial: tokens.next_parsed("SERIAL")?,
+            refresh: tokens.next_ttl("REFRESH")?,
+            retry: tokens.next_ttl("RETRY")?,
+            expire: tokens.next_ttl("EXPIRE")?,
+            minimum: tokens.next_ttl("MINIMUM")?,
+        },
+        QueryType::PTR => ResourceData::PTR {
+            ptr_domain_name: tokens.next_name("PTRDNAME")?,
+        },
+        QueryType::MX => ResourceData::MX {
+            preference: tokens.next_u16("PREFERENCE")?,
+            exchange: tokens.next_name("EXCHANGE")?,
+        },
+        QueryType::TXT => {
+            let mut txt_data = vec![tokens.next_character_string("TXT-DATA")?];
+            while tokens.has_next() {
+                txt_data.push(tokens.next_character_string("TXT-DATA")?);
+            }
+            ResourceData::TXT { txt_data }
+        }
+        QueryType::AAAA => ResourceData::AAAA {
+            address: tokens.next_parsed::<Ipv6Addr>("ADDRESS")?,
+        },
+        QueryType::SRV => ResourceData::SRV {
+            priority: tokens.next_u16("PRIORITY")?,
+            weight: tokens.next_u16("WEIGHT")?,
+            port: tokens.next_u16("PORT")?,
+            target: tokens.next_name("TARGET")?,
+        },
+        QueryType::NAPTR => ResourceData::NAPTR {
+            order: tokens.next_u16("ORDER")?,
+            preference: tokens.next_u16("PREFERENCE")?,
+            flags: tokens.next_character_string("FLAGS")?,
+            services: tokens.next_character_string("SERVICES")?,
+            regexp: tokens.next_character_string("REGEXP")?,
+            replacement: tokens.next_name("REPLACEMENT")?,
+        },
+        #[cfg(feature = "edns")]
+        QueryType::OPT => anyhow::bail!("OPT record can't be used in zone files"),
+        QueryType::DS => ResourceData::DS {
+            key_tag: tokens.next_u16("key tag")?,
+            algorithm: tokens.next_u8("algorithm")?,
+            digest_type: tokens.next_u8("digest type")?,
+            digest: tokens.rest_hex("digest")?.into(),
+        },
+        QueryType::SSHFP => ResourceData::SSHFP {
+            algorithm: tokens.next_u8("algorithm")?,
+            fingerprint_type: tokens.next_u8("fingerprint type")?,
+            fingerprint: tokens.rest_hex("fingerprint")?.into(),
+        },
+        QueryType::RRSIG => ResourceData::RRSIG {
+            type_covered: tokens.next_parsed("type covered")?,
+            algorithm: tokens.next_u8("algorithm")?,
+            labels: tokens.next_u8("labels")?,
+            original_ttl: tokens.next_ttl("original TTL")?,
+            signature_expiration: parse_timestamp(tokens.next("signature expiration")?.text)?,
+            signature_inception: parse_timestamp(tokens.next("signature inception")?.text)?,
+            key_tag: tokens.next_u16("key tag")?,
+            signer_name: tokens.next_name("signer's name")?,
+            signature: tokens.rest_base64("signature")?.into(),
+        },
+        QueryType::NSEC => ResourceData::NSEC {
+            next_domain_name: tokens.next_name("next domain name")?,
+            type_bitmaps: tokens.rest_types()?,
+        },
+        QueryType::DNSKEY => ResourceData::DNSKEY {
+            flags: tokens.next_u16("flags")?,
+            protocol: tokens.next_u8("protocol")?,
+            algorithm: tokens.next_u8("algorithm")?,
+            public_key: tokens.rest_base64("public key")?.into(),
+        },
+        QueryType::NSEC3 => ResourceData::NSEC3 {
+            hash_algorithm: tokens.next_u8("hash algorithm")?,
+            flags: tokens.next_u8("flags")?,
+            iterations: tokens.next_u16("iterations")?,
+            salt: tokens.next_salt()?,
+            next_hashed_owner_name: {
+                let text = tokens.next("next hashed owner name")?.text;
+                BASE32HEX_NOPAD
+                    .decode(text.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("NSEC3 record: invalid next hashed owner name '{}'", text))?
+                    .into()
+            },
+            type_bitmaps: tokens.rest_types()?,
+        },
+        QueryType::NSEC3PARAM => ResourceData::NSEC3PARAM {
+            hash_algorithm: tokens.next_u8("hash algorithm")?,
+            flags: tokens.next_u8("flags")?,
+            iterations: tokens.next_u16("iterations")?,
+            salt: tokens.next_salt()?,
+        },
+        QueryType::TLSA => ResourceData::TLSA {
+            certificate_usage: tokens.next_u8("certificate usage")?,
+            selector: tokens.next_u8("selector")?,
+            matching_type: tokens.next_u8("matching type")?,
+            certificate_association_data: tokens.rest_hex("certificate association data")?.into(),
+        },
+        QueryType::SVCB | QueryType::HTTPS => {
+            let priority = tokens.next_u16("SvcPriority")?;
+            let target_name = tokens.next_name("TargetName")?;
+            let params = tokens.rest_svc_params()?;
+            if query_type == QueryType::SVCB {
+                ResourceData::SVCB {
+                    priority,
+                    target_name,
+                    params,
+                }
+            } else {
+                ResourceData::HTTPS {
+                    priority,
+                    target_name,
+                    params,
+                }
+            }
+        }
+        QueryType::ANY => anyhow::bail!("ANY record doesn't exist"),
+        QueryType::TSIG => anyhow::bail!("TSIG record can't be used in zone files"),
+        QueryType::CAA => ResourceData::CAA {
+            flags: tokens.next_u8("flags")?,
+            tag: {
+                let tag = tokens.next("tag")?.text;
+                validate_caa_tag(tag.as_bytes())?;
+                tag.to_owned().into()
+            },
+            value: unescape(tokens.next("value")?.text)?
+                .into_iter()
+                .map(|(byte, _)| byte)
+                .collect::<Vec<_>>()
+                .into(),
+        },
+    };
+
+    if tokens.has_next() {
+        anyhow::bail!(
+            "{} record: unexpected trailing data '{}'",
+            query_type,
+            tokens.tokens[tokens.pos].text
+        );
+    }
+
+    Ok(resource_data)
+}
+
+struct Tokens<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    pos: usize,
+    query_type: QueryType,
+    origin: &'t DomainName<'t>,
+}
+
+impl<'t, 'a> Tokens<'t, 'a> {
+    fn has_next(&self) -> bool {
+        self.pos < self.tokens.len()
+    }
+
+    fn next(&mut self, field: &str) -> anyhow::Result<&'t Token<'a>> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .with_context(|| format!("{} record: {} is missing", self.query_type, field))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn next_parsed<T: FromStr>(&mut self, field: &str) -> anyhow::Result<T> {
+        let text = self.next(field)?.text;
+        text.parse()
+            .map_err(|_| anyhow::anyhow!("{} record: invalid {} '{}'", self.query_type, field, text))
+    }
+
+    fn next_u8(&mut self, field: &str) -> anyhow::Result<u8> {
+        self.next_parsed(field)
+    }
+
+    fn next_u16(&mut self, field: &str) -> anyhow::Result<u16> {
+        self.next_parsed(field)
+    }
+
+    fn next_ttl(&mut self, field: &str) -> anyhow::Result<u32> {
+        let text = self.next(field)?.text;
+        parse_ttl(text).with_context(|| format!("{} record: invalid {} '{}'", self.query_type, field, text))
+    }
+
+    fn next_name(&mut self, field: &str) -> anyhow::Result<DomainName<'static>> {
+        let text = self.next(field)?.text;
+        parse_name(text, self.origin)
+            .with_context(|| format!("{} record: invalid {} '{}'", self.query_type, field, text))
+    }
+
+    fn next_character_string(&mut self, field: &str) -> anyhow::Result<Cow<'static, [u8]>> {
+        let text = self.next(field)?.text;
+        let data: Vec<u8> = unescape(text)?.into_iter().map(|(byte, _)| byte).collect();
+        if data.len() > 255 {
+            anyhow::bail!("{} record: {} is longer than 255 bytes", self.query_type, field);
+        }
+        Ok(data.into())
+    }
+
+    /// NSEC3 salt, where `-` means that there is no salt
+    fn next_salt(&mut self) -> anyhow::Result<Cow<'static, [u8]>> {
+        let text = self.next("salt")?.text;
+        if text == "-" {
+            return Ok(Cow::Borrowed(&[]));
+        }
+        let salt = HEXLOWER_PERMISSIVE
+            .decode(text.as_bytes())
+            .with_context(|| format!("{} record: invalid salt '{}'", self.query_type, text))?;
+        Ok(salt.into())
+    }
+
+    /// Remaining tokens joined together, as binary data can be split by whitespaces
+    fn rest_joined(&mut self, field: &str) -> anyhow::Result<String> {
+        if !self.has_next() {
+            anyhow::bail!("{} record: {} is missing", self.query_type, field);
+        }
+        let joined = self.tokens[self.pos..].iter().map(|token| token.text).collect();
+        self.pos = self.tokens.len();
+        Ok(joined)
+    }
+
+    fn rest_hex(&mut self, field: &str) -> anyhow::Result<Vec<u8>> {
+        let text = self.rest_joined(field)?;
+        HEXLOWER_PERMISSIVE
+            .decode(text.as_bytes())
+            .with_context(|| format!("{} record: {} is not valid hex", self.query_type, field))
+    }
+
+    fn rest_base64(&mut self, field: &str) -> anyhow::Result<Vec<u8>> {
+        let text = self.rest_joined(field)?;
+        BASE64
+            .decode(text.as_bytes())
+            .with_context(|| format!("{} record: {} is not valid base64", self.query_type, field))
+    }
+
+    fn rest_types(&mut self) -> anyhow::Result<Vec<QueryType>> {
+        let mut types = Vec::new();
+        while self.has_next() {
+            types.push(self.next_parsed("type bitmaps")?);
+        }
+        Ok(types)
+    }
+
+    fn rest_svc_params(&mut self) -> anyhow::Result<Vec<SvcParam<'static>>> {
+        let mut params = Vec::new();
+        while self.has_next() {
+            let token = self.next("SvcParams")?;
+            let (key, value) = match token.text.split_once('=') {
+                // Value can be quoted, which makes it a separate token
+                Some((key, "")) if self.tokens.get(self.pos).is_some_and(|token| token.quoted) => {
+                    (key, Some(self.next("SvcParamValue")?.text))
+                }
+                Some((key, value)) => (key, Some(value)),
+                None => (token.text, None),
+            };
+            let param = parse_svc_param(key, value)
+                .with_context(|| format!("{} record: invalid SvcParam '{}'", self.query_type, key))?;
+            params.push(param);
+        }
+
+        params.sort_by_key(|param| param.get_key());
+        if let Some(params) = params
+            .windows(2)
+            .find(|params| params[0].get_key() == params[1].get_key())
+        {
+            anyhow::bail!(
+                "{} record: duplicate SvcParam '{}'",
+                self.query_type,
+                params[0].get_key()
+            );
+        }
+
+        Ok(params)
+    }
+}
+
+fn parse_svc_param(key: &str, value: Option<&str>) -> anyhow::Result<SvcParam<'static>> {
+    let key = parse_svc_param_key(key)?;
+    let value = value.map(unescape).transpose()?.unwrap_or_default();
+    let value: Vec<u8> = value.into_iter().map(|(byte, _)| byte).collect();
+    // Values of the known keys are always ASCII
+    let value_str = || std::str::from_utf8(&value).context("value is not ASCII");
+
+    let param = match key {
+        0 => SvcParam::Mandatory {
+            keys: value_str()?
+                .split(',')
+                .map(parse_svc_param_key)
+                .collect::<anyhow::Result<_>>()?,
+        },
+        1 => SvcParam::Alpn {
+            protocols: split_value_list(&value)?.into_iter().map(Cow::Owned).collect(),
+        },
+        2 => {
+            if !value.is_empty() {
+                anyhow::bail!("'no-default-alpn' must have an empty value");
+            }
+            SvcParam::NoDefaultAlpn
+        }
+        3 => SvcParam::Port {
+            port: value_str()?.parse().context("invalid port")?,
+        },
+        4 => SvcParam::Ipv4Hint {
+            addresses: value_str()?
+                .split(',')
+                .map(|address| address.parse().context("invalid IPv4 address"))
+                .collect::<anyhow::Result<_>>()?,
+        },
+        5 => SvcParam::Ech {
+            config: BASE64.decode(&value).context("value is not valid base64")?.into(),
+        },
+        6 => SvcParam::Ipv6Hint {
+            addresses: value_str()?
+                .split(',')
+                .map(|address| address.parse().context("invalid IPv6 address"))
+                .collect::<anyhow::Result<_>>()?,
+        },
+        key => SvcParam::Unknown {
+            key,
+            value: value.into(),
+        },
+    };
+
+    Ok(param)
+}
+
+/// Splits the comma-separated list, where commas and backslashes inside of the items are escaped (RFC9460, Appendix
+/// A.1)
+fn split_value_list(value: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut items = vec![Vec::new()];
+    let mut bytes = value.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b',' => items.push(Vec::new()),
+            b'\\' => {
+                let escaped = bytes.next().context("unterminated escape sequence")?;
+                items.last_mut().unwrap().push(*escaped);
+            }
+            byte => items.last_mut().unwrap().push(*byte),
+        }
+    }
+    if items.iter().any(|item| item.is_empty()) {
+        anyhow::bail!("value list contains an empty item");
+    }
+    Ok(items)
+}
@@ -0,0 +1,148 @@
+use anyhow::Context;
+
+/// A single whitespace-separated token, where escape sequences are kept as is
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Token<'a> {
+    pub text: &'a str,
+    pub quoted: bool,
+}
+
+/// Tokens of a single logical line, that can span multiple lines using parentheses
+#[derive(Debug)]
+pub(super) struct Entry<'a> {
+    /// Number of the first line of this entry
+    pub line: usize,
+    /// Is `false` if the entry starts with a whitespace, which means that the previous owner should be used
+    pub has_owner: bool,
+    pub tokens: Vec<Token<'a>>,
+}
+
+pub(super) fn tokenize(input: &str) -> anyhow::Result<Vec<Entry<'_>>> {
+    let bytes = input.as_bytes();
+    let mut entries = Vec::new();
+    let mut line = 1;
+    let mut parentheses_depth = 0;
+    let mut entry = Entry {
+        line,
+        has_owner: !matches!(bytes.first(), Some(b' ' | b'\t')),
+        tokens: Vec::new(),
+    };
+
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\n' => {
+                line += 1;
+                pos += 1;
+                if parentheses_depth == 0 {
+                    if !entry.tokens.is_empty() {
+                        entries.push(entry);
+                    }
+                    entry = Entry {
+                        line,
+                        has_owner: !matches!(bytes.get(pos), Some(b' ' | b'\t')),
+                        tokens: Vec::new(),
+                    };
+                }
+            }
+            b' ' | b'\t' | b'\r' => pos += 1,
+            b';' => {
+                // Skip the comment, but keep the newline
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            b'(' => {
+                parentheses_depth += 1;
+                pos += 1;
+            }
+            b')' => {
+                if parentheses_depth == 0 {
+                    anyhow::bail!("line {}: unbalanced parentheses", line);
+                }
+                parentheses_depth -= 1;
+                pos += 1;
+            }
+            b'"' => {
+                let start = pos + 1;
+                let start_line = line;
+                pos = start;
+                loop {
+                    match bytes.get(pos) {
+                        Some(b'"') => break,
+                        Some(b'\\') => pos += 2,
+                        Some(b'\n') => {
+                            line += 1;
+                            pos += 1;
+                        }
+                        Some(_) => pos += 1,
+                        None => anyhow::bail!("line {}: unterminated quoted string", start_line),
+                    }
+                }
+                let text = input
+                    .get(start..pos)
+                    .with_context(|| format!("line {}: unterminated quoted string", start_line))?;
+                entry.tokens.push(Token { text, quoted: true });
+                // Skip the closing quote
+                pos += 1;
+            }
+            _ => {
+                let start = pos;
+                while pos < bytes.len() {
+                    match bytes[pos] {
+                        b' ' | b'\t' | b'\r' | b'\n' | b';' | b'(' | b')' | b'"' => break,
+                        // Escaped character is always a part of the token
+                        b'\\' => pos += 2,
+                        _ => pos += 1,
+                    }
+                }
+                let text = input
+                    .get(start..pos)
+                    .with_context(|| format!("line {}: unterminated escape sequence", line))?;
+                entry.tokens.push(Token { text, quoted: false });
+            }
+        }
+    }
+
+    if parentheses_depth != 0 {
+        anyhow::bail!("line {}: unbalanced parentheses", entry.line);
+    }
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_multiline_entry() {
+        let input = "@ IN SOA ns ( ; comment\n  host 1 ) \n\tIN TXT \"a \\\" b\" c\\ d\n";
+        let entries = tokenize(input).expect("shouldn't have failed");
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].has_owner);
+        assert_eq!(
+            entries[0].tokens.iter().map(|token| token.text).collect::<Vec<_>>(),
+            ["@", "IN", "SOA", "ns", "host", "1"]
+        );
+        assert_eq!(entries[1].line, 3);
+        assert!(!entries[1].has_owner);
+        assert_eq!(
+            entries[1].tokens[2],
+            Token {
+                text: "a \\\" b",
+                quoted: true
+            }
+        );
+        assert_eq!(entries[1].tokens[3].text, "c\\ d");
+    }
+
+    #[test]
+    #[should_panic(expected = "line 1: unbalanced parentheses")]
+    fn tokenize_unbalanced_parentheses() {
+        tokenize("@ IN SOA ( ns host\n").unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 
+use o_dns_lib::DomainName;
 use regex::Regex;
 
 use super::util::find_wildcard_parts;
@@ -32,19 +33,22 @@ impl Denylist {
         self.regexes.retain(|(id, _)| *id != id_to_delete);
     }
 
-    pub fn contains_entry(&self, qname: &str) -> bool {
+    pub fn contains_entry(&self, qname: &DomainName<'_>) -> bool {
+        // Entries are always stored in lowercase
+        let qname = qname.to_string().to_ascii_lowercase();
+
         // Look for a direct match first
-        if self.entries.contains(&hash_to_u128(qname, None)) {
+        if self.entries.contains(&hash_to_u128(&qname, None)) {
             return true;
         }
 
         // Look for a wildcard match
-        if self.find_wildcard_match(qname) {
+        if self.find_wildcard_match(&qname) {
             return true;
         };
 
         // Compare the qname against all regexes that we have
-        self.regexes.iter().any(|(_, re)| re.is_match(qname))
+        self.regexes.iter().any(|(_, re)| re.is_match(&qname))
     }
 
     fn find_wildcard_match(&self, qname: &str) -> bool {
@@ -54,3 +58,18 @@ impl Denylist {
             .is_some()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use o_dns_common::build_denylist_regex;
+
+    use super::*;
+
+    #[test]
+    fn regex_matches_regardless_of_case() {
+        let mut denylist = Denylist::new();
+        denylist.add_regex(1, build_denylist_regex(r"^ads\.Example\.COM$").unwrap());
+        assert!(denylist.contains_entry(&"ADS.example.com".parse().unwrap()));
+        assert!(!denylist.contains_entry(&"ads.example.org".parse().unwrap()));
+    }
+}
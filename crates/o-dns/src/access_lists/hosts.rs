@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use o_dns_lib::{QueryType, ResourceData};
+use o_dns_lib::{DomainName, QueryType, ResourceData};
 
 use super::util::find_wildcard_parts;
 use crate::util::hash_to_u128;
@@ -35,11 +35,13 @@ impl Hosts {
             .for_each(|vec| vec.retain(|rr| rr.get_query_type() != qtype));
     }
 
-    pub fn get_entry(&self, qname: &str) -> Option<&[ResourceData<'static>]> {
+    pub fn get_entry(&self, qname: &DomainName<'_>) -> Option<&[ResourceData<'static>]> {
+        // Entries are always stored in lowercase
+        let qname = qname.to_string().to_ascii_lowercase();
         self.map
-            .get(&hash_to_u128(qname, None))
+            .get(&hash_to_u128(&qname, None))
             .map(|records| records.as_slice())
-            .or_else(|| self.find_wildcard_match(qname))
+            .or_else(|| self.find_wildcard_match(&qname))
     }
 
     fn find_wildcard_match(&self, qname: &str) -> Option<&[ResourceData<'static>]> {
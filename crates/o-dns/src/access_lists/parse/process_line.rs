@@ -2,8 +2,8 @@ use std::net::IpAddr;
 use std::ops::Deref as _;
 
 use anyhow::Context;
+use o_dns_common::build_denylist_regex;
 use o_dns_db::{EntryKind, ListEntry, Model};
-use regex::Regex;
 use sqlx::SqliteConnection;
 
 use super::parsers::{parse_domain_name, parse_label, parse_regex};
@@ -52,7 +52,8 @@ impl EntryFromStr for Denylist {
             let (regex_str, remaining_line) = parse_regex(line).context("failed to parse regex")?;
 
             // Check if regex is okay
-            Regex::new(regex_str).map_err(|e| anyhow::anyhow!("failed to compile regex '{}': {}", regex_str, e))?;
+            build_denylist_regex(regex_str)
+                .map_err(|e| anyhow::anyhow!("failed to compile regex '{}': {}", regex_str, e))?;
 
             (None, EntryKind::DenyRegex, Some((&*regex_str).into()), remaining_line)
         } else {
@@ -1,26 +1,39 @@
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Context as _;
 use o_dns_api::ApiServer;
-use o_dns_common::{AccessListEntryKind, DnsServerCommand};
-use o_dns_db::{EntryKind, ListEntry, SqliteDb};
-use regex::Regex;
+use o_dns_common::{build_denylist_regex, AccessListEntryKind, DnsServerCommand};
+use o_dns_db::{EntryKind, ForwardingRuleEntry, ListEntry, SqliteDb};
 use sqlx::SqliteConnection;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::task::JoinSet;
 
 use crate::access_lists::{parse_denylist_file, parse_hosts_file};
 use crate::query_logger::QueryLogger;
-use crate::util::{hash_to_u128, read_checksum, write_to_file};
-use crate::{Args, DnsServer};
+use crate::util::{hash_to_u128, read_checksum, read_tls_server_config, read_tsig_keys, write_to_file};
+use crate::{Args, DnsServer, UpstreamConfig};
 
 pub struct App;
 
 impl App {
     pub async fn run_until_completion(args: Args, config_path: PathBuf) -> anyhow::Result<()> {
         let dns_bind_addr = SocketAddr::new(args.host, args.port);
-        let upstream_resolver_addr = SocketAddr::new(args.upstream_resolver, args.upstream_port);
+        let upstream_resolvers = args
+            .upstream_resolvers
+            .iter()
+            .cloned()
+            .map(|addr| addr.with_default_port(args.upstream_port))
+            .collect();
+        let upstream_config = UpstreamConfig {
+            strategy: args.upstream_strategy,
+            timeout: Duration::from_millis(args.upstream_timeout_ms),
+            retries: args.upstream_retries,
+            backoff: Duration::from_millis(args.upstream_backoff_ms),
+            deadline: Duration::from_millis(args.upstream_deadline_ms),
+            doh_method: args.upstream_doh_method,
+        };
 
         // Channel for query logs
         let (log_tx, log_rx) = unbounded_channel();
@@ -72,14 +85,26 @@ impl App {
             .await
             .context("failed to commit entries from denylist and hosts files")?;
 
+        let tsig_keys = match args.tsig_keys_path.as_ref() {
+            Some(path) => read_tsig_keys(path).await.context("failed to read TSIG keys")?,
+            None => Vec::new(),
+        };
+
         let query_logger = QueryLogger::new(log_rx, sqlite_db.clone())
             .await
             .context("error while creating a query logger")?;
 
         let (command_tx, command_rx) = tokio::sync::mpsc::channel(10);
-        let mut server = DnsServer::new(dns_bind_addr, upstream_resolver_addr, log_tx, command_rx)
-            .await
-            .context("failed to instantiate the DNS server")?;
+        let mut server = DnsServer::new(
+            dns_bind_addr,
+            upstream_resolvers,
+            upstream_config,
+            tsig_keys,
+            log_tx,
+            command_rx,
+        )
+        .await
+        .context("failed to instantiate the DNS server")?;
 
         // Fill hosts and denylist with additional data from DB
         let mut connection = sqlite_db.get_connection().await?;
@@ -89,13 +114,65 @@ impl App {
             }
         }
 
+        // Restore the forwarding rules managed through the API
+        for entry in ForwardingRuleEntry::select_all(&mut connection).await? {
+            if let Err(e) = server.set_forwarding_rule(&entry.into()).await {
+                tracing::warn!("Failed to add a forwarding rule: {:#}", e);
+            }
+        }
+
+        let tls_config = match (args.tls_cert_path.as_ref(), args.tls_key_path.as_ref()) {
+            (Some(cert_path), Some(key_path)) => Some(
+                read_tls_server_config(cert_path, key_path)
+                    .await
+                    .context("failed to load the TLS certificate")?,
+            ),
+            _ => None,
+        };
+
+        if args.enable_dot {
+            let tls_config = tls_config
+                .clone()
+                .context("DNS-over-TLS requires both a certificate and a private key")?;
+            server
+                .add_tls_listener(
+                    SocketAddr::new(args.host, args.dot_port),
+                    tls_config,
+                    Duration::from_millis(args.dot_idle_timeout_ms),
+                )
+                .await
+                .context("failed to start the DNS-over-TLS listener")?;
+        }
+
+        if args.enable_doq {
+            let tls_config = tls_config
+                .clone()
+                .context("DNS-over-QUIC requires both a certificate and a private key")?;
+            server
+                .add_quic_listener(
+                    SocketAddr::new(args.host, args.doq_port),
+                    tls_config,
+                    Duration::from_millis(args.doq_idle_timeout_ms),
+                )
+                .await
+                .context("failed to start the DNS-over-QUIC listener")?;
+        }
+
         let mut tasks = JoinSet::new();
         server.add_workers(args.max_parallel_connections).await;
         tasks.spawn(server.block_until_completion());
         tasks.spawn(query_logger.watch_for_logs());
+        let api_server = ApiServer::new(sqlite_db, command_tx);
+        if args.enable_doh {
+            let tls_config = tls_config.context("DNS-over-HTTPS requires both a certificate and a private key")?;
+            tasks.spawn(
+                api_server
+                    .clone()
+                    .serve_doh(SocketAddr::new(args.host, args.doh_port), tls_config),
+            );
+        }
         if !args.disable_api_server {
             let api_server_bind_addr = SocketAddr::new(args.host, args.api_server_port);
-            let api_server = ApiServer::new(sqlite_db, command_tx);
             tasks.spawn(api_server.serve(api_server_bind_addr));
         }
 
@@ -114,11 +191,13 @@ impl App {
         let dynamic_entries = ListEntry::select_all(connection).await?;
 
         Ok(dynamic_entries.into_iter().filter_map(|entry| {
-            let domain = entry.domain.map(|domain| hash_to_u128(domain.as_ref(), None));
+            let domain = entry
+                .domain
+                .map(|domain| hash_to_u128(domain.to_ascii_lowercase(), None));
             Some(match entry.kind {
                 EntryKind::Deny => AccessListEntryKind::DenyDomain(domain?),
                 EntryKind::DenyRegex => {
-                    AccessListEntryKind::DenyRegex((entry.id, Some(Regex::new(&entry.data?).ok()?)))
+                    AccessListEntryKind::DenyRegex((entry.id, Some(build_denylist_regex(&entry.data?).ok()?)))
                 }
                 EntryKind::AllowA | EntryKind::AllowAAAA => {
                     AccessListEntryKind::Hosts((domain?, entry.data?.parse::<IpAddr>().ok()?))
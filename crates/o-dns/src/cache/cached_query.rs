@@ -1,6 +1,6 @@
 use std::time::Instant;
 
-use o_dns_lib::DnsPacket;
+use o_dns_lib::{DnsPacket, ResponseCode};
 
 use super::cached_record::CacheFlags;
 
@@ -9,6 +9,7 @@ pub(super) struct CachedQuery {
     pub(super) authorities: Option<Vec<u128>>,
     pub(super) additionals: Option<Vec<u128>>,
     pub(super) flags: CacheFlags,
+    pub(super) response_code: ResponseCode,
     pub(super) added: Instant,
     pub(super) ttd: u32,
 }
@@ -30,6 +31,7 @@ impl CachedQuery {
             authorities: None,
             additionals: None,
             flags,
+            response_code: response_packet.header.response_code,
             added: Instant::now(),
             ttd,
         }
@@ -1,7 +1,8 @@
 use std::time::Instant;
 
+use anyhow::Context as _;
 use bitflags::bitflags;
-use o_dns_lib::{ResourceData, ResourceRecord};
+use o_dns_lib::{ByteBuf, DomainName, EncodeToBuf as _, QueryType, ResourceData, ResourceRecord};
 use sha1::Digest as _;
 
 bitflags! {
@@ -12,7 +13,7 @@ bitflags! {
 }
 
 pub(super) struct CachedRecord {
-    pub(super) qname: String,
+    pub(super) qname: DomainName<'static>,
     pub(super) resource_data: ResourceData<'static>,
     pub(super) ttl: u32,
     pub(super) class: u16,
@@ -25,7 +26,7 @@ impl CachedRecord {
         let mut flags = CacheFlags::empty();
         flags.set(CacheFlags::AD, authenticated_data);
         CachedRecord {
-            qname: value.name.into_owned(),
+            qname: value.name,
             resource_data: value.resource_data,
             ttl: value.ttl,
             class: value.class,
@@ -34,38 +35,34 @@ impl CachedRecord {
         }
     }
 
-    pub(super) fn get_hash(&self) -> u128 {
-        let qtype: u16 = self.resource_data.get_query_type().into();
+    pub(super) fn get_hash(&self) -> anyhow::Result<u128> {
+        let qtype = self.resource_data.get_query_type();
+        if qtype == QueryType::OPT {
+            anyhow::bail!("bug: we shouldn't cache OPT RRs");
+        }
 
         let mut hasher = sha1::Sha1::new();
 
-        hasher.update(self.qname.as_bytes());
-        hasher.update(qtype.to_be_bytes());
+        hasher.update(self.qname.to_ascii_lowercase().as_wire());
+        hasher.update(u16::from(qtype).to_be_bytes());
         hasher.update(self.class.to_be_bytes());
 
-        // Hash the rdata
-        match &self.resource_data {
-            ResourceData::UNKNOWN { rdata, .. } => {
-                hasher.update(rdata);
-            }
-            ResourceData::A { address } => {
-                hasher.update(address.octets());
-            }
-            ResourceData::NS { ns_domain_name } => hasher.update(ns_domain_name.as_bytes()),
-            ResourceData::CNAME { cname } => hasher.update(cname.as_bytes()),
-            ResourceData::AAAA { address } => hasher.update(address.octets()),
-            ResourceData::OPT { .. } => unreachable!("bug: we shouldn't cache OPT RRs"),
-        };
+        // Hash the rdata in its uncompressed wire format, as it's the same for every RR type
+        let mut rdata = ByteBuf::new_empty(None);
+        self.resource_data
+            .encode_to_buf(&mut rdata, None)
+            .context("error while encoding the RDATA")?;
+        hasher.update(&*rdata);
 
         let hash = hasher.finalize();
 
-        u128::from_be_bytes(hash[..16].try_into().unwrap())
+        Ok(u128::from_be_bytes(hash[..16].try_into().unwrap()))
     }
 
     pub(super) fn as_rr(&self) -> ResourceRecord<'static> {
         let ttl = self.ttl.saturating_sub(self.added.elapsed().as_secs() as u32);
         ResourceRecord::new(
-            self.qname.to_owned().into(),
+            self.qname.clone(),
             self.resource_data.clone(),
             Some(ttl),
             Some(self.class),
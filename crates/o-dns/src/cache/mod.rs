@@ -7,7 +7,7 @@ use cached_record::{CacheFlags, CachedRecord};
 use hashlink::LinkedHashMap;
 use o_dns_lib::{DnsPacket, QueryType, Question};
 
-use crate::util::{get_caching_duration_for_packet, get_dns_query_hash, is_dnssec_qtype};
+use crate::util::{get_caching_duration_for_packet, get_dns_query_hash};
 
 const DEFAULT_CACHE_CAPACITY: usize = 1000;
 
@@ -24,7 +24,9 @@ impl Cache {
         }
     }
 
-    pub fn cache_response(&mut self, response: &DnsPacket<'static>) -> anyhow::Result<()> {
+    /// Responses to the queries matching a forwarding rule are cached under the hash of its domain,
+    /// separately from the responses of the default upstreams
+    pub fn cache_response(&mut self, response: &DnsPacket<'_>, forwarding_rule: Option<u128>) -> anyhow::Result<()> {
         let cache_for = get_caching_duration_for_packet(response);
 
         if cache_for < 15 {
@@ -40,23 +42,24 @@ impl Cache {
             (&response.additionals, &mut cached_query.additionals),
         ];
 
-        sections.into_iter().for_each(|(response_section, cached_section)| {
-            response_section.iter().for_each(|rr| {
+        for (response_section, cached_section) in sections {
+            for rr in response_section.iter() {
                 // Don't cache OPT RRs
                 if rr.resource_data.get_query_type() != QueryType::OPT {
-                    let cached_rr = CachedRecord::new(rr.clone(), response.header.z[1]);
-                    let hash = cached_rr.get_hash();
+                    let cached_rr = CachedRecord::new(rr.clone().into_owned(), response.header.z[1]);
+                    let hash = cached_rr.get_hash().context("failed to hash an RR")?;
                     cached_section.get_or_insert(Vec::new()).push(hash);
                     self.rr_cache.insert(hash, cached_rr);
                 }
-            });
-        });
+            }
+        }
 
         let hash = get_dns_query_hash(
             response
                 .questions
                 .first()
                 .context("malformed response packet: question is missing")?,
+            forwarding_rule,
         );
 
         self.query_cache.insert(hash, cached_query);
@@ -64,8 +67,14 @@ impl Cache {
         Ok(())
     }
 
-    pub fn question_lookup(&self, question: &Question, response_packet: &mut DnsPacket, dnssec: bool) -> bool {
-        let hash = get_dns_query_hash(question);
+    pub fn question_lookup(
+        &self,
+        question: &Question,
+        forwarding_rule: Option<u128>,
+        response_packet: &mut DnsPacket,
+        dnssec: bool,
+    ) -> bool {
+        let hash = get_dns_query_hash(question, forwarding_rule);
         let Some(cached_query) = self.query_cache.get(&hash) else {
             tracing::debug!(
                 qname = ?question.qname,
@@ -100,61 +109,56 @@ impl Cache {
             "Cache hit"
         );
 
-        // Check whether other queries didn't override authenticated data that we need
         let require_ad = cached_query.flags.contains(CacheFlags::AD);
-        response_packet.header.z[1] = require_ad;
-        let include_dnssec_rrs = dnssec || is_dnssec_qtype(question.query_type.into());
-
-        // Process each section
-        let sections = [
-            (
-                &cached_query.answers,
-                &mut response_packet.answers,
-                &mut response_packet.header.answer_rr_count,
-            ),
-            (
-                &cached_query.authorities,
-                &mut response_packet.authorities,
-                &mut response_packet.header.authority_rr_count,
-            ),
-            (
-                &cached_query.additionals,
-                &mut response_packet.additionals,
-                &mut response_packet.header.additional_rr_count,
-            ),
-        ];
+        let include_dnssec_rrs = dnssec || question.query_type.is_dnssec();
+
+        // Look up every section first, so that the response isn't modified if any RR is missing
+        let lookup_section = |cached_section: &Option<Vec<u128>>| {
+            let mut records = Vec::new();
+            for rr_hash in cached_section.iter().flatten() {
+                let Some(cached_rr) = self.rr_cache.get(rr_hash) else {
+                    tracing::debug!(
+                        qname = ?question.qname,
+                        qtype = ?question.query_type,
+                        rr_hash,
+                        "RR is missing. Doing a lookup"
+                    );
+                    return None;
+                };
+
+                if !include_dnssec_rrs && cached_rr.resource_data.get_query_type().is_dnssec() {
+                    continue;
+                }
 
-        for (cached_section, response_section, count) in sections {
-            if let Some(records) = cached_section {
-                for rr_hash in records.iter() {
-                    let Some(cached_rr) = self.rr_cache.get(rr_hash) else {
-                        tracing::debug!(
-                            qname = ?question.qname,
-                            qtype = ?question.query_type,
-                            rr_hash,
-                            "RR is missing. Doing a lookup"
-                        );
-                        return false;
-                    };
-
-                    if !include_dnssec_rrs && is_dnssec_qtype(cached_rr.resource_data.get_query_type().into()) {
-                        continue;
-                    }
-
-                    if require_ad && !cached_rr.flags.contains(CacheFlags::AD) {
-                        tracing::debug!(
-                            qname = ?cached_rr.qname,
-                            qtype = ?cached_rr.resource_data.get_query_type(),
-                            "DNSSEC-validated RR was overridden. Doing a lookup"
-                        );
-                        return false;
-                    }
-
-                    response_section.push(cached_rr.as_rr());
-                    *count += 1;
+                if require_ad && !cached_rr.flags.contains(CacheFlags::AD) {
+                    tracing::debug!(
+                        qname = ?cached_rr.qname,
+                        qtype = ?cached_rr.resource_data.get_query_type(),
+                        "DNSSEC-validated RR was overridden. Doing a lookup"
+                    );
+                    return None;
                 }
+
+                records.push(cached_rr.as_rr());
             }
-        }
+            Some(records)
+        };
+        let (Some(answers), Some(authorities), Some(additionals)) = (
+            lookup_section(&cached_query.answers),
+            lookup_section(&cached_query.authorities),
+            lookup_section(&cached_query.additionals),
+        ) else {
+            return false;
+        };
+
+        // Check whether other queries didn't override authenticated data that we need
+        response_packet.header.z[1] = require_ad;
+        response_packet.header.response_code = cached_query.response_code;
+        answers.into_iter().for_each(|rr| response_packet.add_answer(rr));
+        authorities.into_iter().for_each(|rr| response_packet.add_authority(rr));
+        additionals
+            .into_iter()
+            .for_each(|rr| response_packet.add_additional(rr));
 
         true
     }
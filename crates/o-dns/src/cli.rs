@@ -3,6 +3,8 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
+use crate::{DohMethod, UpstreamAddr, UpstreamStrategy};
+
 #[derive(Parser)]
 #[command(version, name = "o-dns")]
 pub struct Args {
@@ -16,12 +18,67 @@ pub struct Args {
     pub host: IpAddr,
     #[arg(short('p'), long, value_name = "PORT", default_value_t = 53)]
     pub port: u16,
-    #[arg(long, value_name = "ADDR", default_value = "1.1.1.1")]
-    pub upstream_resolver: IpAddr,
+    /// Upstream resolver as `ADDR[:PORT]`, `tls://ADDR[:PORT][#NAME][?spki=PIN]`, `quic://ADDR[:PORT][#NAME][?spki=PIN]`
+    /// or `https://HOST[:PORT][/PATH][#BOOTSTRAP_IP]`, can be repeated
+    #[arg(
+        long = "upstream-resolver",
+        value_name = "ADDR",
+        default_value = "1.1.1.1",
+        value_delimiter = ','
+    )]
+    pub upstream_resolvers: Vec<UpstreamAddr>,
+    /// Port used for the upstream resolvers without an explicit port
     #[arg(long, value_name = "PORT", default_value_t = 53)]
     pub upstream_port: u16,
+    #[arg(long, value_name = "STRATEGY", value_enum, default_value_t = UpstreamStrategy::Strict)]
+    pub upstream_strategy: UpstreamStrategy,
+    /// Timeout of a single query to an upstream resolver
+    #[arg(long, value_name = "MILLIS", default_value_t = 2000)]
+    pub upstream_timeout_ms: u64,
+    /// Number of retries over all upstream resolvers after all of them have failed or timed out
+    #[arg(long, value_name = "RETRIES", default_value_t = 2)]
+    pub upstream_retries: u8,
+    /// Delay before the first retry, doubled after each subsequent one
+    #[arg(long, value_name = "MILLIS", default_value_t = 100)]
+    pub upstream_backoff_ms: u64,
+    /// Overall time limit for resolving a query with the upstream resolvers, including retries
+    #[arg(long, value_name = "MILLIS", default_value_t = 5000)]
+    pub upstream_deadline_ms: u64,
+    /// HTTP method used for DNS-over-HTTPS upstreams
+    #[arg(long, value_name = "METHOD", value_enum, default_value_t = DohMethod::Post)]
+    pub upstream_doh_method: DohMethod,
     #[arg(long, value_name = "PATH")]
     pub config_path: Option<PathBuf>,
+    /// File with TSIG keys in the `algorithm:name:secret` format, one per line
+    #[arg(long, value_name = "PATH")]
+    pub tsig_keys_path: Option<PathBuf>,
+    /// PEM-encoded certificate chain used by the encrypted listeners
+    #[arg(long, value_name = "PATH", requires = "tls_key_path")]
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM-encoded private key of the certificate
+    #[arg(long, value_name = "PATH", requires = "tls_cert_path")]
+    pub tls_key_path: Option<PathBuf>,
+    /// Accept DNS-over-TLS connections, requires a certificate
+    #[arg(long, default_value_t = false, requires = "tls_cert_path")]
+    pub enable_dot: bool,
+    #[arg(long, value_name = "PORT", default_value_t = 853)]
+    pub dot_port: u16,
+    /// Time after which DNS-over-TLS sessions without queries are closed
+    #[arg(long, value_name = "MILLIS", default_value_t = 10000)]
+    pub dot_idle_timeout_ms: u64,
+    /// Accept DNS-over-QUIC connections, requires a certificate
+    #[arg(long, default_value_t = false, requires = "tls_cert_path")]
+    pub enable_doq: bool,
+    #[arg(long, value_name = "PORT", default_value_t = 853)]
+    pub doq_port: u16,
+    /// Time after which DNS-over-QUIC connections without any traffic are closed
+    #[arg(long, value_name = "MILLIS", default_value_t = 30000)]
+    pub doq_idle_timeout_ms: u64,
+    /// Serve the API server's DNS-over-HTTPS endpoint on a separate TLS listener, requires a certificate
+    #[arg(long, default_value_t = false, requires = "tls_cert_path")]
+    pub enable_doh: bool,
+    #[arg(long, value_name = "PORT", default_value_t = 443)]
+    pub doh_port: u16,
     #[arg(short('s'), long, default_value_t = false)]
     pub disable_api_server: bool,
     #[arg(long, value_name = "PORT", default_value_t = 80)]
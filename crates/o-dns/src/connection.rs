@@ -2,9 +2,12 @@ use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use anyhow::Context as _;
-use o_dns_lib::ByteBuf;
-use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use o_dns_lib::{read_tcp_message, write_tcp_message, ByteBuf};
+use quinn::{RecvStream, SendStream};
+use tokio::io::WriteHalf;
 use tokio::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use tokio::sync::Mutex;
+use tokio_rustls::server::TlsStream;
 
 use crate::DEFAULT_EDNS_BUF_CAPACITY;
 
@@ -12,6 +15,11 @@ use crate::DEFAULT_EDNS_BUF_CAPACITY;
 pub enum Connection<U: AsyncUdpSocket> {
     Tcp(TcpStream),
     Udp((U, Option<SocketAddr>)),
+    /// Writer of a DNS-over-TLS (RFC7858) session, which uses the same framing as TCP.
+    /// It's shared by the queries resolved concurrently, while the session itself reads them
+    Tls((Arc<Mutex<WriteHalf<TlsStream<TcpStream>>>>, SocketAddr)),
+    /// Single stream of a DNS-over-QUIC (RFC9250) connection, which carries exactly one query and its response
+    Quic((SendStream, RecvStream, SocketAddr)),
 }
 
 pub trait AsyncUdpSocket {
@@ -69,16 +77,11 @@ impl AsyncUdpSocket for Arc<UdpSocket> {
 impl<U: AsyncUdpSocket> Connection<U> {
     pub async fn send_encoded_packet(&mut self, src: &[u8]) -> anyhow::Result<()> {
         match self {
-            Connection::Tcp(socket) => {
-                let length = (src.len() as u16).to_be_bytes();
-                socket
-                    .write_all(&length)
-                    .await
-                    .context("TCP: error while sending packet's length")?;
-                socket
-                    .write_all(src)
-                    .await
-                    .context("TCP: error while sending a DNS packet")?;
+            Connection::Tcp(socket) => write_tcp_message(socket, src).await?,
+            Connection::Tls((writer, _)) => write_tcp_message(&mut *writer.lock().await, src).await?,
+            Connection::Quic((send, _, _)) => {
+                write_tcp_message(send, src).await?;
+                send.finish().context("QUIC: error while finishing the stream")?;
             }
             Connection::Udp((socket, addr)) => {
                 if let Some(addr) = addr {
@@ -100,20 +103,9 @@ impl<U: AsyncUdpSocket> Connection<U> {
 
     pub async fn read(&mut self, dst: &mut ByteBuf<'_>) -> anyhow::Result<usize> {
         let packet_length = match self {
-            Connection::Tcp(socket) => {
-                let length = socket
-                    .read_u16()
-                    .await
-                    .context("TCP: error while reading packet's length")? as usize;
-                if dst.len() < length {
-                    dst.resize(length);
-                }
-                socket
-                    .read_exact(&mut dst[..length])
-                    .await
-                    .context("TCP: error while reading a packet")?;
-                length
-            }
+            Connection::Tcp(socket) => read_tcp_message(socket, dst).await?,
+            Connection::Tls(_) => anyhow::bail!("bug: DoT queries are read by the session"),
+            Connection::Quic((_, recv, _)) => read_tcp_message(recv, dst).await?,
             Connection::Udp((socket, _)) => {
                 if dst.len() < DEFAULT_EDNS_BUF_CAPACITY {
                     dst.resize(DEFAULT_EDNS_BUF_CAPACITY);
@@ -131,6 +123,7 @@ impl<U: AsyncUdpSocket> Connection<U> {
                 .peer_addr()
                 .map(|socket_addr| socket_addr.ip())
                 .context("bug: TCP socket is not connected?"),
+            Connection::Tls((_, addr)) | Connection::Quic((_, _, addr)) => Ok(addr.ip()),
             Connection::Udp((socket, addr)) => addr
                 .map(|socket_addr| socket_addr.ip())
                 .or_else(|| socket.peer_addr().ok())
@@ -138,7 +131,8 @@ impl<U: AsyncUdpSocket> Connection<U> {
         }
     }
 
+    /// Whether the connection is stream-based, so the responses never have to be truncated
     pub fn is_tcp(&self) -> bool {
-        matches!(self, Connection::Tcp(_))
+        matches!(self, Connection::Tcp(_) | Connection::Tls(_) | Connection::Quic(_))
     }
 }
@@ -6,7 +6,10 @@ mod cache;
 mod connection;
 pub use connection::Connection;
 mod resolver;
-pub use resolver::Resolver;
+pub use resolver::{
+    DohMethod, ForwardingRules, HttpsUpstreamAddr, Resolver, TlsUpstreamAddr, Upstream, UpstreamAddr, UpstreamConfig,
+    UpstreamStrategy, Upstreams,
+};
 mod server;
 pub use server::DnsServer;
 mod cli;
@@ -16,9 +19,8 @@ pub use app::App;
 mod query_logger;
 mod util;
 
-use std::net::SocketAddr;
-
 use cache::Cache;
+use o_dns_lib::TsigKey;
 use tokio::sync::RwLock;
 
 /// Recommended eDNS buf size
@@ -27,21 +29,39 @@ pub const DEFAULT_EDNS_BUF_CAPACITY: usize = 1232;
 pub const MAX_STANDARD_DNS_MSG_SIZE: usize = 512;
 // EDNS DO BIT
 pub const EDNS_DO_BIT: u32 = 1 << 15;
+/// Extended DNS Error INFO-CODE for blocked domains (RFC8914)
+pub const EDE_BLOCKED: u16 = 15;
+/// Extended RCODE for unsupported EDNS versions (RFC6891)
+pub const EDNS_BADVERS: u32 = 16;
+/// ALPN token of DNS-over-QUIC (RFC9250)
+pub const DOQ_ALPN: &[u8] = b"doq";
 
 pub struct State {
-    pub upstream_resolver: SocketAddr,
+    pub upstreams: Upstreams,
+    pub upstream_config: UpstreamConfig,
     pub denylist: RwLock<Denylist>,
     pub hosts: RwLock<Hosts>,
     pub cache: RwLock<Cache>,
+    /// Upstreams used for specific domains instead of the default ones
+    pub forwarding_rules: RwLock<ForwardingRules>,
+    /// Keys used to verify signed queries
+    pub tsig_keys: Vec<TsigKey>,
 }
 
 impl State {
-    pub async fn new(upstream_resolver: SocketAddr) -> anyhow::Result<Self> {
+    pub async fn new(
+        upstream_resolvers: Vec<UpstreamAddr>,
+        upstream_config: UpstreamConfig,
+        tsig_keys: Vec<TsigKey>,
+    ) -> anyhow::Result<Self> {
         Ok(State {
-            upstream_resolver,
+            upstreams: Upstreams::new(upstream_resolvers)?,
+            upstream_config,
+            tsig_keys,
             denylist: Default::default(),
             hosts: Default::default(),
             cache: Default::default(),
+            forwarding_rules: Default::default(),
         })
     }
 }
@@ -0,0 +1,100 @@
+//! Conditional forwarding of specific domains to dedicated upstream resolvers
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use o_dns_lib::DomainName;
+
+use super::Upstreams;
+use crate::util::hash_to_u128;
+
+/// Rules keyed by the hash of the domain in the lowercase wire format
+#[derive(Default)]
+pub struct ForwardingRules {
+    rules: HashMap<u128, Arc<Upstreams>>,
+}
+
+impl ForwardingRules {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds the rule or replaces the existing one for the same domain
+    pub fn set_rule(&mut self, domain: &DomainName<'_>, upstreams: Upstreams) {
+        self.rules.insert(get_domain_hash(domain), Arc::new(upstreams));
+    }
+
+    pub fn remove_rule(&mut self, domain: &DomainName<'_>) {
+        self.rules.remove(&get_domain_hash(domain));
+    }
+
+    /// Finds the rule with the longest domain that `qname` is equal to or is below,
+    /// returning the hash of its domain along with its upstreams
+    pub fn find_rule(&self, qname: &DomainName<'_>) -> Option<(u128, Arc<Upstreams>)> {
+        if self.rules.is_empty() {
+            return None;
+        }
+
+        let mut name = qname.to_ascii_lowercase();
+        loop {
+            let hash = hash_to_u128(name.as_wire(), None);
+            if let Some(upstreams) = self.rules.get(&hash) {
+                return Some((hash, upstreams.clone()));
+            }
+            name = name.parent()?.into_owned();
+        }
+    }
+}
+
+fn get_domain_hash(domain: &DomainName<'_>) -> u128 {
+    hash_to_u128(domain.to_ascii_lowercase().as_wire(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UpstreamAddr;
+
+    fn set_rule(rules: &mut ForwardingRules, domain: &str, upstream: &str) {
+        let upstreams = Upstreams::new([upstream.parse::<UpstreamAddr>().unwrap()]).unwrap();
+        rules.set_rule(&domain.parse().unwrap(), upstreams);
+    }
+
+    /// Returns the domain of the matching rule
+    fn find_rule<'d>(rules: &ForwardingRules, qname: &str, domains: &[&'d str]) -> Option<&'d str> {
+        let (hash, _) = rules.find_rule(&qname.parse().unwrap())?;
+        domains
+            .iter()
+            .copied()
+            .find(|domain| get_domain_hash(&domain.parse().unwrap()) == hash)
+    }
+
+    #[test]
+    fn matches_longest_suffix() {
+        let domains = ["lan", "corp.example", "dev.corp.example", "10.in-addr.arpa"];
+        let mut rules = ForwardingRules::new();
+        set_rule(&mut rules, "lan", "192.168.1.1");
+        set_rule(&mut rules, "corp.example", "10.8.0.1");
+        set_rule(&mut rules, "Dev.Corp.Example", "10.8.0.2");
+        set_rule(&mut rules, "10.in-addr.arpa", "10.0.0.10");
+
+        assert_eq!(find_rule(&rules, "nas.lan", &domains), Some("lan"));
+        assert_eq!(find_rule(&rules, "corp.example", &domains), Some("corp.example"));
+        assert_eq!(find_rule(&rules, "wiki.CORP.example", &domains), Some("corp.example"));
+        assert_eq!(
+            find_rule(&rules, "ci.dev.corp.example", &domains),
+            Some("dev.corp.example")
+        );
+        assert_eq!(
+            find_rule(&rules, "4.3.2.10.in-addr.arpa", &domains),
+            Some("10.in-addr.arpa")
+        );
+        assert_eq!(find_rule(&rules, "4.3.2.1.in-addr.arpa", &domains), None);
+        // Only whole labels match
+        assert_eq!(find_rule(&rules, "notcorp.example", &domains), None);
+        assert_eq!(find_rule(&rules, "example", &domains), None);
+
+        rules.remove_rule(&"dev.corp.example".parse().unwrap());
+        assert_eq!(find_rule(&rules, "ci.dev.corp.example", &domains), Some("corp.example"));
+    }
+}
@@ -1,21 +1,42 @@
+mod forwarding;
 mod upstream;
 
+use std::borrow::Cow;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 
 use anyhow::Context as _;
-use o_dns_common::{AccessListEntryKind, ResponseSource};
+pub use forwarding::ForwardingRules;
+use o_dns_common::{AccessListEntryKind, ApiQueryResponse, ForwardingRule, ResponseSource};
 use o_dns_db::QueryLog;
 use o_dns_lib::{
-    ByteBuf, DnsPacket, EncodeToBuf as _, QueryType, Question, ResourceData, ResourceRecord, ResponseCode,
+    ByteBuf, DnsError, DnsPacket, DomainName, EdnsOption, EncodeToBuf as _, FromBuf as _, QueryType, Question,
+    ResourceData, ResourceRecord, ResponseCode, TsigKey,
 };
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tokio::time::error::Elapsed;
 use tokio::time::Instant;
 use upstream::resolve_with_upstream;
+pub use upstream::{
+    DohMethod, HttpsUpstreamAddr, TlsUpstreamAddr, Upstream, UpstreamAddr, UpstreamConfig, UpstreamStrategy, Upstreams,
+};
 
-use crate::util::get_response_dns_packet;
-use crate::{Connection, State, DEFAULT_EDNS_BUF_CAPACITY, MAX_STANDARD_DNS_MSG_SIZE};
+use crate::util::{
+    add_edns_option, get_response_dns_packet, get_unix_timestamp, set_error_response_code, sign_response,
+};
+use crate::{Connection, State, DEFAULT_EDNS_BUF_CAPACITY, EDE_BLOCKED, MAX_STANDARD_DNS_MSG_SIZE};
+
+/// Response to a query, before it's sent back to the client
+struct Resolution<'a> {
+    packet: DnsPacket<'a>,
+    /// Encoded and possibly signed response
+    encoded: ByteBuf<'static>,
+    source: Option<ResponseSource>,
+    /// Upstream resolver that has answered the query
+    upstream: Option<String>,
+}
 
 pub struct Resolver {
     state: Arc<State>,
@@ -31,10 +52,48 @@ impl Resolver {
 
     pub async fn resolve_query(
         self: Arc<Self>,
-        mut connection: Connection<Arc<UdpSocket>>,
-        parsed_packet: anyhow::Result<DnsPacket<'static>>,
+        connection: &mut Connection<Arc<UdpSocket>>,
+        query: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let start = Instant::now();
+        let resolution = self.resolve(&query, connection.is_tcp()).await?;
+
+        if let Err(e) = connection.send_encoded_packet(&resolution.encoded).await {
+            // Do not propagate the error, as it's per-user and thus recoverable
+            tracing::error!("Error while sending a DNS response: {:#}", e)
+        };
+
+        self.log_query(&resolution, connection.get_client_addr().ok(), start);
+
+        Ok(())
+    }
+
+    /// Resolves a query received by the API server, e.g. over DoH, and sends the encoded response back to it
+    pub async fn resolve_api_query(
+        self: Arc<Self>,
+        query: Vec<u8>,
+        client: Option<IpAddr>,
+        response_tx: oneshot::Sender<ApiQueryResponse>,
     ) -> anyhow::Result<()> {
         let start = Instant::now();
+        // HTTP responses are never truncated, same as the TCP ones
+        let resolution = self.resolve(&query, true).await?;
+
+        // The API server may have given up on the query already
+        let _ = response_tx.send(ApiQueryResponse {
+            response: resolution.encoded.to_vec(),
+            source: resolution.source,
+            ttl: resolution.packet.get_minimum_ttl(),
+        });
+
+        self.log_query(&resolution, client, start);
+
+        Ok(())
+    }
+
+    /// Runs the query through the whole pipeline and returns the encoded response, without sending it
+    async fn resolve<'a>(&self, query: &'a [u8], is_tcp: bool) -> anyhow::Result<Resolution<'a>> {
+        let parsed_packet = DnsPacket::from_buf(&mut ByteBuf::new_from_cow(Cow::Borrowed(query)));
 
         let requestor_edns_buf_size = parsed_packet.as_ref().ok().and_then(|packet| {
             packet.edns.and_then(|idx| {
@@ -49,17 +108,52 @@ impl Resolver {
         // Create an empty response packet and copy the relevant settings from the query
         let mut response_packet = get_response_dns_packet(parsed_packet.as_ref().ok(), None);
 
+        // Signed queries get signed responses, even if the verification has failed
+        let now = get_unix_timestamp();
+        let mut tsig = None;
+        // Upstream resolver that has answered the query
+        let mut upstream = None;
+        // Hash of the domain of the forwarding rule that the query has matched
+        let mut forwarding_rule = None;
+
         let (add_response_to_cache, source) = 'resolve: {
-            let Ok(query_packet) = parsed_packet.as_ref() else {
-                response_packet.header.response_code = ResponseCode::FormatError;
-                break 'resolve (false, None);
+            let query_packet = match parsed_packet.as_ref() {
+                Ok(query_packet) => query_packet,
+                Err(e) => {
+                    tracing::debug!("Rejected a malformed query: {:#}", e);
+                    match e.downcast_ref::<DnsError>() {
+                        Some(dns_error) => set_error_response_code(&mut response_packet, dns_error),
+                        None => response_packet.header.response_code = ResponseCode::FormatError,
+                    }
+                    break 'resolve (false, None);
+                }
             };
+            tracing::trace!("Received a query:\n{}", query_packet);
 
-            if query_packet.header.question_count > 1 || query_packet.questions.len() > 1 {
-                response_packet.header.response_code = ResponseCode::FormatError;
+            if let Err(e) = query_packet.validate_query() {
+                tracing::debug!("Rejected an unsupported query: {}", e);
+                set_error_response_code(&mut response_packet, &e);
                 break 'resolve (false, None);
             }
-            let question = &query_packet.questions[0];
+
+            if let Some(tsig_rr) = query_packet.get_tsig_rr() {
+                let (key, verification) = self.verify_tsig(query, tsig_rr, now);
+                let is_verified = verification.is_ok();
+                if let Err(e) = &verification {
+                    tracing::debug!(key = ?tsig_rr.name, "Rejected a signed query: {}", e);
+                    set_error_response_code(&mut response_packet, e);
+                }
+                tsig = Some((tsig_rr, key, verification));
+                if !is_verified {
+                    break 'resolve (false, None);
+                }
+            }
+
+            // Queries must have exactly one question
+            let [question] = query_packet.questions.as_slice() else {
+                response_packet.header.response_code = ResponseCode::FormatError;
+                break 'resolve (false, None);
+            };
 
             let dnssec = if let Some(edns_data) = query_packet
                 .edns
@@ -95,75 +189,158 @@ impl Resolver {
                 break 'resolve (false, Some(ResponseSource::NoRecurse));
             }
 
+            // Match forwarding rules before the cache lookup, so that their responses are cached separately
+            let rule_upstreams = match self.state.forwarding_rules.read().await.find_rule(&question.qname) {
+                Some((hash, upstreams)) => {
+                    tracing::debug!(qname = ?question.qname, "Query has matched a forwarding rule");
+                    forwarding_rule = Some(hash);
+                    Some(upstreams)
+                }
+                None => None,
+            };
+            let upstreams = rule_upstreams.as_deref().unwrap_or(&self.state.upstreams);
+
             // Check if query is cached
-            if self.cache_lookup(question, &mut response_packet, dnssec).await {
+            if self
+                .cache_lookup(question, forwarding_rule, &mut response_packet, dnssec)
+                .await
+            {
                 // Cache hit
                 break 'resolve (false, Some(ResponseSource::Cache));
             }
 
-            // Try to resolve with the configured upstream resolver
-            if let Err(e) = self
-                .resolve_with_upstream(question, query_packet.header.id, dnssec, &mut response_packet)
+            // Try to resolve with the configured upstream resolvers
+            let checking_disabled = query_packet.header.z[2];
+            match self
+                .resolve_with_upstream(
+                    upstreams,
+                    question,
+                    query_packet.header.id,
+                    dnssec,
+                    checking_disabled,
+                    &mut response_packet,
+                )
                 .await
             {
-                tracing::debug!(resolver = ?self.state.upstream_resolver, "Upstream resolution failed: {:#}", e);
+                Ok(answered_by) => {
+                    upstream = Some(answered_by.to_string());
+                    // Responses that skipped DNSSEC validation shouldn't be served to the clients that want it
+                    (!checking_disabled, Some(ResponseSource::Upstream))
+                }
+                // Don't cache SERVFAIL caused by a timeout, as the upstream resolvers may be just temporarily unreachable
+                Err(e) if e.downcast_ref::<Elapsed>().is_some() => {
+                    tracing::warn!("Upstream resolution timed out: {:#}", e);
+                    (false, Some(ResponseSource::UpstreamTimeout))
+                }
+                Err(e) => {
+                    tracing::debug!("Upstream resolution failed: {:#}", e);
+                    (true, Some(ResponseSource::Upstream))
+                }
             }
-
-            (true, Some(ResponseSource::Upstream))
         };
 
         // Add original questions to the response if possible and wasn't done before
         if response_packet.questions.is_empty() {
             if let Ok(packet) = parsed_packet.as_ref() {
-                response_packet.questions = packet.questions.clone();
-                response_packet.header.question_count = packet.header.question_count;
+                packet
+                    .questions
+                    .iter()
+                    .for_each(|question| response_packet.add_question(question.clone()));
             }
         }
 
         // Encode the response packet
+        // UDP: truncate the response if the requestor's buffer is too small
+        let max_size = (!is_tcp).then(|| requestor_edns_buf_size.unwrap_or(MAX_STANDARD_DNS_MSG_SIZE));
         let mut dst = ByteBuf::new_empty(Some(DEFAULT_EDNS_BUF_CAPACITY));
         response_packet
-            .encode_to_buf(
-                &mut dst,
-                // UDP: truncate the response if the requestor's buffer is too small
-                (!connection.is_tcp()).then(|| requestor_edns_buf_size.unwrap_or(MAX_STANDARD_DNS_MSG_SIZE)),
-            )
+            .encode_to_buf(&mut dst, max_size)
             .context("error while encoding the response")?;
 
+        if let Some((tsig_rr, key, verification)) = &tsig {
+            let unsigned_size = dst.len();
+            sign_response(&mut dst, tsig_rr, *key, verification, now).context("error while signing the response")?;
+
+            // TSIG RR must fit into the requestor's buffer as well (RFC8945, section 5.3),
+            // so encode the response again with the room for it
+            if let Some(max_size) = max_size.filter(|max_size| dst.len() > *max_size) {
+                let tsig_rr_size = dst.len() - unsigned_size;
+                dst = ByteBuf::new_empty(Some(DEFAULT_EDNS_BUF_CAPACITY));
+                response_packet
+                    .encode_to_buf(&mut dst, Some(max_size.saturating_sub(tsig_rr_size)))
+                    .context("error while encoding the response")?;
+                sign_response(&mut dst, tsig_rr, *key, verification, now)
+                    .context("error while signing the response")?;
+            }
+        }
+
+        tracing::trace!(?source, "Sending a response:\n{}", response_packet);
+
         if add_response_to_cache {
             let mut cache = self.state.cache.write().await;
             cache
-                .cache_response(&response_packet)
+                .cache_response(&response_packet, forwarding_rule)
                 .context("bug: caching has failed?")?;
         }
 
-        if let Err(e) = connection.send_encoded_packet(&dst).await {
-            // Do not propagate the error, as it's per-user and thus recoverable
-            tracing::error!("Error while sending a DNS response: {:#}", e)
-        };
+        Ok(Resolution {
+            packet: response_packet,
+            encoded: dst,
+            source,
+            upstream,
+        })
+    }
 
+    fn log_query(&self, resolution: &Resolution<'_>, client: Option<IpAddr>, start: Instant) {
         let log_entry = match QueryLog::new_from_response(
-            &response_packet,
-            connection.get_client_addr().ok(),
+            &resolution.packet,
+            client,
             start.elapsed().as_millis() as u32,
-            source,
+            resolution.source,
+            resolution.upstream.clone(),
         ) {
             Ok(log_entry) => log_entry,
             Err(e) => {
                 tracing::debug!("Failed to create a log entry: {}", e);
-                return Ok(());
+                return;
             }
         };
 
         // We don't care if the receiving end was dropped already, as we can't do nothing about it
         let _ = self.log_tx.send(log_entry);
+    }
 
-        Ok(())
+    /// Verifies the query's TSIG RR with the key of the same name
+    fn verify_tsig(
+        &self,
+        query: &[u8],
+        tsig_rr: &ResourceRecord<'_>,
+        now: u64,
+    ) -> (Option<&TsigKey>, Result<Vec<u8>, DnsError>) {
+        let Some(key) = self.state.tsig_keys.iter().find(|key| key.name == tsig_rr.name) else {
+            return (None, Err(DnsError::BadTsigKey));
+        };
+
+        let verification = key
+            .verify(query, None, now)
+            .map_err(|e| match e.downcast::<DnsError>() {
+                Ok(dns_error) => dns_error,
+                // The query was already parsed, so it's the TSIG RR that doesn't match the message
+                Err(_) => DnsError::BadTsigSignature,
+            });
+
+        (Some(key), verification)
     }
 
-    async fn cache_lookup(&self, question: &Question<'_>, response_packet: &mut DnsPacket<'_>, dnssec: bool) -> bool {
+    async fn cache_lookup(
+        &self,
+        question: &Question<'_>,
+        forwarding_rule: Option<u128>,
+        response_packet: &mut DnsPacket<'_>,
+        dnssec: bool,
+    ) -> bool {
         let cache = self.state.cache.read().await;
-        cache.question_lookup(question, response_packet, dnssec)
+        cache.question_lookup(question, forwarding_rule, response_packet, dnssec)
     }
 
     async fn denylist_lookup<'a>(&self, question: &Question<'a>, response_packet: &mut DnsPacket<'a>) -> bool {
@@ -186,9 +363,15 @@ impl Resolver {
             };
             if let Some(rdata) = rdata {
                 let rr = ResourceRecord::new(question.qname.clone(), rdata, Some(180), None);
-                response_packet.answers.push(rr);
-                response_packet.header.answer_rr_count += 1;
+                response_packet.add_answer(rr);
             }
+            add_edns_option(
+                response_packet,
+                EdnsOption::ExtendedDnsError {
+                    info_code: EDE_BLOCKED,
+                    extra_text: "".into(),
+                },
+            );
         }
 
         is_in_denylist
@@ -196,7 +379,7 @@ impl Resolver {
 
     async fn hosts_lookup<'a>(&self, question: &Question<'a>, response_packet: &mut DnsPacket<'a>) -> bool {
         let hosts = self.state.hosts.read().await;
-        let hosts_records = hosts.get_entry(question.qname.as_ref());
+        let hosts_records = hosts.get_entry(&question.qname);
 
         if let Some(records) = hosts_records {
             response_packet.header.is_authoritative = true;
@@ -208,52 +391,75 @@ impl Resolver {
                 })
                 .for_each(|rdata| {
                     let rr = ResourceRecord::new(question.qname.clone(), rdata.clone(), Some(180), None);
-                    response_packet.answers.push(rr);
-                    response_packet.header.answer_rr_count += 1;
+                    response_packet.add_answer(rr);
                 });
         }
 
         !response_packet.answers.is_empty()
     }
 
-    async fn resolve_with_upstream(
+    async fn resolve_with_upstream<'u>(
         &self,
+        upstreams: &'u Upstreams,
         question: &Question<'_>,
         id: u16,
         dnssec: bool,
+        checking_disabled: bool,
         response_packet: &mut DnsPacket<'_>,
-    ) -> anyhow::Result<()> {
-        let upstream_response = match resolve_with_upstream(question, id, self.state.upstream_resolver, dnssec).await {
-            Ok((upstream_response, _)) => upstream_response,
+    ) -> anyhow::Result<&'u Upstream> {
+        let (upstream_response, upstream) = match resolve_with_upstream(
+            question,
+            id,
+            upstreams,
+            &self.state.upstream_config,
+            dnssec,
+            checking_disabled,
+        )
+        .await
+        {
+            Ok((upstream_response, upstream)) => {
+                tracing::trace!(resolver = %upstream, "Upstream response:\n{}", upstream_response);
+                (upstream_response, upstream)
+            }
             Err(e) => {
                 response_packet.header.response_code = ResponseCode::ServerFailure;
-                anyhow::bail!("Error while forwarding a request to the upstream resolver: {:#}", e);
+                return Err(e.context("Error while forwarding a request to the upstream resolver"));
             }
         };
 
-        response_packet.questions = upstream_response.questions;
-        response_packet.header.question_count = upstream_response.header.question_count;
-
-        response_packet.answers = upstream_response.answers;
-        response_packet.header.answer_rr_count = upstream_response.header.answer_rr_count;
-
-        upstream_response.additionals.into_iter().for_each(|rr| {
-            // OPT RR is alredy present if EDNS is supported by the requestor
-            if rr.resource_data.get_query_type() != QueryType::OPT {
-                response_packet.additionals.push(rr);
-                response_packet.header.additional_rr_count += 1;
-            }
-        });
-
-        response_packet.authorities = upstream_response.authorities;
-        response_packet.header.authority_rr_count = upstream_response.header.authority_rr_count;
+        response_packet.header.response_code = upstream_response.header.response_code;
+        upstream_response
+            .questions
+            .into_iter()
+            .for_each(|question| response_packet.add_question(question));
+        upstream_response
+            .answers
+            .into_iter()
+            .for_each(|rr| response_packet.add_answer(rr));
+        upstream_response
+            .authorities
+            .into_iter()
+            .for_each(|rr| response_packet.add_authority(rr));
+
+        upstream_response
+            .additionals
+            .into_iter()
+            .for_each(|rr| match rr.resource_data {
+                // OPT RR is alredy present if EDNS is supported by the requestor,
+                // so only pass through extended errors, as other options are hop-by-hop
+                ResourceData::OPT { options } => options
+                    .into_iter()
+                    .filter(|option| matches!(option, EdnsOption::ExtendedDnsError { .. }))
+                    .for_each(|option| add_edns_option(response_packet, option)),
+                _ => response_packet.add_additional(rr),
+            });
 
         // AD bit
         if upstream_response.header.z[1] {
             response_packet.header.z[1] = true;
         }
 
-        Ok(())
+        Ok(upstream)
     }
 
     pub async fn add_list_entry(&self, entry: AccessListEntryKind) -> anyhow::Result<()> {
@@ -295,4 +501,204 @@ impl Resolver {
             }
         }
     }
+
+    pub async fn set_forwarding_rule(&self, rule: &ForwardingRule) -> anyhow::Result<()> {
+        let domain: DomainName = rule.domain.parse().context("invalid forwarding rule domain")?;
+        let upstream_resolvers = rule
+            .upstreams
+            .iter()
+            .map(|upstream| upstream.parse::<UpstreamAddr>())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let upstreams = Upstreams::new(upstream_resolvers)?;
+
+        self.state.forwarding_rules.write().await.set_rule(&domain, upstreams);
+
+        Ok(())
+    }
+
+    pub async fn remove_forwarding_rule(&self, domain: &str) -> anyhow::Result<()> {
+        let domain: DomainName = domain.parse().context("invalid forwarding rule domain")?;
+        self.state.forwarding_rules.write().await.remove_rule(&domain);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use o_dns_lib::TsigAlgorithm;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use super::*;
+    use crate::util::hash_to_u128;
+    use crate::{DohMethod, UpstreamStrategy};
+
+    /// Spawns an upstream resolver that answers every query with NXDOMAIN and reports the queries' CD bit
+    async fn spawn_nxdomain_upstream() -> (SocketAddr, UnboundedReceiver<bool>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (cd_tx, cd_rx) = unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+                let query = DnsPacket::from_buf(&mut ByteBuf::new_from_cow(Cow::Borrowed(&buf[..len]))).unwrap();
+                let _ = cd_tx.send(query.header.z[2]);
+
+                let mut response = get_response_dns_packet(Some(&query), Some(ResponseCode::NameError));
+                query
+                    .questions
+                    .iter()
+                    .for_each(|question| response.add_question(question.clone()));
+                let mut dst = ByteBuf::new_empty(None);
+                response.encode_to_buf(&mut dst, None).unwrap();
+                socket.send_to(&dst, client).await.unwrap();
+            }
+        });
+
+        (addr, cd_rx)
+    }
+
+    async fn get_resolver(upstream: SocketAddr, tsig_keys: Vec<TsigKey>) -> Resolver {
+        let upstream_config = UpstreamConfig {
+            strategy: UpstreamStrategy::Strict,
+            timeout: Duration::from_secs(1),
+            retries: 0,
+            backoff: Duration::ZERO,
+            deadline: Duration::from_secs(1),
+            doh_method: DohMethod::Post,
+        };
+        let state = State::new(vec![upstream.to_string().parse().unwrap()], upstream_config, tsig_keys)
+            .await
+            .unwrap();
+        let (log_tx, _log_rx) = unbounded_channel();
+        Resolver::new(state, log_tx)
+    }
+
+    fn get_query(checking_disabled: bool) -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 1;
+        packet.header.recursion_desired = true;
+        // CD bit
+        packet.header.z[2] = checking_disabled;
+        packet.add_question(Question::new("nonexistent.test".parse().unwrap(), QueryType::A, None));
+
+        let mut buf = ByteBuf::new_empty(None);
+        packet.encode_to_buf(&mut buf, None).unwrap();
+        buf.into_inner().into_owned()
+    }
+
+    #[tokio::test]
+    async fn keeps_upstream_response_code() {
+        let (upstream, mut cd_rx) = spawn_nxdomain_upstream().await;
+        let resolver = get_resolver(upstream, Vec::new()).await;
+        let query = get_query(false);
+
+        let resolution = resolver.resolve(&query, false).await.unwrap();
+        assert!(matches!(resolution.source, Some(ResponseSource::Upstream)));
+        assert_eq!(resolution.packet.header.response_code, ResponseCode::NameError);
+        assert_eq!(cd_rx.try_recv(), Ok(false));
+
+        // Cached negative response
+        let resolution = resolver.resolve(&query, false).await.unwrap();
+        assert!(matches!(resolution.source, Some(ResponseSource::Cache)));
+        assert_eq!(resolution.packet.header.response_code, ResponseCode::NameError);
+        assert!(cd_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_queries_without_a_single_question() {
+        let (upstream, mut cd_rx) = spawn_nxdomain_upstream().await;
+        let resolver = get_resolver(upstream, Vec::new()).await;
+
+        for question_count in [0, 2] {
+            let mut packet = DnsPacket::new();
+            packet.header.id = 1;
+            packet.header.recursion_desired = true;
+            for _ in 0..question_count {
+                packet.add_question(Question::new("nonexistent.test".parse().unwrap(), QueryType::A, None));
+            }
+            let mut buf = ByteBuf::new_empty(None);
+            packet.encode_to_buf(&mut buf, None).unwrap();
+
+            let resolution = resolver.resolve(&buf, false).await.unwrap();
+            assert!(resolution.source.is_none());
+            assert_eq!(resolution.packet.header.response_code, ResponseCode::FormatError);
+        }
+        assert!(cd_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn forwards_cd_bit_without_caching() {
+        let (upstream, mut cd_rx) = spawn_nxdomain_upstream().await;
+        let resolver = get_resolver(upstream, Vec::new()).await;
+
+        let query = get_query(true);
+        for _ in 0..2 {
+            let resolution = resolver.resolve(&query, false).await.unwrap();
+            assert!(matches!(resolution.source, Some(ResponseSource::Upstream)));
+            assert!(resolution.packet.header.z[2]);
+            assert_eq!(cd_rx.try_recv(), Ok(true));
+        }
+
+        // Response to the query with the CD bit wasn't cached for the validating clients either
+        let query = get_query(false);
+        let resolution = resolver.resolve(&query, false).await.unwrap();
+        assert!(matches!(resolution.source, Some(ResponseSource::Upstream)));
+        assert_eq!(cd_rx.try_recv(), Ok(false));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn doesnt_cache_upstream_timeouts() {
+        // Upstream that never replies
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = get_resolver(socket.local_addr().unwrap(), Vec::new()).await;
+
+        let query = get_query(false);
+        for _ in 0..2 {
+            let resolution = resolver.resolve(&query, false).await.unwrap();
+            assert!(matches!(resolution.source, Some(ResponseSource::UpstreamTimeout)));
+            assert_eq!(resolution.packet.header.response_code, ResponseCode::ServerFailure);
+        }
+    }
+
+    #[tokio::test]
+    async fn signed_udp_response_fits_with_tsig_rr() {
+        let key = TsigKey::new("key".parse().unwrap(), TsigAlgorithm::HmacSha256, vec![0x42; 32]);
+        let resolver = get_resolver("127.0.0.1:53".parse().unwrap(), vec![key.clone()]).await;
+        // Unsigned response with all the records takes 507 bytes
+        for i in 0..30 {
+            let entry =
+                AccessListEntryKind::Hosts((hash_to_u128("host.test", None), Ipv4Addr::new(192, 0, 2, i).into()));
+            resolver.add_list_entry(entry).await.unwrap();
+        }
+
+        let mut packet = DnsPacket::new();
+        packet.header.id = 1;
+        packet.add_question(Question::new("host.test".parse().unwrap(), QueryType::A, None));
+        let mut query = ByteBuf::new_empty(None);
+        packet.encode_to_buf(&mut query, None).unwrap();
+        let now = get_unix_timestamp();
+        let request_mac = key.sign(&mut query, None, now).unwrap();
+        let query = query.into_inner().into_owned();
+
+        let resolution = resolver.resolve(&query, false).await.unwrap();
+        assert!(resolution.encoded.len() <= MAX_STANDARD_DNS_MSG_SIZE);
+        key.verify(&resolution.encoded, Some(&request_mac), now)
+            .expect("response should have been signed");
+        let response = DnsPacket::from_buf(&mut ByteBuf::new_from_cow(Cow::Borrowed(&resolution.encoded))).unwrap();
+        assert!(response.header.truncation);
+        // RRset doesn't fit as a whole with the TSIG RR
+        assert!(response.answers.is_empty());
+
+        // Nothing is truncated over TCP
+        let resolution = resolver.resolve(&query, true).await.unwrap();
+        let response = DnsPacket::from_buf(&mut ByteBuf::new_from_cow(Cow::Borrowed(&resolution.encoded))).unwrap();
+        assert!(!response.header.truncation);
+        assert_eq!(response.answers.len(), 30);
+    }
 }
@@ -0,0 +1,379 @@
+//! DNS-over-HTTPS (RFC8484) upstream, where queries are multiplexed over a single persistent HTTP/2 connection
+
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use bytes::Bytes;
+use clap::ValueEnum;
+use data_encoding::BASE64URL_NOPAD;
+use http_body_util::{BodyExt as _, Full, Limited};
+use hyper::client::conn::http2::SendRequest;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::{Method, Request, StatusCode, Uri};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use o_dns_lib::{ByteBuf, DnsPacket, EncodeToBuf as _, FromBuf as _};
+use rustls::pki_types::ServerName;
+use rustls::RootCertStore;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use super::tls::{get_client_config, get_webpki_roots};
+
+/// Media type of the wire format DNS messages (RFC8484)
+pub const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+const DEFAULT_HTTPS_PORT: u16 = 443;
+
+/// HTTP method used for DoH queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DohMethod {
+    /// Query in the `dns` parameter, which makes responses cacheable by HTTP caches
+    Get,
+    Post,
+}
+
+/// URL of a DoH upstream along with the address to connect to
+#[derive(Debug, Clone)]
+pub struct HttpsUpstreamAddr {
+    pub url: Uri,
+    /// Either the URL's IP or the bootstrap IP, so that the hostname is never resolved through this server
+    pub addr: SocketAddr,
+    /// Used for SNI and to verify the certificate
+    pub server_name: ServerName<'static>,
+}
+
+impl HttpsUpstreamAddr {
+    /// Parses `https://HOST[:PORT][/PATH][#BOOTSTRAP_IP]`, where the bootstrap IP is required if `HOST` is a hostname
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (url, bootstrap_ip) = match s.split_once('#') {
+            Some((url, bootstrap_ip)) => {
+                let bootstrap_ip: IpAddr = bootstrap_ip
+                    .parse()
+                    .with_context(|| format!("invalid bootstrap IP '{}'", bootstrap_ip))?;
+                (url, Some(bootstrap_ip))
+            }
+            None => (s, None),
+        };
+        let url: Uri = url.parse().with_context(|| format!("invalid DoH URL '{}'", url))?;
+        if url.scheme_str() != Some("https") {
+            anyhow::bail!("DoH URL '{}' must use the https scheme", url);
+        }
+
+        let host = url
+            .host()
+            .with_context(|| format!("DoH URL '{}' is missing a host", url))?;
+        let host_ip = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host)
+            .parse::<IpAddr>()
+            .ok();
+        let ip = bootstrap_ip.or(host_ip).with_context(|| {
+            format!(
+                "DoH upstream '{}' requires a bootstrap IP, e.g. '{}#192.0.2.1'",
+                url, url
+            )
+        })?;
+        let server_name = match host_ip {
+            Some(host_ip) => ServerName::from(host_ip),
+            None => ServerName::try_from(host.to_string()).with_context(|| format!("invalid DoH host '{}'", host))?,
+        };
+
+        Ok(HttpsUpstreamAddr {
+            addr: SocketAddr::new(ip, url.port_u16().unwrap_or(DEFAULT_HTTPS_PORT)),
+            url,
+            server_name,
+        })
+    }
+}
+
+impl Display for HttpsUpstreamAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+pub struct HttpsClient {
+    upstream: HttpsUpstreamAddr,
+    connector: TlsConnector,
+    sender: tokio::sync::Mutex<Option<SendRequest<Full<Bytes>>>>,
+}
+
+impl HttpsClient {
+    pub fn new(upstream: HttpsUpstreamAddr) -> anyhow::Result<Self> {
+        HttpsClient::new_with_roots(upstream, get_webpki_roots())
+    }
+
+    fn new_with_roots(upstream: HttpsUpstreamAddr, roots: RootCertStore) -> anyhow::Result<Self> {
+        let mut config = get_client_config(roots, None)?;
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        Ok(HttpsClient {
+            upstream,
+            connector: TlsConnector::from(Arc::new(config)),
+            sender: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    pub fn get_upstream(&self) -> &HttpsUpstreamAddr {
+        &self.upstream
+    }
+
+    pub async fn query(&self, query: &DnsPacket<'_>, method: DohMethod) -> anyhow::Result<DnsPacket<'static>> {
+        let mut buf = ByteBuf::new_empty(None);
+        query
+            .encode_to_buf(&mut buf, None)
+            .context("error while encoding the query")?;
+        // ID is always 0 to make responses cacheable (RFC8484, section 4.1)
+        buf.set_u16(0, 0)?;
+
+        let request = match method {
+            DohMethod::Get => {
+                let separator = if self.upstream.url.query().is_some() { '&' } else { '?' };
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(format!(
+                        "{}{}dns={}",
+                        self.upstream.url,
+                        separator,
+                        BASE64URL_NOPAD.encode(&buf)
+                    ))
+                    .body(Full::default())
+            }
+            DohMethod::Post => Request::builder()
+                .method(Method::POST)
+                .uri(self.upstream.url.clone())
+                .header(CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE)
+                .body(Full::new(Bytes::from(buf.into_inner().into_owned()))),
+        }
+        .and_then(|mut request| {
+            request.headers_mut().insert(ACCEPT, DNS_MESSAGE_CONTENT_TYPE.parse()?);
+            Ok(request)
+        })
+        .context("HTTPS: error while building the request")?;
+
+        let response = self
+            .get_sender()
+            .await?
+            .send_request(request)
+            .await
+            .context("HTTPS: error while sending the request")?;
+        if response.status() != StatusCode::OK {
+            anyhow::bail!("HTTPS: upstream resolver responded with {}", response.status());
+        }
+        if response
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_none_or(|content_type| content_type != DNS_MESSAGE_CONTENT_TYPE)
+        {
+            anyhow::bail!(
+                "HTTPS: unexpected content type {:?}",
+                response.headers().get(CONTENT_TYPE)
+            );
+        }
+
+        let body = Limited::new(response.into_body(), u16::MAX as usize)
+            .collect()
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+            .context("HTTPS: error while reading the response")?
+            .to_bytes();
+        let mut response = DnsPacket::from_buf(&mut ByteBuf::new_from_vec(body.to_vec()))
+            .context("error while decoding the response")?;
+        if !response.questions.is_empty() && response.questions != query.questions {
+            anyhow::bail!("HTTPS: response doesn't match the query");
+        }
+        response.header.id = query.header.id;
+
+        Ok(response)
+    }
+
+    /// Returns the handle to the existing connection, or establishes a new one if there is none
+    async fn get_sender(&self) -> anyhow::Result<SendRequest<Full<Bytes>>> {
+        let mut sender = self.sender.lock().await;
+        if let Some(sender) = sender.as_ref().filter(|sender| !sender.is_closed()) {
+            return Ok(sender.clone());
+        }
+
+        let stream = TcpStream::connect(self.upstream.addr)
+            .await
+            .context("HTTPS: error while connecting to the upstream resolver")?;
+        let stream = self
+            .connector
+            .connect(self.upstream.server_name.clone(), stream)
+            .await
+            .context("HTTPS: TLS handshake with the upstream resolver failed")?;
+        if stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+            anyhow::bail!("HTTPS: upstream resolver doesn't support HTTP/2");
+        }
+
+        let (new_sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await
+                .context("HTTPS: HTTP/2 handshake with the upstream resolver failed")?;
+        let upstream = self.upstream.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!(resolver = upstream, "HTTPS: upstream connection was closed: {:#}", e);
+            }
+        });
+        tracing::debug!(resolver = %self.upstream, "HTTPS: established a new connection");
+
+        *sender = Some(new_sender.clone());
+
+        Ok(new_sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::resolver::upstream::test_utils::{get_query, get_test_certificate, TestCertificate};
+
+    struct TestServer {
+        addr: SocketAddr,
+        roots: RootCertStore,
+        connections: Arc<AtomicUsize>,
+    }
+
+    /// Extracts the query from either a GET or a POST request
+    async fn get_query_from_request(request: Request<Incoming>) -> Option<Vec<u8>> {
+        match *request.method() {
+            Method::GET => {
+                let dns = request
+                    .uri()
+                    .query()?
+                    .split('&')
+                    .find_map(|param| param.strip_prefix("dns="))?;
+                BASE64URL_NOPAD.decode(dns.as_bytes()).ok()
+            }
+            Method::POST => {
+                if request.headers().get(CONTENT_TYPE)? != DNS_MESSAGE_CONTENT_TYPE {
+                    return None;
+                }
+                Some(request.into_body().collect().await.ok()?.to_bytes().to_vec())
+            }
+            _ => None,
+        }
+    }
+
+    async fn handle_request(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+        if request.uri().path() != "/dns-query" {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::default())
+                .unwrap());
+        }
+        let Some(query) = get_query_from_request(request).await else {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Full::default())
+                .unwrap());
+        };
+
+        let mut response = DnsPacket::from_buf(&mut ByteBuf::new_from_vec(query)).unwrap();
+        assert_eq!(response.header.id, 0, "DoH queries should use ID 0");
+        response.header.is_response = true;
+        let mut buf = ByteBuf::new_empty(None);
+        response.encode_to_buf(&mut buf, None).unwrap();
+
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE)
+            .body(Full::new(Bytes::from(buf.into_inner().into_owned())))
+            .unwrap())
+    }
+
+    /// Starts an HTTP/2 DoH stand-in with a certificate for `dns.test`, which serves queries at `/dns-query`
+    async fn spawn_server() -> TestServer {
+        let TestCertificate {
+            mut server_config,
+            roots,
+            ..
+        } = get_test_certificate();
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let connections_clone = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                connections_clone.fetch_add(1, Ordering::Relaxed);
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+                tokio::spawn(
+                    hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service_fn(handle_request)),
+                );
+            }
+        });
+
+        TestServer {
+            addr,
+            roots,
+            connections,
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_with_get_and_post_over_single_connection() {
+        let server = spawn_server().await;
+        let upstream =
+            HttpsUpstreamAddr::parse(&format!("https://dns.test:{}/dns-query#127.0.0.1", server.addr.port())).unwrap();
+        assert_eq!(upstream.addr, server.addr);
+        let client = HttpsClient::new_with_roots(upstream, server.roots.clone()).unwrap();
+
+        for (qname, method) in [
+            ("get.example", DohMethod::Get),
+            ("post.example", DohMethod::Post),
+            ("another.example", DohMethod::Get),
+        ] {
+            let query = get_query(qname);
+            let response = client.query(&query, method).await.expect("shouldn't have failed");
+            assert!(response.header.is_response);
+            assert_eq!(response.header.id, query.header.id);
+            assert_eq!(response.questions, query.questions);
+        }
+        assert_eq!(server.connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_status() {
+        let server = spawn_server().await;
+        let upstream =
+            HttpsUpstreamAddr::parse(&format!("https://dns.test:{}/wrong-path#127.0.0.1", server.addr.port())).unwrap();
+        let client = HttpsClient::new_with_roots(upstream, server.roots.clone()).unwrap();
+
+        let query = get_query("example.com");
+        assert!(client.query(&query, DohMethod::Post).await.is_err());
+    }
+
+    #[test]
+    fn parse_https_upstream_addr() {
+        let addr = HttpsUpstreamAddr::parse("https://dns.google/dns-query#8.8.8.8").unwrap();
+        assert_eq!(addr.addr, "8.8.8.8:443".parse().unwrap());
+        assert_eq!(addr.server_name, ServerName::try_from("dns.google").unwrap());
+        assert_eq!(addr.to_string(), "https://dns.google/dns-query");
+
+        let addr = HttpsUpstreamAddr::parse("https://[2606:4700::1111]:8443/dns-query").unwrap();
+        assert_eq!(addr.addr, "[2606:4700::1111]:8443".parse().unwrap());
+
+        // Hostnames can't be resolved without a bootstrap IP
+        assert!(HttpsUpstreamAddr::parse("https://dns.google/dns-query").is_err());
+        assert!(HttpsUpstreamAddr::parse("http://1.1.1.1/dns-query").is_err());
+        assert!(HttpsUpstreamAddr::parse("https://dns.google/dns-query#not-an-ip").is_err());
+    }
+}
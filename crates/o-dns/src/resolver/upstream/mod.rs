@@ -0,0 +1,514 @@
+mod https;
+mod quic;
+#[cfg(test)]
+mod test_utils;
+mod tls;
+
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use clap::ValueEnum;
+use data_encoding::BASE64;
+use https::HttpsClient;
+pub use https::{DohMethod, HttpsUpstreamAddr};
+use o_dns_lib::{DnsClient, DnsPacket, Question};
+use quic::QuicClient;
+use rand::seq::SliceRandom as _;
+use rustls::pki_types::ServerName;
+pub use tls::TlsUpstreamAddr;
+use tls::{TlsClient, DEFAULT_TLS_PORT};
+use tokio::time::Instant;
+
+use crate::util::get_query_dns_packet;
+
+/// Port of the plain DNS upstreams without an explicit port
+const DEFAULT_PORT: u16 = 53;
+
+/// Upstream is disabled after this many consecutive failed queries
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+/// For how long a failing upstream is skipped before being tried again
+const UPSTREAM_DISABLE_DURATION: Duration = Duration::from_secs(30);
+
+/// Order in which the upstream resolvers are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UpstreamStrategy {
+    /// Always start with the first upstream and fail over to the next ones in order
+    Strict,
+    RoundRobin,
+    Random,
+    /// Prefer the upstream with the lowest smoothed response time
+    LowestLatency,
+}
+
+/// Timeouts, the retry policy and the selection strategy used for queries to the upstream resolvers
+#[derive(Debug, Clone)]
+pub struct UpstreamConfig {
+    pub strategy: UpstreamStrategy,
+    /// Timeout of a single attempt, including the TCP fallback or establishing a connection
+    pub timeout: Duration,
+    /// Number of additional rounds over all upstreams after the first one has failed
+    pub retries: u8,
+    /// Delay before the first retry, doubled after every subsequent round
+    pub backoff: Duration,
+    /// Overall time limit for all attempts combined
+    pub deadline: Duration,
+    pub doh_method: DohMethod,
+}
+
+/// Upstream resolver as given on the command line
+#[derive(Debug, Clone)]
+pub enum UpstreamAddr {
+    /// `ADDR[:PORT]`, plain DNS over UDP with the TCP fallback
+    Plain { ip: IpAddr, port: Option<u16> },
+    /// `tls://ADDR[:PORT][#NAME][?spki=PIN]`, where `NAME` defaults to `ADDR`
+    /// and `PIN` is base64-encoded SHA-256 of the certificate's SubjectPublicKeyInfo
+    Tls(TlsUpstreamAddr),
+    /// `quic://ADDR[:PORT][#NAME][?spki=PIN]`, same as for DoT
+    Quic(TlsUpstreamAddr),
+    /// `https://HOST[:PORT][/PATH][#BOOTSTRAP_IP]`, where `BOOTSTRAP_IP` is required if `HOST` is a hostname
+    Https(HttpsUpstreamAddr),
+}
+
+impl UpstreamAddr {
+    /// Sets the port of the plain DNS upstream if it wasn't specified explicitly
+    pub fn with_default_port(self, default_port: u16) -> Self {
+        match self {
+            UpstreamAddr::Plain { ip, port } => UpstreamAddr::Plain {
+                ip,
+                port: port.or(Some(default_port)),
+            },
+            addr => addr,
+        }
+    }
+}
+
+impl FromStr for UpstreamAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            return HttpsUpstreamAddr::parse(s).map(UpstreamAddr::Https);
+        }
+
+        let (tls_addr, is_quic) = match (s.strip_prefix("tls://"), s.strip_prefix("quic://")) {
+            (Some(tls_addr), _) => (tls_addr, false),
+            (_, Some(quic_addr)) => (quic_addr, true),
+            _ => {
+                let (ip, port) = parse_ip_with_port(s)?;
+                return Ok(UpstreamAddr::Plain { ip, port });
+            }
+        };
+
+        let (tls_addr, spki_pin) = match tls_addr.split_once("?spki=") {
+            Some((tls_addr, pin)) => {
+                let pin = BASE64
+                    .decode(pin.as_bytes())
+                    .ok()
+                    .and_then(|pin| <[u8; 32]>::try_from(pin).ok())
+                    .with_context(|| format!("invalid SPKI pin '{}', expected base64-encoded SHA-256", pin))?;
+                (tls_addr, Some(pin))
+            }
+            None => (tls_addr, None),
+        };
+        let (tls_addr, server_name) = match tls_addr.split_once('#') {
+            Some((tls_addr, server_name)) => (tls_addr, Some(server_name)),
+            None => (tls_addr, None),
+        };
+
+        let (ip, port) = parse_ip_with_port(tls_addr)?;
+        let server_name = match server_name {
+            Some(server_name) => ServerName::try_from(server_name.to_string())
+                .with_context(|| format!("invalid TLS server name '{}'", server_name))?,
+            None => ServerName::from(ip),
+        };
+
+        // DoQ uses the same port as DoT, but over UDP (RFC9250, section 4.1.1)
+        let tls_addr = TlsUpstreamAddr {
+            addr: SocketAddr::new(ip, port.unwrap_or(DEFAULT_TLS_PORT)),
+            server_name,
+            spki_pin,
+        };
+
+        if is_quic {
+            Ok(UpstreamAddr::Quic(tls_addr))
+        } else {
+            Ok(UpstreamAddr::Tls(tls_addr))
+        }
+    }
+}
+
+/// Parses `ADDR`, `ADDR:PORT`, `[ADDR]` or `[ADDR]:PORT`
+fn parse_ip_with_port(s: &str) -> anyhow::Result<(IpAddr, Option<u16>)> {
+    let unbracketed = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(s);
+    if let Ok(ip) = unbracketed.parse() {
+        return Ok((ip, None));
+    }
+
+    let addr: SocketAddr = s
+        .parse()
+        .with_context(|| format!("invalid upstream resolver address '{}'", s))?;
+    Ok((addr.ip(), Some(addr.port())))
+}
+
+/// Protocol used to forward queries to an upstream resolver
+enum Transport {
+    /// UDP with the TCP fallback
+    Plain(DnsClient),
+    Tls(TlsClient),
+    Quic(QuicClient),
+    Https(HttpsClient),
+}
+
+pub struct Upstream {
+    transport: Transport,
+    consecutive_failures: AtomicU32,
+    disabled_until: Mutex<Option<Instant>>,
+    /// Smoothed response time in microseconds, 0 until the first successful query
+    latency_us: AtomicU64,
+}
+
+impl Upstream {
+    fn new(addr: UpstreamAddr) -> anyhow::Result<Self> {
+        let transport = match addr {
+            UpstreamAddr::Plain { ip, port } => {
+                Transport::Plain(DnsClient::new(SocketAddr::new(ip, port.unwrap_or(DEFAULT_PORT))))
+            }
+            UpstreamAddr::Tls(addr) => Transport::Tls(TlsClient::new(addr)?),
+            UpstreamAddr::Quic(addr) => Transport::Quic(QuicClient::new(addr)?),
+            UpstreamAddr::Https(addr) => Transport::Https(HttpsClient::new(addr)?),
+        };
+
+        Ok(Upstream {
+            transport,
+            consecutive_failures: AtomicU32::new(0),
+            disabled_until: Mutex::new(None),
+            latency_us: AtomicU64::new(0),
+        })
+    }
+
+    async fn query(&self, query: &DnsPacket<'_>, config: &UpstreamConfig) -> anyhow::Result<DnsPacket<'static>> {
+        match &self.transport {
+            Transport::Plain(client) => client.query(query).await,
+            Transport::Tls(client) => client.query(query).await,
+            Transport::Quic(client) => client.query(query).await,
+            Transport::Https(client) => client.query(query, config.doh_method).await,
+        }
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        self.disabled_until
+            .lock()
+            .unwrap()
+            .is_none_or(|disabled_until| now >= disabled_until)
+    }
+
+    fn record_success(&self, latency: Duration) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.disabled_until.lock().unwrap() = None;
+
+        // Exponentially weighted moving average with the 1/8 weight, like the SRTT in TCP
+        let latency = latency.as_micros().clamp(1, u64::MAX as u128) as u64;
+        let _ = self
+            .latency_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |smoothed| match smoothed {
+                0 => Some(latency),
+                smoothed => Some(smoothed - smoothed / 8 + latency / 8),
+            });
+    }
+
+    fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        // The counter isn't reset, so that a single failure after the upstream is re-enabled disables it again
+        if failures >= MAX_CONSECUTIVE_FAILURES {
+            tracing::warn!(
+                resolver = %self,
+                failures,
+                "Disabling the upstream resolver for {:?}",
+                UPSTREAM_DISABLE_DURATION
+            );
+            *self.disabled_until.lock().unwrap() = Some(Instant::now() + UPSTREAM_DISABLE_DURATION);
+        }
+    }
+}
+
+impl Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.transport {
+            Transport::Plain(client) => write!(f, "{}", client.get_server()),
+            Transport::Tls(client) => write!(f, "{}", client.get_upstream()),
+            Transport::Quic(client) => {
+                let upstream = client.get_upstream();
+                write!(f, "quic://{}#{}", upstream.addr, upstream.server_name.to_str())
+            }
+            Transport::Https(client) => write!(f, "{}", client.get_upstream()),
+        }
+    }
+}
+
+/// Upstream resolvers along with their health and latency stats
+pub struct Upstreams {
+    upstreams: Vec<Upstream>,
+    /// Index of the first upstream for the round-robin strategy
+    next: AtomicUsize,
+}
+
+impl Upstreams {
+    pub fn new(addrs: impl IntoIterator<Item = UpstreamAddr>) -> anyhow::Result<Self> {
+        let upstreams = addrs
+            .into_iter()
+            .map(Upstream::new)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if upstreams.is_empty() {
+            anyhow::bail!("at least one upstream resolver is required");
+        }
+
+        Ok(Upstreams {
+            upstreams,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the available upstreams in the order they should be tried
+    fn get_candidates(&self, strategy: UpstreamStrategy) -> Vec<&Upstream> {
+        let now = Instant::now();
+        let mut candidates: Vec<_> = self
+            .upstreams
+            .iter()
+            .filter(|upstream| upstream.is_available(now))
+            .collect();
+        // Try all of them anyway instead of failing right away
+        if candidates.is_empty() {
+            candidates = self.upstreams.iter().collect();
+        }
+
+        match strategy {
+            UpstreamStrategy::Strict => {}
+            UpstreamStrategy::RoundRobin => {
+                let offset = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                candidates.rotate_left(offset);
+            }
+            UpstreamStrategy::Random => candidates.shuffle(&mut rand::thread_rng()),
+            // Stable sort keeps the configured order for the upstreams without any stats
+            UpstreamStrategy::LowestLatency => {
+                candidates.sort_by_key(|upstream| upstream.latency_us.load(Ordering::Relaxed))
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Forwards the question to the upstream resolvers, failing over to the next one and retrying until the deadline.
+/// Returns the response along with the upstream that has answered.
+///
+/// If the last attempt has timed out, the returned error can be downcast to [`tokio::time::error::Elapsed`].
+pub(super) async fn resolve_with_upstream<'u>(
+    question: &Question<'_>,
+    id: u16,
+    upstreams: &'u Upstreams,
+    config: &UpstreamConfig,
+    enable_dnssec: bool,
+    checking_disabled: bool,
+) -> anyhow::Result<(DnsPacket<'static>, &'u Upstream)> {
+    let mut packet = get_query_dns_packet(Some(id), enable_dnssec);
+    // CD bit
+    packet.header.z[2] = checking_disabled;
+    packet.add_question(question.clone());
+
+    // TODO: verify whether the upstream server supports EDNS by maintaining a cache.
+    //   if it's the first query to this server -> assume no EDNS by default but add OPT RR
+    let deadline = Instant::now() + config.deadline;
+    let mut backoff = config.backoff;
+    let mut attempts = 0;
+    let mut round = 0;
+    let error = 'rounds: loop {
+        let mut last_error = None;
+        for upstream in upstreams.get_candidates(config.strategy) {
+            let start = Instant::now();
+            let attempt_deadline = deadline.min(start + config.timeout);
+            attempts += 1;
+            let error = match tokio::time::timeout_at(attempt_deadline, upstream.query(&packet, config)).await {
+                Ok(Ok(response)) => {
+                    upstream.record_success(start.elapsed());
+                    return Ok((response, upstream));
+                }
+                Ok(Err(e)) => e,
+                Err(elapsed) => anyhow::Error::new(elapsed),
+            };
+            upstream.record_failure();
+            tracing::debug!(resolver = %upstream, attempts, "Upstream query failed: {:#}", error);
+
+            if Instant::now() >= deadline {
+                break 'rounds error;
+            }
+            last_error = Some(error);
+        }
+        let error = last_error.context("bug: no upstream resolvers?")?;
+
+        round += 1;
+        if round > config.retries || Instant::now() + backoff >= deadline {
+            break error;
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = backoff.saturating_mul(2);
+    };
+
+    Err(error).with_context(|| format!("upstream resolution failed after {} attempt(s)", attempts))
+}
+
+#[cfg(test)]
+mod tests {
+    use o_dns_lib::QueryType;
+    use tokio::net::UdpSocket;
+    use tokio::time::error::Elapsed;
+
+    use super::*;
+
+    fn get_config(retries: u8, deadline: Duration) -> UpstreamConfig {
+        UpstreamConfig {
+            strategy: UpstreamStrategy::Strict,
+            timeout: Duration::from_secs(1),
+            retries,
+            backoff: Duration::from_millis(100),
+            deadline,
+            doh_method: DohMethod::Post,
+        }
+    }
+
+    /// Returns the error along with how long the resolution took
+    async fn resolve_with_silent_upstream(config: &UpstreamConfig) -> (anyhow::Error, Duration) {
+        // Socket is kept open, so that the queries aren't rejected, but it never replies
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstreams = Upstreams::new([socket.local_addr().unwrap().to_string().parse().unwrap()]).unwrap();
+        let question = Question::new("example.com".parse().unwrap(), QueryType::A, None);
+
+        let start = Instant::now();
+        let Err(error) = resolve_with_upstream(&question, 1, &upstreams, config, false, false).await else {
+            panic!("should have timed out");
+        };
+        (error, start.elapsed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_with_backoff() {
+        let (error, elapsed) = resolve_with_silent_upstream(&get_config(2, Duration::from_secs(10))).await;
+        assert_eq!(error.to_string(), "upstream resolution failed after 3 attempt(s)");
+        assert!(error.downcast_ref::<Elapsed>().is_some());
+        // Three 1s attempts along with the 100ms and 200ms backoffs
+        assert_eq!(elapsed, Duration::from_millis(3300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_at_deadline() {
+        let (error, elapsed) = resolve_with_silent_upstream(&get_config(5, Duration::from_millis(2500))).await;
+        // Last attempt is cut short by the deadline
+        assert_eq!(error.to_string(), "upstream resolution failed after 3 attempt(s)");
+        assert!(error.downcast_ref::<Elapsed>().is_some());
+        assert_eq!(elapsed, Duration::from_millis(2500));
+
+        // Backoff that would end past the deadline isn't waited for
+        let (error, elapsed) = resolve_with_silent_upstream(&get_config(5, Duration::from_millis(1050))).await;
+        assert_eq!(error.to_string(), "upstream resolution failed after 1 attempt(s)");
+        assert_eq!(elapsed, Duration::from_secs(1));
+    }
+
+    fn get_upstreams() -> Upstreams {
+        Upstreams::new(["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"].map(|addr| addr.parse().unwrap())).unwrap()
+    }
+
+    fn get_candidates(upstreams: &Upstreams, strategy: UpstreamStrategy) -> Vec<String> {
+        upstreams
+            .get_candidates(strategy)
+            .iter()
+            .map(|upstream| upstream.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn orders_candidates_by_strategy() {
+        let upstreams = get_upstreams();
+        let configured = ["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"];
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict), configured);
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict), configured);
+
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::RoundRobin), configured);
+        assert_eq!(
+            get_candidates(&upstreams, UpstreamStrategy::RoundRobin),
+            ["127.0.0.1:2", "127.0.0.1:3", "127.0.0.1:1"]
+        );
+        assert_eq!(
+            get_candidates(&upstreams, UpstreamStrategy::RoundRobin),
+            ["127.0.0.1:3", "127.0.0.1:1", "127.0.0.1:2"]
+        );
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::RoundRobin), configured);
+
+        let mut random = get_candidates(&upstreams, UpstreamStrategy::Random);
+        random.sort();
+        assert_eq!(random, configured);
+
+        // Upstream without any stats yet is tried first
+        upstreams.upstreams[1].record_success(Duration::from_millis(5));
+        upstreams.upstreams[2].record_success(Duration::from_millis(1));
+        assert_eq!(
+            get_candidates(&upstreams, UpstreamStrategy::LowestLatency),
+            ["127.0.0.1:1", "127.0.0.1:3", "127.0.0.1:2"]
+        );
+        upstreams.upstreams[0].record_success(Duration::from_millis(3));
+        assert_eq!(
+            get_candidates(&upstreams, UpstreamStrategy::LowestLatency),
+            ["127.0.0.1:3", "127.0.0.1:1", "127.0.0.1:2"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disables_failing_upstreams() {
+        let upstreams = get_upstreams();
+        let first = &upstreams.upstreams[0];
+        for _ in 0..MAX_CONSECUTIVE_FAILURES - 1 {
+            first.record_failure();
+        }
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict).len(), 3);
+
+        first.record_failure();
+        assert_eq!(
+            get_candidates(&upstreams, UpstreamStrategy::Strict),
+            ["127.0.0.1:2", "127.0.0.1:3"]
+        );
+
+        // Re-enabled after a while, but disabled again by the next failure
+        tokio::time::advance(UPSTREAM_DISABLE_DURATION).await;
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict).len(), 3);
+        first.record_failure();
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict).len(), 2);
+
+        // Successful query resets the failures right away
+        first.record_success(Duration::from_millis(1));
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict).len(), 3);
+        first.record_failure();
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict).len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn falls_back_to_all_upstreams_when_all_are_disabled() {
+        let upstreams = get_upstreams();
+        upstreams.upstreams.iter().for_each(|upstream| {
+            for _ in 0..MAX_CONSECUTIVE_FAILURES {
+                upstream.record_failure();
+            }
+        });
+        assert_eq!(
+            get_candidates(&upstreams, UpstreamStrategy::Strict),
+            ["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"]
+        );
+
+        // Only the available ones are tried once any of them is re-enabled
+        upstreams.upstreams[1].record_success(Duration::from_millis(1));
+        assert_eq!(get_candidates(&upstreams, UpstreamStrategy::Strict), ["127.0.0.1:2"]);
+    }
+}
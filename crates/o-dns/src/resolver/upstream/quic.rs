@@ -0,0 +1,264 @@
+//! DNS-over-QUIC (RFC9250) upstream, where each query uses its own stream of a single persistent connection
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use o_dns_lib::{ByteBuf, DnsPacket, EncodeToBuf as _, FromBuf as _};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Connection, Endpoint, ReadError, ReadToEndError, WriteError};
+use rustls::RootCertStore;
+
+use super::tls::{get_client_config, get_webpki_roots};
+use super::TlsUpstreamAddr;
+use crate::DOQ_ALPN;
+
+pub struct QuicClient {
+    upstream: TlsUpstreamAddr,
+    endpoint: Endpoint,
+    config: ClientConfig,
+    connection: tokio::sync::Mutex<Option<Connection>>,
+}
+
+impl QuicClient {
+    pub fn new(upstream: TlsUpstreamAddr) -> anyhow::Result<Self> {
+        QuicClient::new_with_roots(upstream, get_webpki_roots())
+    }
+
+    fn new_with_roots(upstream: TlsUpstreamAddr, roots: RootCertStore) -> anyhow::Result<Self> {
+        let mut tls_config = get_client_config(roots, upstream.spki_pin)?;
+        tls_config.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        // Queries are sent right away when resuming a session with the same upstream
+        tls_config.enable_early_data = true;
+        let config = ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(tls_config).context("QUIC: failed to create a client config")?,
+        ));
+
+        let bind_addr = match upstream.addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let endpoint = Endpoint::client(bind_addr).context("QUIC: failed to create an endpoint")?;
+
+        Ok(QuicClient {
+            upstream,
+            endpoint,
+            config,
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    pub fn get_upstream(&self) -> &TlsUpstreamAddr {
+        &self.upstream
+    }
+
+    /// Sends the query on a new stream of the existing connection, or establishes a new one if there is none
+    pub async fn query(&self, query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        let mut buf = ByteBuf::new_empty(None);
+        query
+            .encode_to_buf(&mut buf, None)
+            .context("error while encoding the query")?;
+        // ID is always 0 (RFC9250, section 4.2.1)
+        buf.set_u16(0, 0)?;
+
+        let connection = self.get_connection().await?;
+        let response = match send_query(&connection, &buf).await {
+            // Queries sent as 0-RTT data are discarded by the servers that reject it, but the connection is still usable
+            Err(e) if is_zero_rtt_rejected(&e) => {
+                tracing::debug!(resolver = %self.upstream.addr, "QUIC: 0-RTT was rejected, resending the query");
+                send_query(&connection, &buf).await?
+            }
+            result => result?,
+        };
+
+        let mut response =
+            DnsPacket::from_buf(&mut ByteBuf::new_from_vec(response)).context("error while decoding the response")?;
+        if !response.questions.is_empty() && response.questions != query.questions {
+            anyhow::bail!("QUIC: response doesn't match the query");
+        }
+        response.header.id = query.header.id;
+
+        Ok(response)
+    }
+
+    async fn get_connection(&self) -> anyhow::Result<Connection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection
+            .as_ref()
+            .filter(|connection| connection.close_reason().is_none())
+        {
+            return Ok(connection.clone());
+        }
+
+        let connecting = self
+            .endpoint
+            .connect_with(
+                self.config.clone(),
+                self.upstream.addr,
+                &self.upstream.server_name.to_str(),
+            )
+            .context("QUIC: error while connecting to the upstream resolver")?;
+        let new_connection = match connecting.into_0rtt() {
+            Ok((new_connection, _)) => {
+                tracing::debug!(resolver = %self.upstream.addr, "QUIC: resumed a connection with 0-RTT");
+                new_connection
+            }
+            Err(connecting) => {
+                let new_connection = connecting
+                    .await
+                    .context("QUIC: handshake with the upstream resolver failed")?;
+                tracing::debug!(resolver = %self.upstream.addr, "QUIC: established a new connection");
+                new_connection
+            }
+        };
+        *connection = Some(new_connection.clone());
+
+        Ok(new_connection)
+    }
+}
+
+/// Sends the length-prefixed query on a new stream and returns the response without the prefix
+async fn send_query(connection: &Connection, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (mut send, mut recv) = connection
+        .open_bi()
+        .await
+        .context("QUIC: error while opening a stream")?;
+
+    let length = u16::try_from(query.len()).context("QUIC: query is too long")?;
+    let mut framed = Vec::with_capacity(2 + query.len());
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(query);
+    send.write_all(&framed)
+        .await
+        .context("QUIC: error while sending the query")?;
+    // The server only responds once the stream is finished
+    send.finish().context("QUIC: error while finishing the stream")?;
+
+    let mut response = recv
+        .read_to_end(2 + u16::MAX as usize)
+        .await
+        .context("QUIC: error while reading the response")?;
+    if response.len() < 2 || u16::from_be_bytes([response[0], response[1]]) as usize != response.len() - 2 {
+        anyhow::bail!("QUIC: response length doesn't match the length prefix");
+    }
+    response.drain(..2);
+
+    Ok(response)
+}
+
+fn is_zero_rtt_rejected(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref(), Some(WriteError::ZeroRttRejected))
+        || matches!(
+            error.downcast_ref(),
+            Some(ReadToEndError::Read(ReadError::ZeroRttRejected))
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rustls::pki_types::ServerName;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    use super::*;
+    use crate::resolver::upstream::test_utils::{get_query, get_test_certificate, TestCertificate};
+    use crate::{DnsServer, DohMethod, UpstreamConfig, UpstreamStrategy};
+
+    /// Starts the DoQ listener of the server itself with a certificate for `dns.test`.
+    /// Queries are non-recursive, so it answers them without contacting its upstream resolver
+    async fn spawn_server() -> (SocketAddr, RootCertStore) {
+        let TestCertificate {
+            server_config, roots, ..
+        } = get_test_certificate();
+
+        let upstream_config = UpstreamConfig {
+            strategy: UpstreamStrategy::Strict,
+            timeout: Duration::from_secs(1),
+            retries: 0,
+            backoff: Duration::ZERO,
+            deadline: Duration::from_secs(1),
+            doh_method: DohMethod::Post,
+        };
+        let (log_tx, _log_rx) = unbounded_channel();
+        let (_command_tx, command_rx) = channel(1);
+        let mut server = DnsServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            vec!["127.0.0.1:53".parse().unwrap()],
+            upstream_config,
+            Vec::new(),
+            log_tx,
+            command_rx,
+        )
+        .await
+        .unwrap();
+
+        // Find a free UDP port for the QUIC listener
+        let addr = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        server
+            .add_quic_listener(addr, server_config, Duration::from_secs(5))
+            .await
+            .unwrap();
+        tokio::spawn(server.block_until_completion());
+
+        (addr, roots)
+    }
+
+    #[tokio::test]
+    async fn resolves_queries_over_quic() {
+        let (addr, roots) = spawn_server().await;
+        let upstream = TlsUpstreamAddr {
+            addr,
+            server_name: ServerName::try_from("dns.test").unwrap(),
+            spki_pin: None,
+        };
+        let client = QuicClient::new_with_roots(upstream, roots).unwrap();
+
+        let first_query = get_query("first.example");
+        let second_query = get_query("second.example");
+        let (first_response, second_response) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(client.query(&first_query), client.query(&second_query))
+        })
+        .await
+        .expect("queries shouldn't have timed out");
+
+        for (query, response) in [(first_query, first_response), (second_query, second_response)] {
+            let response = response.expect("shouldn't have failed");
+            assert!(response.header.is_response);
+            assert_eq!(response.header.id, query.header.id);
+            assert_eq!(response.questions, query.questions);
+        }
+
+        // Subsequent queries reuse the connection
+        let connection = client.connection.lock().await.clone().unwrap();
+        let query = get_query("third.example");
+        client.query(&query).await.expect("shouldn't have failed");
+        let reused_connection = client.connection.lock().await.clone().unwrap();
+        assert_eq!(reused_connection.stable_id(), connection.stable_id());
+
+        // Once the connection is closed, the next one resumes the session with 0-RTT
+        connection.close(0u32.into(), b"");
+        let connecting = client
+            .endpoint
+            .connect_with(client.config.clone(), addr, "dns.test")
+            .unwrap();
+        let (_connection, accepted) = connecting
+            .into_0rtt()
+            .unwrap_or_else(|_| panic!("session should've been resumed"));
+        assert!(accepted.await, "server should've accepted 0-RTT");
+
+        let query = get_query("fourth.example");
+        let response = client.query(&query).await.expect("shouldn't have failed");
+        assert_eq!(response.questions, query.questions);
+    }
+
+    #[test]
+    fn parse_quic_upstream_addr() {
+        let addr: crate::UpstreamAddr = "quic://94.140.14.140#dns.adguard-dns.com".parse().unwrap();
+        let crate::UpstreamAddr::Quic(addr) = addr else {
+            panic!("expected a DoQ upstream");
+        };
+        assert_eq!(addr.addr, "94.140.14.140:853".parse().unwrap());
+        assert_eq!(addr.server_name.to_str(), "dns.adguard-dns.com");
+    }
+}
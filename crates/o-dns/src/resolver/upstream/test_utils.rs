@@ -0,0 +1,60 @@
+//! Fixtures shared by the tests of the upstream transports
+
+use std::sync::Arc;
+
+use o_dns_lib::{DnsPacket, QueryType, Question};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use rustls::pki_types::PrivateKeyDer;
+use rustls::{RootCertStore, ServerConfig};
+use sha2::{Digest as _, Sha256};
+
+pub struct TestCertificate {
+    /// Config with the certificate for `dns.test`, where ALPN is left for the caller to set
+    pub server_config: ServerConfig,
+    /// Only contains the CA that has issued the certificate
+    pub roots: RootCertStore,
+    /// SHA-256 of the certificate's SubjectPublicKeyInfo
+    pub spki_pin: [u8; 32],
+}
+
+/// Issues a certificate for `dns.test` by a new CA
+pub fn get_test_certificate() -> TestCertificate {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+    let server_key = KeyPair::generate().unwrap();
+    let server_cert = CertificateParams::new(vec!["dns.test".to_string()])
+        .unwrap()
+        .signed_by(&server_key, &ca_cert, &ca_key)
+        .unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(ca_cert.der().clone()).unwrap();
+    let spki_pin = Sha256::digest(server_key.public_key_der()).into();
+
+    let server_config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![server_cert.der().clone()],
+            PrivateKeyDer::Pkcs8(server_key.serialize_der().into()),
+        )
+        .unwrap();
+
+    TestCertificate {
+        server_config,
+        roots,
+        spki_pin,
+    }
+}
+
+/// Non-recursive query with the fixed ID, as different clients may use the same ID
+pub fn get_query(qname: &str) -> DnsPacket<'static> {
+    let mut query = DnsPacket::new();
+    query.header.id = 0x1234;
+    query.add_question(Question::new(qname.parse().unwrap(), QueryType::A, None));
+    query
+}
@@ -0,0 +1,465 @@
+//! DNS-over-TLS (RFC7858) upstream, where queries are pipelined over a single persistent connection
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context as _;
+use o_dns_lib::{read_tcp_message, write_tcp_message, ByteBuf, DnsPacket, EncodeToBuf as _, FromBuf as _};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest as _, Sha256};
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+/// Default DoT port (RFC7858)
+pub const DEFAULT_TLS_PORT: u16 = 853;
+
+/// Senders for the in-flight queries by their IDs, `None` once the connection is closed
+type PendingQueries = Arc<Mutex<Option<HashMap<u16, oneshot::Sender<DnsPacket<'static>>>>>>;
+
+/// Address and the authentication details of a DoT upstream
+#[derive(Debug, Clone)]
+pub struct TlsUpstreamAddr {
+    pub addr: SocketAddr,
+    /// Used for SNI and to verify the certificate
+    pub server_name: ServerName<'static>,
+    /// SHA-256 of the certificate's SubjectPublicKeyInfo, checked in addition to the certificate chain (RFC7858)
+    pub spki_pin: Option<[u8; 32]>,
+}
+
+impl Display for TlsUpstreamAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tls://{}#{}", self.addr, self.server_name.to_str())
+    }
+}
+
+pub struct TlsClient {
+    upstream: TlsUpstreamAddr,
+    connector: TlsConnector,
+    connection: tokio::sync::Mutex<Option<Arc<TlsConnection>>>,
+}
+
+/// Mozilla's root certificates
+pub(super) fn get_webpki_roots() -> RootCertStore {
+    RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    }
+}
+
+/// Creates a config that verifies the certificate chain and the server name, along with the SPKI pin if present
+pub(super) fn get_client_config(roots: RootCertStore, spki_pin: Option<[u8; 32]>) -> anyhow::Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .context("TLS: failed to create a certificate verifier")?;
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("TLS: failed to create a client config")?;
+    let config = match spki_pin {
+        Some(pin) => config
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SpkiPinVerifier { inner: verifier, pin })),
+        None => config.with_webpki_verifier(verifier),
+    }
+    .with_no_client_auth();
+
+    Ok(config)
+}
+
+impl TlsClient {
+    pub fn new(upstream: TlsUpstreamAddr) -> anyhow::Result<Self> {
+        TlsClient::new_with_roots(upstream, get_webpki_roots())
+    }
+
+    fn new_with_roots(upstream: TlsUpstreamAddr, roots: RootCertStore) -> anyhow::Result<Self> {
+        let config = get_client_config(roots, upstream.spki_pin)?;
+
+        Ok(TlsClient {
+            upstream,
+            connector: TlsConnector::from(Arc::new(config)),
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    pub fn get_upstream(&self) -> &TlsUpstreamAddr {
+        &self.upstream
+    }
+
+    /// Sends the query over the existing connection, or establishes a new one if there is none
+    pub async fn query(&self, query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        self.get_connection().await?.query(query).await
+    }
+
+    async fn get_connection(&self) -> anyhow::Result<Arc<TlsConnection>> {
+        // Concurrent queries wait for the same handshake instead of opening multiple connections
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection.as_ref().filter(|connection| !connection.is_closed()) {
+            return Ok(connection.clone());
+        }
+
+        let stream = TcpStream::connect(self.upstream.addr)
+            .await
+            .context("TLS: error while connecting to the upstream resolver")?;
+        let stream = self
+            .connector
+            .connect(self.upstream.server_name.clone(), stream)
+            .await
+            .context("TLS: handshake with the upstream resolver failed")?;
+        tracing::debug!(resolver = %self.upstream, "TLS: established a new connection");
+
+        let new_connection = Arc::new(TlsConnection::new(stream));
+        *connection = Some(new_connection.clone());
+
+        Ok(new_connection)
+    }
+}
+
+struct TlsConnection {
+    writer: tokio::sync::Mutex<WriteHalf<TlsStream<TcpStream>>>,
+    pending: PendingQueries,
+    /// Queries get their own IDs, as different clients may use the same ID at the same time
+    next_id: AtomicU16,
+    reader: JoinHandle<()>,
+}
+
+impl TlsConnection {
+    fn new(stream: TlsStream<TcpStream>) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        let pending: PendingQueries = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        TlsConnection {
+            writer: tokio::sync::Mutex::new(writer),
+            pending: pending.clone(),
+            next_id: AtomicU16::new(rand::random()),
+            reader: tokio::spawn(read_responses(reader, pending)),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().is_none()
+    }
+
+    fn close(&self) {
+        // Dropping the senders notifies all waiting queries
+        self.pending.lock().unwrap().take();
+    }
+
+    async fn query(&self, query: &DnsPacket<'_>) -> anyhow::Result<DnsPacket<'static>> {
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            let pending = pending.as_mut().context("TLS: connection is closed")?;
+            if pending.len() > u16::MAX as usize {
+                anyhow::bail!("TLS: too many queries in flight");
+            }
+            let id = std::iter::repeat_with(|| self.next_id.fetch_add(1, Ordering::Relaxed))
+                .find(|id| !pending.contains_key(id))
+                .unwrap();
+            pending.insert(id, tx);
+            id
+        };
+        // Removes the sender if the query times out
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            id,
+        };
+
+        let mut buf = ByteBuf::new_empty(None);
+        query
+            .encode_to_buf(&mut buf, None)
+            .context("error while encoding the query")?;
+        buf.set_u16(0, id)?;
+
+        let mut writer = self.writer.lock().await;
+        // Query that held the lock before could have left a partial message behind
+        if self.is_closed() {
+            anyhow::bail!("TLS: connection is closed");
+        }
+        // Closes the connection if the write fails or the query times out in the middle of it
+        let mut write_guard = WriteGuard {
+            connection: self,
+            is_finished: false,
+        };
+        let write_result = write_tcp_message(&mut *writer, &buf).await;
+        write_guard.is_finished = write_result.is_ok();
+        drop(write_guard);
+        drop(writer);
+        write_result?;
+
+        let mut response = rx
+            .await
+            .context("TLS: connection was closed before receiving the response")?;
+        if !response.questions.is_empty() && response.questions != query.questions {
+            anyhow::bail!("TLS: response doesn't match the query");
+        }
+        response.header.id = query.header.id;
+
+        Ok(response)
+    }
+}
+
+impl Drop for TlsConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+struct PendingGuard<'a> {
+    pending: &'a Mutex<Option<HashMap<u16, oneshot::Sender<DnsPacket<'static>>>>>,
+    id: u16,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// Closes the connection unless the message was written completely, as the stream can't be used after a partial one
+struct WriteGuard<'a> {
+    connection: &'a TlsConnection,
+    is_finished: bool,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if !self.is_finished {
+            self.connection.close();
+        }
+    }
+}
+
+/// Dispatches responses to the queries by their IDs until the connection is closed
+async fn read_responses(mut reader: ReadHalf<TlsStream<TcpStream>>, pending: PendingQueries) {
+    let result: anyhow::Result<()> = async {
+        loop {
+            let mut buf = ByteBuf::new_empty(None);
+            let length = read_tcp_message(&mut reader, &mut buf).await?;
+            buf.get_inner_mut().truncate(length);
+
+            let id = buf.peek_u16(0)?;
+            let sender = pending.lock().unwrap().as_mut().and_then(|pending| pending.remove(&id));
+            // Dropping the sender of a malformed response fails the matching query right away
+            if let (Some(sender), Ok(response)) = (sender, DnsPacket::from_buf(&mut buf)) {
+                let _ = sender.send(response);
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = result {
+        tracing::debug!("TLS: upstream connection was closed: {:#}", e);
+    }
+    pending.lock().unwrap().take();
+}
+
+/// Verifies the certificate chain as usual and then checks that the certificate's key matches the pin
+#[derive(Debug)]
+struct SpkiPinVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pin: [u8; 32],
+}
+
+impl ServerCertVerifier for SpkiPinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        let certificate = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let spki_hash = Sha256::digest(certificate.subject_public_key_info());
+        if spki_hash.as_slice() != self.pin {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::resolver::upstream::test_utils::{get_query, get_test_certificate, TestCertificate};
+
+    struct TestServer {
+        addr: SocketAddr,
+        roots: RootCertStore,
+        spki_pin: [u8; 32],
+    }
+
+    /// Starts a DoT stand-in with a certificate for `dns.test`, which answers `batch_size` queries at once
+    /// in the reverse order
+    async fn spawn_server(batch_size: usize) -> TestServer {
+        let TestCertificate {
+            server_config,
+            roots,
+            spki_pin,
+        } = get_test_certificate();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+                tokio::spawn(async move {
+                    loop {
+                        let mut batch = Vec::new();
+                        for _ in 0..batch_size {
+                            let mut buf = ByteBuf::new_empty(None);
+                            let Ok(length) = read_tcp_message(&mut stream, &mut buf).await else {
+                                return;
+                            };
+                            buf.get_inner_mut().truncate(length);
+                            batch.push(DnsPacket::from_buf(&mut buf).unwrap());
+                        }
+
+                        for mut response in batch.into_iter().rev() {
+                            response.header.is_response = true;
+                            let mut buf = ByteBuf::new_empty(None);
+                            response.encode_to_buf(&mut buf, None).unwrap();
+                            write_tcp_message(&mut stream, &buf).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        TestServer { addr, roots, spki_pin }
+    }
+
+    fn get_client(server: &TestServer, server_name: &str, spki_pin: Option<[u8; 32]>) -> TlsClient {
+        let upstream = TlsUpstreamAddr {
+            addr: server.addr,
+            server_name: ServerName::try_from(server_name.to_string()).unwrap(),
+            spki_pin,
+        };
+        TlsClient::new_with_roots(upstream, server.roots.clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pipelines_queries_over_single_connection() {
+        let server = spawn_server(2).await;
+        let client = get_client(&server, "dns.test", Some(server.spki_pin));
+
+        let first_query = get_query("first.example");
+        let second_query = get_query("second.example");
+        let (first_response, second_response) = tokio::time::timeout(Duration::from_secs(5), async {
+            // The server only responds after receiving both queries
+            tokio::join!(client.query(&first_query), client.query(&second_query))
+        })
+        .await
+        .expect("queries should've been pipelined");
+
+        for (query, response) in [(first_query, first_response), (second_query, second_response)] {
+            let response = response.expect("shouldn't have failed");
+            assert!(response.header.is_response);
+            assert_eq!(response.header.id, query.header.id);
+            assert_eq!(response.questions, query.questions);
+        }
+
+        // Both queries were answered over the same connection
+        let connection = client.connection.lock().await.clone().unwrap();
+        let (third_query, fourth_query) = (get_query("third.example"), get_query("fourth.example"));
+        let (third_response, fourth_response) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(client.query(&third_query), client.query(&fourth_query))
+        })
+        .await
+        .unwrap();
+        assert!(third_response.is_ok() && fourth_response.is_ok());
+        assert!(Arc::ptr_eq(
+            &connection,
+            client.connection.lock().await.as_ref().unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_unauthenticated_server() {
+        let server = spawn_server(1).await;
+        let query = get_query("example.com");
+
+        let client = get_client(&server, "other.test", None);
+        assert!(client.query(&query).await.is_err(), "server name mismatch");
+
+        let client = get_client(&server, "dns.test", Some([0; 32]));
+        assert!(client.query(&query).await.is_err(), "SPKI pin mismatch");
+
+        let client = get_client(&server, "dns.test", None);
+        assert!(client.query(&query).await.is_ok());
+    }
+
+    #[test]
+    fn parse_tls_upstream_addr() {
+        // Base64 of 32 `0xab` bytes
+        let addr: crate::UpstreamAddr =
+            "tls://[2606:4700:4700::1111]#one.one.one.one?spki=q6urq6urq6urq6urq6urq6urq6urq6urq6urq6urq6s="
+                .parse()
+                .unwrap();
+        let crate::UpstreamAddr::Tls(addr) = addr else {
+            panic!("expected a DoT upstream");
+        };
+        assert_eq!(addr.to_string(), "tls://[2606:4700:4700::1111]:853#one.one.one.one");
+        assert_eq!(addr.spki_pin, Some([0xab; 32]));
+
+        let addr: crate::UpstreamAddr = "tls://1.1.1.1:8853".parse().unwrap();
+        let crate::UpstreamAddr::Tls(addr) = addr else {
+            panic!("expected a DoT upstream");
+        };
+        assert_eq!(addr.addr, SocketAddr::new(Ipv4Addr::new(1, 1, 1, 1).into(), 8853));
+        assert_eq!(addr.server_name.to_str(), "1.1.1.1");
+
+        assert!("tls://1.1.1.1?spki=AAAA".parse::<crate::UpstreamAddr>().is_err());
+    }
+}
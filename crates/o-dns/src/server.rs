@@ -1,19 +1,29 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context as _;
-use o_dns_common::DnsServerCommand;
+use o_dns_common::{DnsServerCommand, ForwardingRule};
 use o_dns_db::QueryLog;
-use o_dns_lib::{ByteBuf, DnsPacket, FromBuf as _};
-use tokio::net::{TcpListener, UdpSocket};
+use o_dns_lib::{read_tcp_message, ByteBuf, TsigKey};
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Endpoint, Incoming, TransportConfig};
+use rustls::ServerConfig;
+use tokio::io::AsyncWriteExt as _;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::mpsc::{Receiver, UnboundedSender};
+use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
 use tracing::Instrument;
 
-use crate::{Connection, Resolver, State, DEFAULT_EDNS_BUF_CAPACITY};
+use crate::{Connection, Resolver, State, UpstreamAddr, UpstreamConfig, DEFAULT_EDNS_BUF_CAPACITY, DOQ_ALPN};
 
 type HandlerResult = anyhow::Result<()>;
 
+/// Queries of a single TLS session that are resolved at once, further ones are read only after some of them finish
+const MAX_TLS_SESSION_QUERIES: usize = 100;
+
 pub struct DnsServer {
     udp_socket: Arc<UdpSocket>,
     tcp_listener: Arc<TcpListener>,
@@ -25,7 +35,9 @@ pub struct DnsServer {
 impl DnsServer {
     pub async fn new(
         listen_on: SocketAddr,
-        resolver_addr: SocketAddr,
+        upstream_resolvers: Vec<UpstreamAddr>,
+        upstream_config: UpstreamConfig,
+        tsig_keys: Vec<TsigKey>,
         log_tx: UnboundedSender<QueryLog>,
         command_rx: Receiver<DnsServerCommand>,
     ) -> anyhow::Result<Self> {
@@ -41,7 +53,7 @@ impl DnsServer {
                 .context("error while creating a TcpListener")?,
         );
 
-        let state = State::new(resolver_addr)
+        let state = State::new(upstream_resolvers, upstream_config, tsig_keys)
             .await
             .context("failed to instantiate a shared state")?;
 
@@ -58,12 +70,22 @@ impl DnsServer {
 
     pub async fn new_with_workers(
         listen_on: SocketAddr,
-        resolver_addr: SocketAddr,
+        upstream_resolvers: Vec<UpstreamAddr>,
+        upstream_config: UpstreamConfig,
+        tsig_keys: Vec<TsigKey>,
         log_tx: UnboundedSender<QueryLog>,
         max_parallel_connections: u8,
         command_rx: Receiver<DnsServerCommand>,
     ) -> anyhow::Result<Self> {
-        let mut server = DnsServer::new(listen_on, resolver_addr, log_tx, command_rx).await?;
+        let mut server = DnsServer::new(
+            listen_on,
+            upstream_resolvers,
+            upstream_config,
+            tsig_keys,
+            log_tx,
+            command_rx,
+        )
+        .await?;
         server.add_workers(max_parallel_connections).await;
 
         Ok(server)
@@ -82,6 +104,54 @@ impl DnsServer {
         }
     }
 
+    /// Starts accepting DNS-over-TLS (RFC7858) connections, closing the ones without queries for `idle_timeout`
+    pub async fn add_tls_listener(
+        &mut self,
+        listen_on: SocketAddr,
+        mut tls_config: ServerConfig,
+        idle_timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let tls_listener = TcpListener::bind(listen_on)
+            .await
+            .context("error while creating a TLS listener")?;
+        tls_config.alpn_protocols = vec![b"dot".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        self.workers.spawn(
+            handle_incoming_tls_connections(tls_listener, acceptor, self.resolver.clone(), idle_timeout)
+                .instrument(tracing::trace_span!("", worker = "tls")),
+        );
+
+        Ok(())
+    }
+
+    /// Starts accepting DNS-over-QUIC (RFC9250) connections, closing the ones without any traffic for `idle_timeout`
+    pub async fn add_quic_listener(
+        &mut self,
+        listen_on: SocketAddr,
+        mut tls_config: ServerConfig,
+        idle_timeout: Duration,
+    ) -> anyhow::Result<()> {
+        tls_config.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        // Accept queries sent as 0-RTT data by the clients resuming their sessions
+        tls_config.max_early_data_size = u32::MAX;
+        let crypto = QuicServerConfig::try_from(tls_config).context("failed to create a QUIC config")?;
+
+        let mut transport = TransportConfig::default();
+        transport.max_idle_timeout(Some(idle_timeout.try_into().context("idle timeout is too long")?));
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+
+        let endpoint = Endpoint::server(config, listen_on).context("error while creating a QUIC endpoint")?;
+
+        self.workers.spawn(
+            handle_incoming_quic_connections(endpoint, self.resolver.clone())
+                .instrument(tracing::trace_span!("", worker = "quic")),
+        );
+
+        Ok(())
+    }
+
     pub async fn block_until_completion(mut self) -> anyhow::Result<()> {
         loop {
             tokio::select! {
@@ -114,10 +184,44 @@ impl DnsServer {
                 .await
                 .context("failed to add a new list entry")?,
             DnsServerCommand::RemoveListEntry(list_entry) => self.resolver.remove_list_entry(list_entry).await,
+            DnsServerCommand::ResolveQuery {
+                query,
+                client,
+                response_tx,
+            } => {
+                // Resolve in the background, so that the other commands aren't blocked by the upstream resolvers
+                let resolver = self.resolver.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = resolver.resolve_api_query(query, client, response_tx).await {
+                            tracing::debug!("Error while resolving a query from the API server: {:#}", e);
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            DnsServerCommand::SetForwardingRule { rule, response_tx } => {
+                let result = self.set_forwarding_rule(&rule).await;
+                // Reply with the reason why the rule was rejected, as the API server only stores the valid ones
+                let _ = response_tx.send(result.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e)));
+                result?
+            }
+            DnsServerCommand::RemoveForwardingRule(domain) => self
+                .resolver
+                .remove_forwarding_rule(&domain)
+                .await
+                .context("failed to remove a forwarding rule")?,
         }
 
         Ok(())
     }
+
+    pub async fn set_forwarding_rule(&self, rule: &ForwardingRule) -> anyhow::Result<()> {
+        self.resolver
+            .set_forwarding_rule(rule)
+            .await
+            .with_context(|| format!("failed to set a forwarding rule for '{}'", rule.domain))
+    }
 }
 
 async fn handle_incoming_requests(
@@ -128,36 +232,367 @@ async fn handle_incoming_requests(
     let mut recv = ByteBuf::new_from_vec(vec![0; DEFAULT_EDNS_BUF_CAPACITY]);
     let mut handlers: JoinSet<HandlerResult> = JoinSet::new();
     loop {
-        let connection: Connection<_> = tokio::select! {
-            Ok((_, from)) = udp_socket.recv_from(&mut recv) => {
+        let (mut connection, length): (Connection<_>, usize) = tokio::select! {
+            Ok((length, from)) = udp_socket.recv_from(&mut recv) => {
                 tracing::trace!("new UDP connection");
 
-                Connection::Udp((udp_socket.clone(), Some(from)))
+                (Connection::Udp((udp_socket.clone(), Some(from))), length)
             }
             Ok((conn, _)) = tcp_listener.accept() => {
                  tracing::trace!("new TCP connection");
 
                  let mut connection = Connection::Tcp(conn);
-                 if connection.read(&mut recv).await.is_err() {
+                 let Ok(length) = connection.read(&mut recv).await else {
                      continue;
                  };
 
-                connection
+                (connection, length)
             }
             Some(result) = handlers.join_next() => {
-               result
-                   .context("connection handling task failed to execute")?
-                   .context("unrecoverable error while handling a query")?;
-               continue;
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::debug!("Error while handling a query: {:#}", e),
+                    // A single query shouldn't stop the worker
+                    Err(e) => tracing::error!("Connection handling task failed to execute: {}", e),
+                }
+                continue;
             }
         };
 
-        let mut reader = ByteBuf::new(&recv);
-        handlers.spawn(
-            resolver
-                .clone()
-                .resolve_query(connection, DnsPacket::from_buf(&mut reader))
-                .in_current_span(),
+        // The query is parsed inside the handler, borrowing from its own copy of the received bytes
+        let query = recv[..length].to_vec();
+        let resolver = resolver.clone();
+        handlers.spawn(async move { resolver.resolve_query(&mut connection, query).await }.in_current_span());
+    }
+}
+
+async fn handle_incoming_tls_connections(
+    tls_listener: TcpListener,
+    acceptor: TlsAcceptor,
+    resolver: Arc<Resolver>,
+    idle_timeout: Duration,
+) -> HandlerResult {
+    let mut sessions = JoinSet::new();
+    loop {
+        tokio::select! {
+            result = tls_listener.accept() => {
+                let stream = match result {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::debug!("Error while accepting a TLS connection: {}", e);
+                        continue;
+                    }
+                };
+                tracing::trace!("new TLS connection");
+
+                sessions.spawn(
+                    handle_tls_session(stream, acceptor.clone(), resolver.clone(), idle_timeout).in_current_span(),
+                );
+            }
+            Some(result) = sessions.join_next() => match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::debug!("TLS session was closed: {:#}", e),
+                // A single session shouldn't stop the listener
+                Err(e) => tracing::error!("TLS session task failed to execute: {}", e),
+            }
+        }
+    }
+}
+
+/// Resolves queries from a single TLS session concurrently, until the client closes it or stays idle for too long.
+///
+/// Responses are sent as soon as they are ready, so they may be out of order (RFC7766, section 6.2.1.1)
+async fn handle_tls_session(
+    stream: TcpStream,
+    acceptor: TlsAcceptor,
+    resolver: Arc<Resolver>,
+    idle_timeout: Duration,
+) -> HandlerResult {
+    let stream = tokio::time::timeout(idle_timeout, acceptor.accept(stream))
+        .await
+        .context("TLS handshake has timed out")?
+        .context("TLS handshake has failed")?;
+    let client = stream
+        .get_ref()
+        .0
+        .peer_addr()
+        .context("bug: TLS stream is not connected?")?;
+    let (mut reader, writer) = tokio::io::split(stream);
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+
+    let mut queries = JoinSet::new();
+    let permits = Arc::new(Semaphore::new(MAX_TLS_SESSION_QUERIES));
+    let mut recv = ByteBuf::new_empty(Some(DEFAULT_EDNS_BUF_CAPACITY));
+    loop {
+        // Stop reading from the session until a query in flight finishes
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .context("bug: semaphore of the TLS session is closed?")?;
+        let length = match tokio::time::timeout(idle_timeout, read_tcp_message(&mut reader, &mut recv)).await {
+            Ok(Ok(length)) => length,
+            // The client has closed the session
+            Ok(Err(_)) => break,
+            Err(_) => {
+                tracing::trace!("closing an idle TLS session");
+                break;
+            }
+        };
+
+        let query = recv[..length].to_vec();
+        let mut connection = Connection::Tls((writer.clone(), client));
+        let resolver = resolver.clone();
+        queries.spawn(
+            async move {
+                let result = resolver.resolve_query(&mut connection, query).await;
+                drop(permit);
+                result
+            }
+            .in_current_span(),
         );
+
+        while let Some(result) = queries.try_join_next() {
+            result
+                .context("TLS query task failed to execute")?
+                .context("unrecoverable error while handling a query")?;
+        }
+    }
+
+    // The client may still be waiting for the responses to the queries in flight
+    while let Some(result) = queries.join_next().await {
+        result
+            .context("TLS query task failed to execute")?
+            .context("unrecoverable error while handling a query")?;
+    }
+    // Send close_notify, so that the client can tell the session was closed on purpose
+    let _ = writer.lock().await.shutdown().await;
+
+    Ok(())
+}
+
+async fn handle_incoming_quic_connections(endpoint: Endpoint, resolver: Arc<Resolver>) -> HandlerResult {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            Some(incoming) = endpoint.accept() => {
+                tracing::trace!("new QUIC connection");
+
+                connections.spawn(handle_quic_connection(incoming, resolver.clone()).in_current_span());
+            }
+            Some(result) = connections.join_next() => match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::debug!("QUIC connection was closed: {:#}", e),
+                // A single connection shouldn't stop the listener
+                Err(e) => tracing::error!("QUIC connection task failed to execute: {}", e),
+            },
+            // The endpoint was closed
+            else => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves queries from all streams of a single connection concurrently, until the client closes it or it times out
+async fn handle_quic_connection(incoming: Incoming, resolver: Arc<Resolver>) -> HandlerResult {
+    let connecting = incoming.accept().context("failed to accept a QUIC connection")?;
+    // Queries sent as 0-RTT data are available before the handshake is complete
+    let connection = match connecting.into_0rtt() {
+        Ok((connection, _)) => connection,
+        Err(connecting) => connecting.await.context("QUIC handshake has failed")?,
+    };
+    let client = connection.remote_address();
+
+    let mut queries = JoinSet::new();
+    loop {
+        let (send, recv) = match connection.accept_bi().await {
+            Ok(streams) => streams,
+            // The client has closed the connection or it has timed out
+            Err(_) => break,
+        };
+
+        let resolver = resolver.clone();
+        queries.spawn(
+            async move {
+                let mut connection = Connection::Quic((send, recv, client));
+                let mut recv = ByteBuf::new_empty(None);
+                let length = connection.read(&mut recv).await?;
+                let query = recv[..length].to_vec();
+                resolver.resolve_query(&mut connection, query).await
+            }
+            .in_current_span(),
+        );
+
+        while let Some(result) = queries.try_join_next() {
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::debug!("Error while handling a QUIC stream: {:#}", e),
+                // A single stream shouldn't close the whole connection
+                Err(e) => tracing::error!("QUIC stream task failed to execute: {}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use o_dns_lib::{write_tcp_message, DnsPacket, EncodeToBuf as _, FromBuf as _, QueryType, Question, ResponseCode};
+    use rcgen::{CertificateParams, KeyPair};
+    use rustls::pki_types::{PrivateKeyDer, ServerName};
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::AsyncReadExt as _;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+    use crate::{DohMethod, UpstreamStrategy};
+
+    /// Starts the DoT listener of the server with a self-signed certificate for `dns.test` and connects to it
+    async fn connect_to_tls_listener(upstream: SocketAddr, idle_timeout: Duration) -> TlsStream<TcpStream> {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["dns.test".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let tls_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            )
+            .unwrap();
+
+        let upstream_config = UpstreamConfig {
+            strategy: UpstreamStrategy::Strict,
+            timeout: Duration::from_secs(1),
+            retries: 0,
+            backoff: Duration::ZERO,
+            deadline: Duration::from_secs(1),
+            doh_method: DohMethod::Post,
+        };
+        let (log_tx, _log_rx) = unbounded_channel();
+        let (_command_tx, command_rx) = channel(1);
+        let mut server = DnsServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            vec![upstream.to_string().parse().unwrap()],
+            upstream_config,
+            Vec::new(),
+            log_tx,
+            command_rx,
+        )
+        .await
+        .unwrap();
+
+        // Find a free port for the TLS listener
+        let tls_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        server
+            .add_tls_listener(tls_addr, tls_config, idle_timeout)
+            .await
+            .unwrap();
+        tokio::spawn(server.block_until_completion());
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TcpStream::connect(tls_addr).await.unwrap();
+        TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("dns.test").unwrap(), stream)
+            .await
+            .unwrap()
+    }
+
+    fn get_query(id: u16, recursion_desired: bool) -> DnsPacket<'static> {
+        let mut query = DnsPacket::new();
+        query.header.id = id;
+        query.header.recursion_desired = recursion_desired;
+        query.add_question(Question::new("example.com".parse().unwrap(), QueryType::A, None));
+        query
+    }
+
+    async fn send_query(stream: &mut TlsStream<TcpStream>, query: &DnsPacket<'_>) {
+        let mut buf = ByteBuf::new_empty(None);
+        query.encode_to_buf(&mut buf, None).unwrap();
+        write_tcp_message(stream, &buf).await.unwrap();
+    }
+
+    async fn read_response(stream: &mut TlsStream<TcpStream>) -> DnsPacket<'static> {
+        let mut buf = ByteBuf::new_empty(None);
+        let length = read_tcp_message(stream, &mut buf).await.unwrap();
+        buf.get_inner_mut().truncate(length);
+        DnsPacket::from_buf(&mut buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn resolves_multiple_queries_per_tls_session() {
+        let idle_timeout = Duration::from_millis(200);
+        let mut stream = connect_to_tls_listener("127.0.0.1:53".parse().unwrap(), idle_timeout).await;
+
+        for id in 1..=3 {
+            // Answered without contacting the upstream resolver
+            let query = get_query(id, false);
+            send_query(&mut stream, &query).await;
+
+            let response = read_response(&mut stream).await;
+            assert!(response.header.is_response);
+            assert_eq!(response.header.id, id);
+            assert_eq!(response.questions, query.questions);
+        }
+
+        // Idle sessions are closed by the server
+        let closed = tokio::time::timeout(idle_timeout * 5, stream.read(&mut [0; 1]))
+            .await
+            .expect("idle session should've been closed");
+        assert!(matches!(closed, Ok(0)));
+    }
+
+    #[tokio::test]
+    async fn resolves_tls_queries_concurrently() {
+        // Upstream resolver that never replies
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = connect_to_tls_listener(upstream.local_addr().unwrap(), Duration::from_secs(5)).await;
+
+        send_query(&mut stream, &get_query(1, true)).await;
+        send_query(&mut stream, &get_query(2, false)).await;
+
+        // The second query doesn't wait for the first one to time out
+        let response = read_response(&mut stream).await;
+        assert_eq!(response.header.id, 2);
+        assert_eq!(response.header.response_code, ResponseCode::Success);
+        let response = read_response(&mut stream).await;
+        assert_eq!(response.header.id, 1);
+        assert_eq!(response.header.response_code, ResponseCode::ServerFailure);
+    }
+
+    #[tokio::test]
+    async fn limits_tls_queries_in_flight() {
+        // Upstream resolver that never replies, so the queries stay in flight until they time out
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = connect_to_tls_listener(upstream.local_addr().unwrap(), Duration::from_secs(5)).await;
+
+        for id in 0..MAX_TLS_SESSION_QUERIES + 10 {
+            send_query(&mut stream, &get_query(id as u16, true)).await;
+        }
+
+        // Count the forwarded queries before the first ones time out after 1 second
+        let mut forwarded = 0;
+        let mut buf = [0; 512];
+        while tokio::time::timeout(Duration::from_millis(500), upstream.recv(&mut buf))
+            .await
+            .is_ok()
+        {
+            forwarded += 1;
+        }
+        assert_eq!(forwarded, MAX_TLS_SESSION_QUERIES);
     }
 }
@@ -1,14 +1,18 @@
-use std::borrow::Cow;
-use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
-use o_dns_lib::{DnsPacket, QueryType, Question, ResourceData, ResourceRecord, ResponseCode};
+use o_dns_lib::{
+    write_unsigned_tsig_error, ByteBuf, DnsError, DnsPacket, DomainName, EdnsOption, Question, ResourceData,
+    ResourceRecord, ResponseCode, TsigKey,
+};
+use rustls::ServerConfig;
 use sha1::Digest;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncReadExt as _, AsyncWriteExt};
 
-use crate::{DEFAULT_EDNS_BUF_CAPACITY, EDNS_DO_BIT};
+use crate::{DEFAULT_EDNS_BUF_CAPACITY, EDNS_BADVERS, EDNS_DO_BIT};
 
 pub fn get_response_dns_packet(
     request_packet: Option<&DnsPacket>,
@@ -28,11 +32,7 @@ pub fn get_response_dns_packet(
             .and_then(|idx| request_packet.additionals.get(idx).and_then(|rr| rr.get_edns_data()))
         {
             let flags = edns_data.dnssec_ok_bit.then_some(EDNS_DO_BIT);
-            packet
-                .additionals
-                .push(get_edns_rr(DEFAULT_EDNS_BUF_CAPACITY as u16, None, flags));
-            packet.header.additional_rr_count += 1;
-            packet.edns = Some(0);
+            packet.set_edns(get_edns_rr(DEFAULT_EDNS_BUF_CAPACITY as u16, Vec::new(), flags));
         }
     };
     if let Some(rcode) = response_code {
@@ -49,23 +49,137 @@ pub fn get_query_dns_packet(id: Option<u16>, enable_dnssec: bool) -> DnsPacket<'
     packet.header.z[1] = true;
     // EDNS
     let flags = enable_dnssec.then_some(EDNS_DO_BIT);
+    packet.set_edns(get_edns_rr(DEFAULT_EDNS_BUF_CAPACITY as u16, Vec::new(), flags));
     packet
-        .additionals
-        .push(get_edns_rr(DEFAULT_EDNS_BUF_CAPACITY as u16, None, flags));
-    packet.header.additional_rr_count += 1;
-    packet.edns = Some(0);
-    packet
 }
 
-pub fn get_edns_rr(buf_size: u16, options: Option<HashMap<u16, Cow<'_, [u8]>>>, flags: Option<u32>) -> ResourceRecord {
-    ResourceRecord::new("".into(), ResourceData::OPT { options }, flags, Some(buf_size))
+pub fn get_edns_rr(buf_size: u16, options: Vec<EdnsOption<'_>>, flags: Option<u32>) -> ResourceRecord<'_> {
+    ResourceRecord::new(DomainName::root(), ResourceData::OPT { options }, flags, Some(buf_size))
+}
+
+/// Adds the option to the packet's OPT RR, if the packet has one
+pub fn add_edns_option<'a>(packet: &mut DnsPacket<'a>, option: EdnsOption<'a>) {
+    if let Some(ResourceData::OPT { options }) = packet
+        .edns
+        .and_then(|idx| packet.additionals.get_mut(idx))
+        .map(|rr| &mut rr.resource_data)
+    {
+        options.push(option);
+    }
+}
+
+/// Sets the response code that matches the reason why the query was rejected
+pub fn set_error_response_code(packet: &mut DnsPacket<'_>, error: &DnsError) {
+    match error {
+        DnsError::BadEdnsVersion(_) => {
+            // Upper 8 bits of the extended RCODE are stored in the OPT RR's TTL
+            packet.header.response_code = ResponseCode::Success;
+            if let Some(opt_rr) = packet.edns.and_then(|idx| packet.additionals.get_mut(idx)) {
+                opt_rr.ttl = (opt_rr.ttl & 0x00FF_FFFF) | ((EDNS_BADVERS >> 4) << 24);
+            }
+        }
+        DnsError::UnknownClass(_) => packet.header.response_code = ResponseCode::NotImplemented,
+        DnsError::BadTsigKey | DnsError::BadTsigSignature | DnsError::BadTsigTime { .. } => {
+            packet.header.response_code = ResponseCode::NotAuth
+        }
+        DnsError::Truncated { .. }
+        | DnsError::MisplacedTsig
+        | DnsError::BadClientSubnet
+        | DnsError::BadPointer { .. }
+        | DnsError::TooManyPointers
+        | DnsError::LabelTooLong(_)
+        | DnsError::NameTooLong(_) => packet.header.response_code = ResponseCode::FormatError,
+        // Only returned while encoding
+        DnsError::TooManyEntries(_) | DnsError::LengthOverflow(_) | DnsError::CharacterStringTooLong(_) => {
+            packet.header.response_code = ResponseCode::ServerFailure
+        }
+    }
+}
+
+/// Signs the encoded response to a signed query, or reports why the query's signature was rejected (RFC8945)
+pub fn sign_response(
+    buf: &mut ByteBuf<'_>,
+    request_tsig_rr: &ResourceRecord<'_>,
+    key: Option<&TsigKey>,
+    verification: &Result<Vec<u8>, DnsError>,
+    now: u64,
+) -> anyhow::Result<()> {
+    match (key, verification) {
+        (Some(key), Ok(request_mac)) => key.sign(buf, Some(request_mac), now).map(|_| ()),
+        (Some(key), Err(DnsError::BadTsigTime { .. })) => {
+            let ResourceData::TSIG { mac, .. } = &request_tsig_rr.resource_data else {
+                anyhow::bail!("bug: request RR isn't a TSIG RR");
+            };
+            key.sign_bad_time(buf, mac, now).map(|_| ())
+        }
+        (_, Err(error)) => write_unsigned_tsig_error(buf, request_tsig_rr, error),
+        (None, Ok(_)) => anyhow::bail!("bug: query was verified without a key"),
+    }
+}
+
+/// Reads TSIG keys in the `algorithm:name:secret` format, one per line, where lines starting with `#` are ignored
+pub async fn read_tsig_keys(path: impl AsRef<Path>) -> anyhow::Result<Vec<TsigKey>> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .context("failed to read the file")?;
+
+    contents
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| line.parse().with_context(|| format!("line {}", line_number)))
+        .collect()
 }
 
-pub fn get_dns_query_hash(question: &Question) -> u128 {
+/// Reads a PEM-encoded certificate chain and private key for the encrypted listeners
+pub async fn read_tls_server_config(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> anyhow::Result<ServerConfig> {
+    let certs = tokio::fs::read(cert_path)
+        .await
+        .context("failed to read the certificate file")?;
+    let certs = rustls_pemfile::certs(&mut certs.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .context("failed to parse the certificate file")?;
+    if certs.is_empty() {
+        anyhow::bail!("certificate file doesn't contain any certificates");
+    }
+
+    let key = tokio::fs::read(key_path)
+        .await
+        .context("failed to read the private key file")?;
+    let key = rustls_pemfile::private_key(&mut key.as_slice())
+        .context("failed to parse the private key file")?
+        .context("private key file doesn't contain a private key")?;
+
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("failed to select TLS versions")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("certificate doesn't match the private key")
+}
+
+/// Returns the number of seconds since the UNIX epoch
+pub fn get_unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn get_dns_query_hash(question: &Question, forwarding_rule: Option<u128>) -> u128 {
     let mut hasher = sha1::Sha1::new();
 
-    // Hash the question itself
-    hasher.update(question.qname.as_bytes());
+    // Keep the queries matching a forwarding rule apart from the ones with the same question
+    if let Some(forwarding_rule) = forwarding_rule {
+        hasher.update(forwarding_rule.to_be_bytes());
+    }
+
+    // Hash the question itself, where QNAME is case-insensitive
+    hasher.update(question.qname.to_ascii_lowercase().as_wire());
     hasher.update(Into::<u16>::into(question.query_type).to_be_bytes());
     hasher.update(question.qclass.to_be_bytes());
 
@@ -86,38 +200,18 @@ pub fn hash_to_u128(data: impl AsRef<[u8]>, prefix: Option<&[u8]>) -> u128 {
     u128::from_be_bytes(hash[..16].try_into().unwrap())
 }
 
-// TODO: add these RRs to o-dns-lib?
-pub fn is_dnssec_qtype(qtype: u16) -> bool {
-    match qtype {
-        // DS | RRSIG | NSEC | DNSKEY | NSEC3
-        43 | 46 | 47 | 48 | 50 => true,
-        _ => false,
-    }
-}
-
 pub fn get_caching_duration_for_packet(packet: &DnsPacket<'_>) -> u32 {
     match packet.header.response_code {
         // Cache for the lowest TTL from all response RRs OR for 5 minutes
-        ResponseCode::Success => get_minimum_ttl_for_packet(packet).unwrap_or(60 * 5),
+        ResponseCode::Success => packet.get_minimum_ttl().unwrap_or(60 * 5),
         // TODO: cache NXDOMAIN for SOA TTL (or 1 min if SOA is missing)
         ResponseCode::Refused | ResponseCode::NameError => 60, // Cache for 1 min
         ResponseCode::ServerFailure => 30,                     // Cache for 30s
         ResponseCode::NotImplemented => 60 * 5,                // Cache for 5 min
-        ResponseCode::FormatError | ResponseCode::Unknown => 0, // Don't cache these responses
+        ResponseCode::FormatError | ResponseCode::NotAuth | ResponseCode::Unknown(_) => 0, // Don't cache these responses
     }
 }
 
-pub fn get_minimum_ttl_for_packet(packet: &DnsPacket<'_>) -> Option<u32> {
-    packet
-        .answers
-        .iter()
-        .chain(packet.authorities.iter())
-        .chain(packet.additionals.iter())
-        .filter(|rr| rr.resource_data.get_query_type() != QueryType::OPT)
-        .map(|rr| rr.ttl)
-        .min()
-}
-
 pub async fn read_checksum(path: impl AsRef<Path>) -> anyhow::Result<Option<[u8; 20]>> {
     let mut checksum_buf = [0; 20];
 
@@ -91,6 +91,7 @@ export const RESPONSE_SOURCES: Record<string, { label: string }> = {
     2: { label: "Cache" },
     3: { label: "Recursion Disabled" },
     4: { label: "Upstream" },
+    5: { label: "Upstream Timeout" },
     // Fallback value in case response source is missing for whatever reason
     unknown: { label: "Unknown" },
 };
@@ -11,6 +11,7 @@ export interface Query {
     response_code: number;
     response_delay_ms?: number;
     source?: number;
+    upstream?: string;
 }
 
 export const queryColumns: ColumnDef<Query>[] = [
@@ -51,6 +52,11 @@ export const queryColumns: ColumnDef<Query>[] = [
         header: "Client",
         filterFn: "equalsString",
     },
+    {
+        accessorKey: "upstream",
+        header: "Upstream",
+        filterFn: "equalsString",
+    },
 ];
 
 export interface ListEntryRaw {